@@ -2,9 +2,9 @@
 //! to allow for the buils.rs script to
 //! generate the man pages and completions
 //! for the CLI at build time.
+use clap::Parser;
 use clap::crate_authors;
 use std::path::PathBuf;
-use clap::Parser;
 
 use std::sync::LazyLock;
 
@@ -46,6 +46,10 @@ pub enum Commands {
     /// Connect explicitly (same as default command)
     Connect(ConnectArgs),
 
+    /// Make a remote directory match a local one, or with --pull the other
+    /// way round, copying only what changed
+    Sync(SyncArgs),
+
     /// Install man pages into the system
     InstallManPages,
 
@@ -91,6 +95,77 @@ pub struct ConnectArgs {
     /// Host alias defined there instead of as a host name.
     #[clap(short, long)]
     pub from_config: bool,
+
+    /// Preserve permissions, modification and access times on transferred
+    /// files, like `scp -p`.
+    #[clap(long)]
+    pub preserve: bool,
+
+    /// Also preserve the owner and group of transferred files. Usually needs
+    /// root on the receiving side. Implies --preserve.
+    #[clap(long)]
+    pub preserve_ownership: bool,
+
+    /// Limit all downloads together to RATE per second, such as 500K or 2M.
+    /// A bare number is in KiB.
+    #[clap(long, value_name = "RATE")]
+    pub download_limit: Option<String>,
+
+    /// Limit all uploads together to RATE per second, such as 500K or 2M.
+    /// A bare number is in KiB.
+    #[clap(long, value_name = "RATE")]
+    pub upload_limit: Option<String>,
+
+    /// Run the SFTP server as root through sudo, to browse and edit files the
+    /// login user cannot. The sudo password is asked for if needed.
+    #[clap(long)]
+    pub sudo: bool,
+
+    /// Run the SFTP server as USER rather than root. Implies --sudo.
+    #[clap(long, value_name = "USER")]
+    pub sudo_user: Option<String>,
+}
+
+/// Arguments for `sync`. <HOST> and <PATH> name the remote directory.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct SyncArgs {
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+
+    /// The local directory to sync with the remote <PATH>.
+    #[clap(index = 3)]
+    pub local: Option<PathBuf>,
+
+    /// Make the local directory match the remote one instead.
+    #[clap(long)]
+    pub pull: bool,
+
+    /// Delete files on the receiving side that the sending side does not
+    /// have.
+    #[clap(long)]
+    pub delete: bool,
+
+    /// Only print what would be added, updated and deleted.
+    #[clap(long, short = 'n')]
+    pub dry_run: bool,
+
+    /// Compare files of the same size by SHA-256 rather than by modification
+    /// time. Needs `sha256sum` on the remote host.
+    #[clap(long, short = 'c')]
+    pub checksum: bool,
+
+    /// Send changed files with `rsync` over the session's connection, which
+    /// transfers only the parts that differ, when both hosts have it.
+    #[clap(long)]
+    pub delta: bool,
+
+    /// Only sync files matching PATTERN. May be repeated.
+    #[clap(long, short = 'i', value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Leave out files and directories matching PATTERN. May be repeated.
+    #[clap(long, short = 'x', value_name = "PATTERN")]
+    pub exclude: Vec<String>,
 }
 
 #[cfg(test)]
@@ -154,4 +229,6 @@ pub struct ResolvedConnectArgs {
     pub private_key: PathBuf,
     pub openssh_certificate: Option<PathBuf>,
     pub path: PathBuf,
+    /// The user the SFTP server runs as through sudo, if it does.
+    pub sudo: Option<String>,
 }
@@ -114,6 +114,7 @@ impl ConnectArgs {
                 private_key,
                 openssh_certificate: self.openssh_certificate.clone(),
                 path,
+                sudo: self.sudo_user(),
             });
         }
         let host = self
@@ -144,6 +145,14 @@ impl ConnectArgs {
             private_key,
             openssh_certificate: self.openssh_certificate.clone(),
             path,
+            sudo: self.sudo_user(),
         })
     }
+
+    /// Whom `--sudo` or `--sudo-user` ask to run the SFTP server as.
+    fn sudo_user(&self) -> Option<String> {
+        self.sudo_user
+            .clone()
+            .or_else(|| self.sudo.then(|| "root".to_string()))
+    }
 }
@@ -1,4 +1,5 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     env,
     fmt::{self, Display, Formatter},
     path::PathBuf,
@@ -13,6 +14,12 @@ use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
 
 use crate::logging::{PROJECT_NAME, project_directory};
+use crate::sort::SortOrder;
+use crate::transfer::TransferSettings;
+use crate::transfer::filter::DownloadSettings;
+use crate::transfer::open::OpenSettings;
+use crate::transfer::save::EditorSettings;
+use crate::transfer::trash::TrashSettings;
 
 pub static THEME: OnceLock<&'static str> = OnceLock::new();
 
@@ -35,8 +42,37 @@ pub(crate) struct Settings {
     pub(crate) silent: bool,
     #[serde(default)]
     pub(crate) theme: Theme,
+    #[serde(default)]
+    pub(crate) transfer: TransferSettings,
+    #[serde(default)]
+    pub(crate) download: DownloadSettings,
+    #[serde(default)]
+    pub(crate) open: OpenSettings,
+    #[serde(default)]
+    pub(crate) trash: TrashSettings,
+    #[serde(default)]
+    pub(crate) editor: EditorSettings,
+    /// Settings for single hosts, by the host name connected to.
+    #[serde(default)]
+    pub(crate) hosts: HashMap<String, HostSettings>,
 }
 
+/// The `[hosts.NAME]` settings of one host.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct HostSettings {
+    /// The SFTP server `--sudo` starts, for a host that keeps it somewhere
+    /// unusual.
+    #[serde(default)]
+    pub(crate) sftp_server: Option<String>,
+    /// How the file list was last sorted.
+    #[serde(default)]
+    pub(crate) sort: Option<SortOrder>,
+}
+
+/// The file the sort order of each host is kept in. It sorts after
+/// `default.toml`, so what it says wins.
+const SORT_FILE: &str = "sort.toml";
+
 pub static CONFIG_FOLDER: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
     env::var(format!("{}_CONFIG", &*PROJECT_NAME))
         .ok()
@@ -159,6 +195,55 @@ impl Settings {
     pub(crate) fn get_theme(&self) -> &Theme {
         &self.theme
     }
+
+    /// The settings for `host`, whose name is matched regardless of case.
+    pub(crate) fn host(&self, host: &str) -> HostSettings {
+        self.hosts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, settings)| settings.clone())
+            .unwrap_or_default()
+    }
+
+    /// Keeps `order` as the sort order of `host`, for this session and later
+    /// ones. It is written to a file of its own, so that the rest of the
+    /// configuration is left as it was, along with the orders saved there for
+    /// other hosts; orders set in the configuration itself stay there.
+    pub(crate) fn save_sort(&mut self, host: &str, order: SortOrder) -> Result<()> {
+        let name = self
+            .hosts
+            .keys()
+            .find(|name| name.eq_ignore_ascii_case(host))
+            .cloned()
+            .unwrap_or_else(|| host.to_lowercase());
+        self.hosts.entry(name.clone()).or_default().sort = Some(order);
+
+        let dir = get_config_dir().join("config");
+        let path = dir.join(SORT_FILE);
+        let saved = std::fs::read_to_string(&path).unwrap_or_default();
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(path, with_sort(&saved, &name, order)?)?;
+        Ok(())
+    }
+}
+
+/// The sort file `saved`, with `order` as the sort order of `host`.
+fn with_sort(saved: &str, host: &str, order: SortOrder) -> Result<String> {
+    #[derive(Deserialize, Serialize, Default)]
+    struct SortFile {
+        #[serde(default)]
+        hosts: BTreeMap<String, Sorted>,
+    }
+    #[derive(Deserialize, Serialize)]
+    struct Sorted {
+        sort: SortOrder,
+    }
+    // A file that no longer reads is only sort orders, and is started over.
+    let mut file: SortFile = toml::from_str(saved).unwrap_or_default();
+    file.hosts
+        .retain(|name, _| !name.eq_ignore_ascii_case(host));
+    file.hosts.insert(host.to_string(), Sorted { sort: order });
+    Ok(toml::to_string_pretty(&file)?)
 }
 
 pub(crate) struct LoggingConfig {
@@ -407,3 +492,18 @@ pub fn init_config() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_a_sort_keeps_the_other_saved_ones() {
+        let saved = with_sort("", "db.example.com", SortOrder::default()).unwrap();
+        let saved = with_sort(&saved, "web.example.com", SortOrder::default()).unwrap();
+        let saved = with_sort(&saved, "DB.example.com", SortOrder::default()).unwrap();
+        assert!(saved.contains("[hosts.\"web.example.com\".sort]"));
+        assert!(saved.contains("[hosts.\"DB.example.com\".sort]"));
+        assert!(!saved.contains("[hosts.\"db.example.com\".sort]"));
+    }
+}
@@ -1,13 +1,16 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::path::MAIN_SEPARATOR;
 
 use derive_getters::Getters;
+use futures::stream::{self, StreamExt};
 use rat_ftable::TableData;
 use rat_widget::paragraph::Paragraph;
 use ratatui::prelude::Line;
-use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::style::{Color, Modifier, Style, Stylize};
 use ratatui::symbols::line::{ROUNDED_BOTTOM_LEFT, VERTICAL_RIGHT};
 use ratatui::{text::Span, widgets::Widget};
+use russh_sftp::client::SftpSession;
 use russh_sftp::client::fs::Metadata;
 use russh_sftp::protocol::{FileAttributes, FileType};
 
@@ -16,6 +19,19 @@ pub struct FileEntry {
     pub name: String,
     pub type_: FileType,
     pub attributes: FileAttributes,
+    /// Where the entry points, if it is a symbolic link whose target has
+    /// been looked up. Boxed, as entries are moved around in bulk by the
+    /// directory walker and most are not links.
+    pub link: Option<Box<LinkTarget>>,
+}
+
+/// The target of a symbolic link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkTarget {
+    /// As stored in the link, so possibly relative to the link's directory.
+    pub path: String,
+    /// What the target is, or `None` if it does not exist.
+    pub type_: Option<FileType>,
 }
 
 impl From<russh_sftp::client::fs::DirEntry> for FileEntry {
@@ -27,6 +43,7 @@ impl From<russh_sftp::client::fs::DirEntry> for FileEntry {
             name: name.to_string(),
             type_,
             attributes,
+            link: None,
         }
     }
 }
@@ -42,6 +59,42 @@ impl FileEntry {
     pub fn is_symlink(&self) -> bool {
         self.type_ == FileType::Symlink
     }
+
+    /// Whether the entry is a link whose target is missing.
+    pub fn is_dangling(&self) -> bool {
+        self.link.as_ref().is_some_and(|link| link.type_.is_none())
+    }
+
+    /// Whether the entry can be entered: a directory, or a link to one.
+    pub fn leads_to_dir(&self) -> bool {
+        self.is_dir()
+            || self
+                .link
+                .as_ref()
+                .is_some_and(|link| link.type_ == Some(FileType::Dir))
+    }
+
+    /// Looks up where each link among `entries`, listed from `dir`, points,
+    /// with many lookups in flight. A link that cannot be read is left as it
+    /// was.
+    pub async fn resolve_links(sftp: &SftpSession, dir: &str, entries: &mut [FileEntry]) {
+        const IN_FLIGHT: usize = 16;
+        let links = entries.iter_mut().filter(|entry| entry.is_symlink());
+        stream::iter(links)
+            .for_each_concurrent(IN_FLIGHT, |entry| async move {
+                let path = dir.to_string().join(&entry.name);
+                let (target, metadata) =
+                    tokio::join!(sftp.read_link(path.as_str()), sftp.metadata(path.as_str()));
+                let Ok(target) = target else {
+                    return;
+                };
+                entry.link = Some(Box::new(LinkTarget {
+                    path: target,
+                    type_: metadata.ok().map(|target| target.file_type()),
+                }));
+            })
+            .await;
+    }
 }
 
 pub struct MetadataTable {
@@ -202,7 +255,8 @@ impl From<FileEntry> for Paragraph<'_> {
     }
 }
 
-pub struct FileDataSlice<'a>(pub &'a [FileEntry]);
+/// The file table's rows, with the names of the marked entries.
+pub struct FileDataSlice<'a>(pub &'a [FileEntry], pub &'a HashSet<String>);
 
 impl FileEntry {
     pub fn from_file(name: String, type_: FileType, attributes: FileAttributes) -> Self {
@@ -210,8 +264,17 @@ impl FileEntry {
             name,
             type_,
             attributes,
+            link: None,
         }
     }
+
+    /// An entry of `size` bytes, modified at `mtime` if given, for tests.
+    #[cfg(test)]
+    pub(crate) fn sample(name: &str, type_: FileType, size: u64, mtime: Option<u32>) -> Self {
+        let mut attributes = FileAttributes::empty();
+        (attributes.size, attributes.mtime) = (Some(size), mtime);
+        Self::from_file(name.to_string(), type_, attributes)
+    }
 }
 
 pub struct ProgressDataSlice<'a>(pub &'a [FileEntry]);
@@ -283,10 +346,12 @@ impl<'a> TableData<'a> for FileDataSlice<'a> {
                 } else {
                     ROUNDED_BOTTOM_LEFT.to_string()
                 };
-                let vertical_line_symbol = if _ctx.selected_row {
-                    vertical_right + "> "
-                } else {
-                    vertical_right + "  "
+                let marked = self.1.contains(&entry.name);
+                let vertical_line_symbol = match (_ctx.selected_row, marked) {
+                    (true, true) => vertical_right + ">*",
+                    (true, false) => vertical_right + "> ",
+                    (false, true) => vertical_right + " *",
+                    (false, false) => vertical_right + "  ",
                 };
                 let span_prefix = match entry.type_() {
                     FileType::Dir => "ð ",
@@ -294,36 +359,65 @@ impl<'a> TableData<'a> for FileDataSlice<'a> {
                     FileType::Symlink => "§ ",
                     _ => "├ █ ",
                 };
-                let entry_span = if entry.is_dir() {
-                    ratatui_macros::span![entry.name.clone() + "/"].blue()
+                let name = if entry.leads_to_dir() {
+                    entry.name.clone() + "/"
+                } else {
+                    entry.name.clone()
+                };
+                let entry_span = if marked {
+                    ratatui_macros::span![name.clone()].yellow()
+                } else if entry.is_dangling() {
+                    ratatui_macros::span![name.clone()].red()
+                } else if entry.leads_to_dir() {
+                    ratatui_macros::span![name.clone()].blue()
                 } else {
-                    ratatui_macros::span![entry.name.as_str()]
+                    ratatui_macros::span![name.clone()]
+                };
+                let link_span = match &entry.link {
+                    Some(link) if link.type_.is_none() => {
+                        Span::styled(format!(" → {} (dangling)", link.path), Color::Red)
+                    }
+                    Some(link) => Span::styled(format!(" → {}", link.path), Color::DarkGray),
+                    None => Span::raw(""),
                 };
                 let mut line = ratatui_macros::line![
                     vertical_line_symbol.clone() + span_prefix + " ",
-                    entry_span
+                    entry_span,
+                    link_span.clone()
                 ];
 
                 if _ctx.selected_row {
-                    let name = if entry.is_dir() {
-                        entry.name.clone() + "/"
-                    } else {
-                        entry.name.clone()
-                    };
-                    line = Line::from(format!("{}{}[{}]", vertical_line_symbol, span_prefix, name))
-                        .style(Style::default().add_modifier(Modifier::BOLD));
+                    line = Line::from(vec![
+                        Span::raw(format!("{}{}[{}]", vertical_line_symbol, span_prefix, name)),
+                        link_span,
+                    ])
+                    .style(Style::default().add_modifier(Modifier::BOLD));
                 }
                 line.render(area, buf);
             }
-            2 => {
-                if !entry.is_dir() {
-                    let size = entry.attributes.size.unwrap_or_default();
-                    let size_string = human_readable_size(size);
-                    let span = Span::from(size_string);
-                    span.render(area, buf);
-                }
+            2 if !entry.is_dir() => {
+                let size = entry.attributes.size.unwrap_or_default();
+                let size_string = human_readable_size(size);
+                let span = Span::from(size_string);
+                span.render(area, buf);
+            }
+            3 => {
+                let attributes = &entry.attributes;
+                let user = attributes
+                    .user
+                    .clone()
+                    .or(attributes.uid.map(|uid| uid.to_string()));
+                let group = attributes
+                    .group
+                    .clone()
+                    .or(attributes.gid.map(|gid| gid.to_string()));
+                let owner = match (user, group) {
+                    (Some(user), Some(group)) => format!("{user}:{group}"),
+                    (user, group) => user.or(group).unwrap_or_default(),
+                };
+                Span::from(owner).render(area, buf);
             }
-            3 => match format_timestamp(entry.attributes().mtime) {
+            4 => match format_timestamp(entry.attributes().mtime) {
                 Some(timestamp_string) => {
                     Span::from(timestamp_string).render(area, buf);
                 }
@@ -336,7 +430,7 @@ impl<'a> TableData<'a> for FileDataSlice<'a> {
     }
 }
 
-fn human_readable_size<'a>(bytes: u64) -> Cow<'a, str> {
+pub(crate) fn human_readable_size<'a>(bytes: u64) -> Cow<'a, str> {
     const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
 
     if bytes == 0 {
@@ -358,7 +452,7 @@ fn human_readable_size<'a>(bytes: u64) -> Cow<'a, str> {
     }
 }
 
-fn format_timestamp(timestamp: Option<u32>) -> Option<String> {
+pub(crate) fn format_timestamp(timestamp: Option<u32>) -> Option<String> {
     let timestamp = timestamp?;
     let datetime = chrono::DateTime::from_timestamp(timestamp.into(), 0)?;
     let fmt_datetime = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
@@ -1,11 +1,16 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::cli::{Cli, Commands};
+use crate::cli::{Cli, Commands, ResolvedConnectArgs, SyncArgs};
 use crate::completions::detect_shell;
 use crate::ssh::Session;
+use crate::transfer::filter::PathFilter;
+use crate::transfer::ratelimit::Direction;
+use crate::transfer::sync::{self, SyncOptions};
 use async_lock::Mutex as AsyncMutex;
 use clap::Parser;
-use color_eyre::eyre::{self, Result, eyre};
+use color_eyre::eyre::{self, Context, Result, eyre};
+use russh_sftp::client::SftpSession;
 use tracing::{debug, info};
 
 mod cli;
@@ -16,12 +21,23 @@ mod files;
 mod logging;
 mod par_dir_traversal;
 mod patched_line_gauge;
+mod scp;
+mod sort;
 mod ssh;
 mod ssh_config;
+mod transfer;
 mod tui;
 
 fn main() -> Result<()> {
-    let config = config::Settings::new()?;
+    // Run as rsync's remote shell by a sync with `--delta`.
+    #[cfg(unix)]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if args.get(1).is_some_and(|arg| arg == sync::RSYNC_SHELL) {
+            return sync::rsync_shell(&args[2..]);
+        }
+    }
+    let mut config = config::Settings::new()?;
     let logging_config = (&config).into();
     logging::init(logging_config)?;
 
@@ -48,11 +64,34 @@ fn main() -> Result<()> {
         _ => {}
     }
 
-    let cli = match cli.command {
-        Some(Commands::Connect(cli)) => cli,
-        None => cli.connect,
+    let (cli, sync_args) = match cli.command {
+        Some(Commands::Connect(cli)) => (cli, None),
+        Some(Commands::Sync(args)) => {
+            // Checked before connecting, so that a missing argument does not
+            // wait on the network or a password first.
+            let local = args
+                .local
+                .clone()
+                .ok_or_else(|| eyre!("missing required argument: <local>"))
+                .wrap_err(
+                    "You must provide a local directory. Example: filessh sync example.com /var/www ./site",
+                )?;
+            (args.connect.clone(), Some((args, local)))
+        }
+        None => (cli.connect, None),
         _ => unreachable!(),
     };
+    // Flags can only switch behaviour on, over what the config file says.
+    config.transfer.preserve |= cli.preserve || cli.preserve_ownership;
+    config.transfer.preserve_ownership |= cli.preserve_ownership;
+    if let Some(rate) = &cli.download_limit {
+        config.transfer.download_limit = Some(rate.parse()?);
+    }
+    if let Some(rate) = &cli.upload_limit {
+        config.transfer.upload_limit = Some(rate.parse()?);
+    }
+    transfer::ratelimit::set_global_limit(Direction::Download, config.transfer.download_limit);
+    transfer::ratelimit::set_global_limit(Direction::Upload, config.transfer.upload_limit);
     let cli = cli.resolve()?;
 
     info!("Connecting to {}:{}", cli.host, cli.port);
@@ -65,6 +104,7 @@ fn main() -> Result<()> {
         .build()?;
     let (session, sftp) = rt.block_on(async {
         let cli = cli.clone();
+        let sftp_server = config.host(&cli.host).sftp_server;
         let mut ssh = Session::connect(
             cli.private_key,
             cli.username.unwrap_or("root".to_string()),
@@ -73,11 +113,18 @@ fn main() -> Result<()> {
         )
         .await?;
         info!("Connected");
+        if let Some(user) = &cli.sudo {
+            ssh.elevate(user, sftp_server.as_deref()).await?;
+            info!("Running the SFTP server as {user}");
+        }
         let sftp = ssh.sftp().await?;
         sftp.set_timeout(60000).await;
         eyre::Ok((ssh, sftp))
     })?;
     let sftp = Arc::new(sftp);
+    if let Some((args, local)) = sync_args {
+        return rt.block_on(run_sync(&session, sftp, args, local, cli, &config));
+    }
     let session = Arc::new(AsyncMutex::new(session));
 
     crate::tui::tui(
@@ -86,7 +133,57 @@ fn main() -> Result<()> {
         rt,
         sftp,
         session,
-        config.get_theme(),
+        config,
     )?;
     eyre::Ok(())
 }
+
+/// Runs `filessh sync`, printing the plan for a dry run or each change as it
+/// is made.
+async fn run_sync(
+    session: &Session,
+    sftp: Arc<SftpSession>,
+    args: SyncArgs,
+    local: PathBuf,
+    cli: ResolvedConnectArgs,
+    config: &config::Settings,
+) -> Result<()> {
+    let remote_root = cli.path.display().to_string();
+    // Walked entries are named from the root as given, so settle on one
+    // spelling of it where it exists.
+    let remote_root = sftp
+        .canonicalize(remote_root.clone())
+        .await
+        .unwrap_or(remote_root);
+    let include = [config.download.include.clone(), args.include].concat();
+    let exclude = [config.download.exclude.clone(), args.exclude].concat();
+    let options = SyncOptions {
+        direction: if args.pull {
+            Direction::Download
+        } else {
+            Direction::Upload
+        },
+        delete: args.delete,
+        checksum: args.checksum,
+        filter: PathFilter::new(&include, &exclude)?,
+        delta: args.delta,
+    };
+    let remote = session.remote();
+    let plan = sync::plan(Arc::clone(&sftp), &remote, &local, &remote_root, &options).await?;
+    if let Some(report) = plan.skipped.report("Listing") {
+        eprintln!("{report}");
+    }
+    if args.dry_run {
+        println!("{}", plan.describe());
+        return Ok(());
+    }
+    let summary = sync::apply(&sftp, &remote, &plan, &options, config.transfer, |change| {
+        println!("{change}")
+    })
+    .await?;
+    if let Some(report) = summary.report("Sync") {
+        eyre::bail!("{report}");
+    }
+    println!("Synced {} changes", summary.done);
+    Ok(())
+}
@@ -1,17 +1,14 @@
 #![allow(dead_code)]
 /// Inspired by the Parallel Walker in the
 /// `ignore` crate
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, eyre};
 use crossbeam::deque::{Stealer, Worker as Deque};
 use futures::future::join_all; // add at top of file if not present
-use russh_sftp::{
-    client::{SftpSession, fs::Metadata},
-    protocol::FileType,
-};
+use russh_sftp::client::{SftpSession, fs::Metadata};
 use std::{
     path::PathBuf,
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, AtomicUsize, Ordering},
     },
 };
@@ -23,12 +20,21 @@ pub type Filter = Arc<dyn Fn(&FileEntry) -> bool + Send + Sync + 'static>;
 
 #[allow(dead_code)]
 pub struct WalkParallel {
+    /// Decides which entries below the root are visited. It sees each entry
+    /// with its full path as the name; a directory it rejects is pruned.
     pub filter: Filter,
     pub path: PathBuf,
+    /// How deep to descend below the root, or `None` for no limit.
     pub max_depth: Option<usize>,
+    /// Entries shallower than this are walked through but not visited.
     pub min_depth: Option<usize>,
     pub threads: usize,
     pub sftp: Arc<SftpSession>,
+    /// Visit what symbolic links point to rather than the links themselves,
+    /// and descend into linked directories. A link leading back into a
+    /// directory being walked is reported as an error instead. Links that
+    /// point nowhere are visited as links either way.
+    pub follow_links: bool,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -81,20 +87,59 @@ impl WalkParallel {
         self.visit(&mut FnBuilder { builder: mkf }).await
     }
 
+    /// Walks the whole tree, returning every visited entry along with the
+    /// errors met on the way.
+    pub async fn collect(self) -> (Vec<FileEntry>, Vec<color_eyre::Report>) {
+        let found = Arc::new(Mutex::new((Vec::new(), Vec::new())));
+        let shared = Arc::clone(&found);
+        self.run(move || {
+            let found = Arc::clone(&shared);
+            Box::new(move |entry| {
+                let mut found = found.lock().unwrap_or_else(|p| p.into_inner());
+                match entry {
+                    Ok(entry) => found.0.push(entry),
+                    Err(error) => found.1.push(error),
+                }
+                WalkState::Continue
+            })
+        })
+        .await;
+        let mut found = found.lock().unwrap_or_else(|p| p.into_inner());
+        std::mem::take(&mut *found)
+    }
+
     pub async fn visit(self, builder: &mut dyn ParallelVisitorBuilder<'static>) {
         let threads = self.threads();
 
         // --- Create the root work item ------------------------------------
         let root_path = self.path.display().to_string();
 
-        // Build a synthetic root FileEntry (a directory)
-        let attr = Metadata::empty();
-        let root_entry = FileEntry::from_file(root_path.clone(), FileType::Dir, attr);
+        // Stat the root so visitors see its real attributes, falling back to
+        // a bare directory if that fails
+        let attr = self
+            .sftp
+            .metadata(root_path.clone())
+            .await
+            .unwrap_or_else(|_| {
+                let mut attr = Metadata::empty();
+                attr.permissions = Some(0o040000);
+                attr
+            });
+        let root_entry = FileEntry::from_file(root_path.clone(), attr.file_type(), attr);
+        let real_path = if self.follow_links {
+            self.sftp
+                .canonicalize(root_path.clone())
+                .await
+                .unwrap_or_else(|_| root_path.clone())
+        } else {
+            root_path.clone()
+        };
 
-        // The traversal starts at the root path (cwd = root_path)
         let init: Vec<Message> = vec![Message::Work(Work {
             entry: root_entry,
-            cwd: root_path.clone(),
+            path: root_path,
+            real_path,
+            depth: 0,
         })];
 
         // --- Create per-thread work-stealing stacks -----------------------
@@ -111,15 +156,17 @@ impl WalkParallel {
         // --- Spawn workers ------------------------------------------------
         let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(threads);
 
-        for (stack, visitor) in stacks.into_iter().zip(visitors.into_iter()) {
+        for (stack, visitor) in stacks.into_iter().zip(visitors) {
             let worker = Woker {
                 visitor,
                 stack,
                 quit_now: Arc::clone(&quit_now),
                 active_workers: Arc::clone(&active_workers),
                 max_depth: self.max_depth,
+                min_depth: self.min_depth,
                 filter: Some(self.filter.clone()),
                 sftp: Arc::clone(&self.sftp),
+                follow_links: self.follow_links,
             };
 
             // Each worker runs concurrently; owns its visitor and stack
@@ -154,38 +201,70 @@ enum Message {
 
 pub struct Work {
     entry: FileEntry,
-    cwd: String, // full remote path to the *entry*
+    /// Full remote path to the entry.
+    path: String,
+    /// The entry's path with every followed link resolved. Only kept up to
+    /// date when following links, to notice loops.
+    real_path: String,
+    /// How far below the root the entry is; the root itself is at 0.
+    depth: usize,
 }
 
 impl Work {
-    pub async fn read_dir(&self, sftp: Arc<SftpSession>) -> Result<Vec<Work>> {
-        // Determine the directory path we should read.
-        // For the root, `cwd` is already the directory path.
-        let dir_path = if self.entry.is_dir() {
-            if self.cwd.ends_with(self.entry.name()) {
-                // If cwd already ends with the entry name, use it as-is
-                self.cwd.clone()
-            } else {
-                // Otherwise, join cwd + entry name
-                format!("{}/{}", self.cwd, self.entry.name())
-            }
-        } else {
+    /// The children of this entry, if it is a directory. A child that is a
+    /// followed link leading back into this directory comes out as an error.
+    pub async fn read_dir(
+        &self,
+        sftp: Arc<SftpSession>,
+        follow_links: bool,
+    ) -> Result<Vec<Result<Work>>> {
+        if !self.entry.is_dir() {
             // Not a directory, nothing to read
             return Ok(vec![]);
-        };
+        }
 
         // Read entries via SFTP
-        let entries = sftp.read_dir(dir_path.clone()).await?;
-
-        let works = entries
-            .map(|child| Work {
-                entry: FileEntry::from(child),
-                cwd: dir_path.clone(), // new base for children
-            })
-            .collect::<Vec<_>>();
+        let entries = sftp.read_dir(self.path.clone()).await?;
+
+        let mut works = Vec::new();
+        for child in entries {
+            let mut entry = FileEntry::from(child);
+            let path = format!("{}/{}", self.path.trim_end_matches('/'), entry.name());
+            let mut real_path =
+                format!("{}/{}", self.real_path.trim_end_matches('/'), entry.name());
+            if follow_links
+                && entry.is_symlink()
+                && let Ok(target) = sftp.metadata(path.clone()).await
+            {
+                entry.type_ = target.file_type();
+                entry.attributes = target;
+                if entry.is_dir() {
+                    real_path = sftp.canonicalize(path.clone()).await?;
+                    if leads_back(&self.real_path, &real_path) {
+                        works.push(Err(eyre!("{path}: symbolic link loop to {real_path}")));
+                        continue;
+                    }
+                }
+            }
+            works.push(Ok(Work {
+                path,
+                real_path,
+                entry,
+                depth: self.depth + 1,
+            }));
+        }
 
         Ok(works)
     }
+
+    /// The entry with its full path as the name, as visitors see it.
+    fn absolute_entry(&self) -> FileEntry {
+        FileEntry::from_file(
+            self.path.clone(),
+            self.entry.type_,
+            self.entry.attributes.clone(),
+        )
+    }
 }
 
 /// A work-stealing stack.
@@ -264,8 +343,10 @@ pub struct Woker<'a> {
     quit_now: Arc<AtomicBool>,
     active_workers: Arc<AtomicUsize>,
     max_depth: Option<usize>,
+    min_depth: Option<usize>,
     filter: Option<Filter>,
     sftp: Arc<SftpSession>,
+    follow_links: bool,
 }
 
 impl WalkState {
@@ -288,38 +369,29 @@ impl<'a> Woker<'a> {
     }
 
     pub async fn run_one(&mut self, work: Work) -> WalkState {
-        let sftp = Arc::clone(&self.sftp);
-        let readdir = work.read_dir(sftp).await;
-
-        // --- Create a new FileEntry with an absolute name -----------------
-        let abs_entry = {
-            let path = if work.cwd.ends_with(work.entry.name()) {
-                work.cwd.clone()
-            } else {
-                PathBuf::from(&work.cwd)
-                    .join(work.entry.name())
-                    .to_string_lossy()
-                    .to_string()
-            };
-
-            // Rebuild FileEntry, preserving its metadata but using abs path as the name
-            FileEntry::from_file(path, FileType::Dir, work.entry.attributes.clone())
-        };
-
         // Visit the current file/directory with absolute name
-        let state = self.visitor.visit(Ok(abs_entry));
-        if !state.is_continue() {
-            return state;
+        if self.min_depth.is_none_or(|min| work.depth >= min) {
+            let state = self.visitor.visit(Ok(work.absolute_entry()));
+            if !state.is_continue() {
+                return state;
+            }
+        }
+        if self.max_depth.is_some_and(|max| work.depth >= max) {
+            return WalkState::Continue;
         }
 
         // --- Process directory contents -----------------------------------
-        let readdir = match readdir {
+        let sftp = Arc::clone(&self.sftp);
+        let readdir = match work.read_dir(sftp, self.follow_links).await {
             Ok(readdir) => readdir,
-            Err(err) => return self.visitor.visit(Err(err)),
+            Err(err) => return self.visitor.visit(Err(eyre!("{}: {err}", work.path))),
         };
 
         for child_work in readdir {
-            let state = self.generate_work(child_work).await;
+            let state = match child_work {
+                Ok(child_work) => self.generate_work(child_work).await,
+                Err(err) => self.visitor.visit(Err(err)),
+            };
             if state.is_quit() {
                 return state;
             }
@@ -329,6 +401,13 @@ impl<'a> Woker<'a> {
     }
 
     pub async fn generate_work(&mut self, work: Work) -> WalkState {
+        // Entries the filter turns down are never pushed, so a rejected
+        // directory is not read at all.
+        if let Some(filter) = &self.filter
+            && !filter(&work.absolute_entry())
+        {
+            return WalkState::Continue;
+        }
         // Push this new work onto the queue
         self.send(work);
         WalkState::Continue
@@ -418,3 +497,28 @@ impl<'a> Woker<'a> {
         self.active_workers.fetch_add(1, Ordering::Release);
     }
 }
+
+/// Whether a link resolving to `target` leads back into the directory at
+/// `real_dir`, that is, to it or to one of its ancestors.
+pub fn leads_back(real_dir: &str, target: &str) -> bool {
+    let target = target.trim_end_matches('/');
+    let real_dir = real_dir.trim_end_matches('/');
+    real_dir == target
+        || real_dir
+            .strip_prefix(target)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::leads_back;
+
+    #[test]
+    fn links_to_ancestors_lead_back() {
+        assert!(leads_back("/srv/data/a", "/srv/data/a"));
+        assert!(leads_back("/srv/data/a", "/srv"));
+        assert!(leads_back("/srv/data/a", "/"));
+        assert!(!leads_back("/srv/data/a", "/srv/data/a/b"));
+        assert!(!leads_back("/srv/data/ab", "/srv/data/a"));
+    }
+}
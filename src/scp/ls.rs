@@ -0,0 +1,198 @@
+//! Listing directories by reading `ls -l`, for hosts with no SFTP server to
+//! ask.
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use russh_sftp::protocol::FileAttributes;
+
+use super::quote;
+
+/// One line of `ls -ln` output.
+#[derive(Debug, Clone)]
+pub(crate) struct Listing {
+    pub(crate) name: String,
+    pub(crate) attrs: FileAttributes,
+    /// The line as `ls` printed it, which is what an SFTP server sends as the
+    /// long name anyway.
+    pub(crate) line: String,
+}
+
+/// The `ls` command that lists `path` with `flags`, which should include
+/// `-ln`. With `epoch_times`, GNU `ls` is asked for exact times in seconds;
+/// others only print minutes, or only the day for older files.
+pub(crate) fn command(flags: &str, path: &str, epoch_times: bool) -> String {
+    let time_style = if epoch_times { " --time-style=+%s" } else { "" };
+    format!("LC_ALL=C ls {flags}{time_style} -- {}", quote(path))
+}
+
+/// Parses one line of `ls -ln`, or returns `None` for anything that does not
+/// describe an entry, such as the `total` line.
+///
+/// Times without a year, which `ls` prints for recent files, are taken to be
+/// in the last twelve months before `now`. Times are read as UTC, since the
+/// server's time zone is not known.
+pub(crate) fn parse_line(line: &str, now: DateTime<Utc>) -> Option<Listing> {
+    let (mode, rest) = next_word(line)?;
+    let permissions = parse_mode(mode)?;
+    let (_links, rest) = next_word(rest)?;
+    let (uid, rest) = next_word(rest)?;
+    let (gid, rest) = next_word(rest)?;
+    let (size, rest) = next_word(rest)?;
+    // Devices print `major, minor` where other entries print their size.
+    let (size, rest) = if size.ends_with(',') {
+        (None, next_word(rest)?.1)
+    } else {
+        (Some(size.parse().ok()?), rest)
+    };
+    let (time, rest) = next_word(rest)?;
+    let (mtime, rest) = if time.bytes().all(|b| b.is_ascii_digit()) {
+        (time.parse().ok()?, rest)
+    } else {
+        let (day, rest) = next_word(rest)?;
+        let (year_or_time, rest) = next_word(rest)?;
+        (parse_date(time, day, year_or_time, now)?, rest)
+    };
+    // A single space separates the time from the name, which keeps any
+    // spaces of its own.
+    let name = rest.strip_prefix(' ')?;
+    let name = match name.split_once(" -> ") {
+        Some((name, _target)) if mode.starts_with('l') => name,
+        _ => name,
+    };
+    if name.is_empty() {
+        return None;
+    }
+    Some(Listing {
+        name: name.to_string(),
+        attrs: FileAttributes {
+            size,
+            uid: uid.parse().ok(),
+            gid: gid.parse().ok(),
+            permissions: Some(permissions),
+            atime: Some(mtime),
+            mtime: Some(mtime),
+            ..Default::default()
+        },
+        line: line.to_string(),
+    })
+}
+
+/// The first word of `s`, and everything after it.
+fn next_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start_matches(' ');
+    let end = s.find(' ').unwrap_or(s.len());
+    (end > 0).then(|| s.split_at(end))
+}
+
+/// Turns `drwxr-sr-x` into the mode it shows, file type bits included.
+fn parse_mode(mode: &str) -> Option<u32> {
+    let mode = mode.as_bytes();
+    if mode.len() < 10 {
+        return None;
+    }
+    let mut bits = match mode[0] {
+        b'-' => 0o100000,
+        b'd' => 0o040000,
+        b'l' => 0o120000,
+        b'c' => 0o020000,
+        b'b' => 0o060000,
+        b'p' => 0o010000,
+        b's' => 0o140000,
+        _ => return None,
+    };
+    for (class, triple) in mode[1..10].chunks(3).enumerate() {
+        let shift = 6 - 3 * class as u32;
+        if triple[0] == b'r' {
+            bits |= 0o4 << shift;
+        }
+        if triple[1] == b'w' {
+            bits |= 0o2 << shift;
+        }
+        // The execute column also carries setuid, setgid and sticky: lower
+        // case when the execute bit is set as well.
+        let special = 0o1000 << (2 - class);
+        match triple[2] {
+            b'x' => bits |= 0o1 << shift,
+            b's' | b't' => bits |= special | 0o1 << shift,
+            b'S' | b'T' => bits |= special,
+            _ => {}
+        }
+    }
+    Some(bits)
+}
+
+/// Reads `Jan  5 12:00` or `Jan  5  2023` as seconds since the epoch.
+fn parse_date(month: &str, day: &str, year_or_time: &str, now: DateTime<Utc>) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let day = day.parse().ok()?;
+    let at = |year, hour, minute| {
+        let date = NaiveDate::from_ymd_opt(year, month, day)?;
+        Some(Utc.from_utc_datetime(&date.and_hms_opt(hour, minute, 0)?))
+    };
+    let time = match year_or_time.split_once(':') {
+        Some((hour, minute)) => {
+            let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+            // A day ahead of `now` allows for the server's clock or zone.
+            match at(now.year(), hour, minute)? {
+                time if time > now + chrono::Duration::days(1) => at(now.year() - 1, hour, minute)?,
+                time => time,
+            }
+        }
+        None => at(year_or_time.parse().ok()?, 0, 0)?,
+    };
+    u32::try_from(time.timestamp()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gnu_and_busybox_listings() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+
+        let gnu = parse_line(
+            "-rw-r--r--. 1 1000 100 1234 1709251200 notes with  spaces.txt",
+            now,
+        )
+        .unwrap();
+        assert_eq!(gnu.name, "notes with  spaces.txt");
+        assert_eq!(gnu.attrs.size, Some(1234));
+        assert_eq!(gnu.attrs.permissions, Some(0o100644));
+        assert_eq!(gnu.attrs.uid, Some(1000));
+        assert_eq!(gnu.attrs.mtime, Some(1709251200));
+
+        let recent = parse_line("drwxrwsr-t    2 0  0  4096 Feb 29 12:30 shared", now).unwrap();
+        assert_eq!(recent.attrs.permissions, Some(0o043775));
+        assert_eq!(
+            recent.attrs.mtime,
+            Some(
+                Utc.with_ymd_and_hms(2024, 2, 29, 12, 30, 0)
+                    .unwrap()
+                    .timestamp() as u32
+            )
+        );
+        // Later in the year than `now`, so last year's.
+        let last_year = parse_line("-rwsr-xr-x 1 0 0 10 Dec 24 18:00 su", now).unwrap();
+        assert_eq!(last_year.attrs.permissions, Some(0o104755));
+        assert_eq!(
+            last_year.attrs.mtime,
+            Some(
+                Utc.with_ymd_and_hms(2023, 12, 24, 18, 0, 0)
+                    .unwrap()
+                    .timestamp() as u32
+            )
+        );
+
+        let link = parse_line("lrwxrwxrwx 1 0 0 7 Jan  5  2020 sh -> busybox", now).unwrap();
+        assert_eq!(link.name, "sh");
+        assert_eq!(link.attrs.permissions, Some(0o120777));
+
+        let device = parse_line("crw-rw-rw- 1 0 0 1,   3 Jan  5  2020 null", now).unwrap();
+        assert_eq!(device.attrs.size, None);
+        assert_eq!(device.attrs.permissions, Some(0o020666));
+
+        assert!(parse_line("total 12", now).is_none());
+    }
+}
@@ -0,0 +1,189 @@
+//! The scp wire protocol, spoken to a remote `scp -f` (which sends a file) or
+//! `scp -t` (which receives one) over an exec channel.
+//!
+//! Each side acknowledges a step with a zero byte, or refuses it with a 1
+//! (warning) or 2 (fatal error) followed by a message line. A file is sent as
+//! a `C<mode> <size> <name>` line, the contents, and a zero byte.
+use std::collections::VecDeque;
+
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg};
+use russh_sftp::protocol::StatusCode;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{Failure, quote};
+use crate::ssh::Remote;
+
+/// An scp command and what it has sent that has not been read yet.
+struct Wire {
+    channel: Channel<Msg>,
+    received: VecDeque<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Wire {
+    async fn start(remote: &Remote, command: String) -> Result<Self, Failure> {
+        Ok(Self {
+            channel: remote.exec(&command).await?.into_channel(),
+            received: VecDeque::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    /// Waits for more data, failing with whatever went to stderr if the
+    /// command ends first, as it does when there is no `scp` to run.
+    async fn fill(&mut self) -> Result<(), Failure> {
+        while self.received.is_empty() {
+            match self.channel.wait().await {
+                Some(ChannelMsg::Data { data }) => self.received.extend(&data[..]),
+                Some(ChannelMsg::ExtendedData { data, .. }) => self.stderr.extend_from_slice(&data),
+                Some(ChannelMsg::Eof | ChannelMsg::Close) | None => {
+                    let stderr = String::from_utf8_lossy(&self.stderr).trim().to_string();
+                    return Err(if stderr.is_empty() {
+                        Failure::new(StatusCode::Failure, "scp exited early")
+                    } else {
+                        Failure::from_message(stderr)
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, Failure> {
+        self.fill().await?;
+        Ok(self.received.pop_front().expect("filled"))
+    }
+
+    async fn read_line(&mut self) -> Result<String, Failure> {
+        let mut line = Vec::new();
+        loop {
+            match self.read_byte().await? {
+                b'\n' => return Ok(String::from_utf8_lossy(&line).into_owned()),
+                byte => line.push(byte),
+            }
+        }
+    }
+
+    /// Reads a zero byte, or the error sent in its place.
+    async fn read_ack(&mut self) -> Result<(), Failure> {
+        match self.read_byte().await? {
+            0 => Ok(()),
+            1 | 2 => Err(Failure::from_message(self.read_line().await?)),
+            byte => Err(Failure::new(
+                StatusCode::BadMessage,
+                format!("scp sent {byte:#04x} instead of an acknowledgement"),
+            )),
+        }
+    }
+
+    async fn send(&self, data: impl AsyncRead + Unpin) -> Result<(), Failure> {
+        self.channel
+            .data(data)
+            .await
+            .map_err(|err| Failure::new(StatusCode::ConnectionLost, err.to_string()))
+    }
+}
+
+/// A file coming from a remote `scp -f`, read front to back.
+pub(crate) struct Source {
+    wire: Wire,
+    pub(crate) mode: u32,
+    pub(crate) size: u64,
+    /// How much of the file has been read so far.
+    pub(crate) position: u64,
+    finished: bool,
+}
+
+impl Source {
+    pub(crate) async fn open(remote: &Remote, path: &str) -> Result<Self, Failure> {
+        let mut wire = Wire::start(remote, format!("scp -f -- {}", quote(path))).await?;
+        wire.send(&b"\0"[..]).await?;
+        loop {
+            let first = wire.read_byte().await?;
+            if matches!(first, 1 | 2) {
+                return Err(Failure::from_message(wire.read_line().await?));
+            }
+            let record = format!("{}{}", first as char, wire.read_line().await?);
+            match first {
+                // Times, which only come with `-p`.
+                b'T' => wire.send(&b"\0"[..]).await?,
+                b'C' => {
+                    let mut fields = record[1..].splitn(3, ' ');
+                    let mode = fields.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+                    let size = fields.next().and_then(|s| s.parse().ok());
+                    let (Some(mode), Some(size)) = (mode, size) else {
+                        return Err(Failure::new(
+                            StatusCode::BadMessage,
+                            format!("scp sent an unreadable header {record:?}"),
+                        ));
+                    };
+                    wire.send(&b"\0"[..]).await?;
+                    return Ok(Self {
+                        wire,
+                        mode,
+                        size,
+                        position: 0,
+                        finished: false,
+                    });
+                }
+                b'D' => {
+                    return Err(Failure::new(
+                        StatusCode::Failure,
+                        format!("{path} is a directory"),
+                    ));
+                }
+                _ => {
+                    return Err(Failure::new(
+                        StatusCode::BadMessage,
+                        format!("scp sent an unexpected {record:?}"),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Reads up to `len` bytes, or nothing at the end of the file.
+    pub(crate) async fn read(&mut self, len: usize) -> Result<Vec<u8>, Failure> {
+        if self.position == self.size {
+            if !self.finished {
+                // The sender ends the file with an acknowledgement of its own.
+                self.wire.read_ack().await?;
+                self.wire.send(&b"\0"[..]).await?;
+                self.finished = true;
+            }
+            return Ok(Vec::new());
+        }
+        self.wire.fill().await?;
+        let len = len
+            .min(self.wire.received.len())
+            .min((self.size - self.position) as usize);
+        self.position += len as u64;
+        Ok(self.wire.received.drain(..len).collect())
+    }
+}
+
+/// Writes `size` bytes of `contents` to the remote `path` through `scp -t`,
+/// creating or replacing the file with the given permission bits.
+pub(crate) async fn send_file(
+    remote: &Remote,
+    path: &str,
+    mode: u32,
+    size: u64,
+    contents: impl AsyncRead + Unpin,
+) -> Result<(), Failure> {
+    let mut wire = Wire::start(remote, format!("scp -t -- {}", quote(path))).await?;
+    wire.read_ack().await?;
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let header = format!("C{:04o} {size} {name}\n", mode & 0o7777);
+    wire.send(header.as_bytes()).await?;
+    wire.read_ack().await?;
+    wire.send(contents.take(size)).await?;
+    wire.send(&b"\0"[..]).await?;
+    wire.read_ack().await?;
+    wire.channel
+        .eof()
+        .await
+        .map_err(|err| Failure::new(StatusCode::ConnectionLost, err.to_string()))
+}
@@ -0,0 +1,247 @@
+//! The order the file list is shown in: by a column, either way round, with
+//! directories first or mixed in with the files.
+//!
+//! Names compare the way people read them, ignoring case and taking runs of
+//! digits as numbers, so `file2` comes before `file10`.
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::files::FileEntry;
+
+/// What the list is sorted by.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    /// The part of the name after its last dot, for names with one.
+    Extension,
+    Permissions,
+}
+
+impl SortKey {
+    /// The column of the file list that shows what is sorted by.
+    pub(crate) fn column(self) -> usize {
+        match self {
+            Self::Permissions => 0,
+            Self::Name | Self::Extension => 1,
+            Self::Size => 2,
+            Self::Modified => 4,
+        }
+    }
+
+    /// What clicking on the header of `column` sorts by.
+    pub(crate) fn for_column(column: usize) -> Option<Self> {
+        match column {
+            0 => Some(Self::Permissions),
+            1 => Some(Self::Name),
+            2 => Some(Self::Size),
+            4 => Some(Self::Modified),
+            _ => None,
+        }
+    }
+}
+
+/// How the file list is sorted.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct SortOrder {
+    pub(crate) key: SortKey,
+    pub(crate) descending: bool,
+    /// Whether directories, and links to them, come before the files
+    /// whichever way the rest is sorted.
+    pub(crate) dirs_first: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            key: SortKey::default(),
+            descending: false,
+            dirs_first: true,
+        }
+    }
+}
+
+impl SortOrder {
+    /// Sorts by `key`, or the other way round if the list already is.
+    pub(crate) fn toggle(self, key: SortKey) -> Self {
+        Self {
+            key,
+            descending: self.key == key && !self.descending,
+            ..self
+        }
+    }
+
+    /// The header of the sorted column, marked with which way it goes.
+    pub(crate) fn mark(&self, column: usize, header: &str) -> String {
+        if column != self.key.column() {
+            return header.to_string();
+        }
+        let arrow = if self.descending { '▼' } else { '▲' };
+        match self.key {
+            SortKey::Extension => format!("{header} (ext) {arrow}"),
+            _ => format!("{header} {arrow}"),
+        }
+    }
+
+    pub(crate) fn sort(&self, entries: &mut [FileEntry]) {
+        entries.sort_by(|a, b| self.compare(a, b));
+    }
+
+    fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        if self.dirs_first {
+            let grouped = b.leads_to_dir().cmp(&a.leads_to_dir());
+            if grouped.is_ne() {
+                return grouped;
+            }
+        }
+        let (a_attributes, b_attributes) = (&a.attributes, &b.attributes);
+        let by_key = match self.key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a_attributes.size.cmp(&b_attributes.size),
+            SortKey::Modified => a_attributes.mtime.cmp(&b_attributes.mtime),
+            SortKey::Extension => match (extension(&a.name), extension(&b.name)) {
+                (Some(a), Some(b)) => natural(a, b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            },
+            SortKey::Permissions => {
+                let mode = |permissions: Option<u32>| permissions.map(|mode| mode & 0o7777);
+                mode(a_attributes.permissions).cmp(&mode(b_attributes.permissions))
+            }
+        };
+        let ordering = by_key.then_with(|| natural(&a.name, &b.name));
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// What follows the last dot of `name`, unless the dot starts it.
+fn extension(name: &str) -> Option<&str> {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => Some(extension),
+        _ => None,
+    }
+}
+
+/// Compares names regardless of case, with runs of digits compared as the
+/// numbers they spell. Names that differ only in case or leading zeros are
+/// told apart by their bytes, so that no two names are equal.
+pub(crate) fn natural(a: &str, b: &str) -> Ordering {
+    let (mut a_rest, mut b_rest) = (a, b);
+    loop {
+        let (a_part, a_next) = next_part(a_rest);
+        let (b_part, b_next) = next_part(b_rest);
+        let ordering = match (a_part, b_part) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_part), Some(b_part)) => {
+                let a_number = a_part.starts_with(|c: char| c.is_ascii_digit());
+                let b_number = b_part.starts_with(|c: char| c.is_ascii_digit());
+                if a_number && b_number {
+                    let a_digits = a_part.trim_start_matches('0');
+                    let b_digits = b_part.trim_start_matches('0');
+                    a_digits
+                        .len()
+                        .cmp(&b_digits.len())
+                        .then_with(|| a_digits.cmp(b_digits))
+                } else {
+                    let a_lower = a_part.chars().flat_map(char::to_lowercase);
+                    a_lower.cmp(b_part.chars().flat_map(char::to_lowercase))
+                }
+            }
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+        (a_rest, b_rest) = (a_next, b_next);
+    }
+}
+
+/// The leading run of digits, or of anything else, in `name`, and the rest.
+fn next_part(name: &str) -> (Option<&str>, &str) {
+    let Some(first) = name.chars().next() else {
+        return (None, name);
+    };
+    let digit = first.is_ascii_digit();
+    let end = name
+        .find(|c: char| c.is_ascii_digit() != digit)
+        .unwrap_or(name.len());
+    (Some(&name[..end]), &name[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_sftp::protocol::FileType;
+
+    fn names(entries: &[FileEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn names_sort_naturally_with_directories_first() {
+        let mut entries = vec![
+            FileEntry::sample("file10.txt", FileType::File, 1, None),
+            FileEntry::sample("src", FileType::Dir, 0, None),
+            FileEntry::sample("File2.md", FileType::File, 30, None),
+            FileEntry::sample("file1.txt", FileType::File, 20, None),
+            FileEntry::sample(".env", FileType::File, 5, None),
+            FileEntry::sample("Build", FileType::Dir, 0, None),
+        ];
+        let order = SortOrder::default();
+        order.sort(&mut entries);
+        assert_eq!(
+            names(&entries),
+            [
+                "Build",
+                "src",
+                ".env",
+                "file1.txt",
+                "File2.md",
+                "file10.txt"
+            ]
+        );
+
+        let by_size = order.toggle(SortKey::Size).toggle(SortKey::Size);
+        assert!(by_size.descending);
+        by_size.sort(&mut entries);
+        assert_eq!(
+            names(&entries),
+            [
+                "src",
+                "Build",
+                "File2.md",
+                "file1.txt",
+                ".env",
+                "file10.txt"
+            ]
+        );
+
+        let mixed = SortOrder {
+            key: SortKey::Extension,
+            descending: false,
+            dirs_first: false,
+        };
+        mixed.sort(&mut entries);
+        assert_eq!(
+            names(&entries),
+            [
+                ".env",
+                "Build",
+                "src",
+                "File2.md",
+                "file1.txt",
+                "file10.txt"
+            ]
+        );
+        assert_eq!(natural("a007", "a7"), Ordering::Less);
+        assert_eq!(natural("A", "a"), Ordering::Less);
+    }
+}
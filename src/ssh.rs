@@ -11,9 +11,11 @@ use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers
 use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
 use russh::keys::*;
 use russh::*;
-use russh_sftp::client::SftpSession;
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::net::ToSocketAddrs;
-use tracing::debug;
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
 
 /// How many times to ask for a passphrase before giving up, as in `ssh`.
 const PASSPHRASE_ATTEMPTS: usize = 3;
@@ -27,6 +29,30 @@ const PASSPHRASE_ATTEMPTS: usize = 3;
 static DECRYPTED_KEYS: LazyLock<Mutex<HashMap<PathBuf, Arc<PrivateKey>>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// The sudo password given in this process, kept for the same reason as
+/// [`DECRYPTED_KEYS`]: `main_ui::init` elevates its session too. It is
+/// wiped from memory when replaced or dropped.
+static SUDO_PASSWORD: Mutex<Option<Zeroizing<String>>> = Mutex::new(None);
+
+/// How many times to ask for the sudo password, as sudo does.
+const SUDO_ATTEMPTS: usize = 3;
+
+/// What sudo is told to ask for the password with, so that it is only sent
+/// once sudo is reading it, and not taken by the command as its input.
+const SUDO_PROMPT: &str = "filessh-sudo-password:";
+
+/// How long to wait for sudo to ask for the password.
+const SUDO_PROMPT_WAIT: Duration = Duration::from_secs(30);
+
+/// Where OpenSSH installs its SFTP server on Debian, Red Hat, Arch and the
+/// BSDs, tried in turn when a host does not say.
+const SFTP_SERVERS: [&str; 4] = [
+    "/usr/lib/openssh/sftp-server",
+    "/usr/libexec/openssh/sftp-server",
+    "/usr/lib/ssh/sftp-server",
+    "/usr/libexec/sftp-server",
+];
+
 fn decrypted_keys() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Arc<PrivateKey>>> {
     // A panic while holding this lock would leave a key cache, not a torn one.
     DECRYPTED_KEYS
@@ -34,6 +60,12 @@ fn decrypted_keys() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Arc<Priva
         .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
+fn remembered_sudo_password() -> std::sync::MutexGuard<'static, Option<Zeroizing<String>>> {
+    SUDO_PASSWORD
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 /// Loads a private key, asking for the passphrase if the key turns out to be
 /// encrypted. `russh` reports that as [`keys::Error::KeyIsEncrypted`] rather
 /// than prompting itself.
@@ -53,8 +85,11 @@ fn load_secret_key_interactive(key_path: &Path) -> Result<Arc<PrivateKey>> {
     }
 
     for attempt in 1..=PASSPHRASE_ATTEMPTS {
-        let passphrase = prompt_passphrase(key_path)?;
-        match load_secret_key(key_path, Some(&passphrase)) {
+        let passphrase = prompt_secret(
+            &format!("Enter passphrase for key '{}': ", key_path.display()),
+            &format!("the passphrase of {}", key_path.display()),
+        )?;
+        match load_secret_key(key_path, Some(passphrase.as_str())) {
             Ok(key) => {
                 let key = Arc::new(key);
                 decrypted_keys().insert(key_path.to_path_buf(), Arc::clone(&key));
@@ -81,17 +116,15 @@ fn load_secret_key_interactive(key_path: &Path) -> Result<Arc<PrivateKey>> {
     unreachable!("the last attempt returns")
 }
 
-/// Reads a passphrase from the terminal without echoing it.
-fn prompt_passphrase(key_path: &Path) -> Result<String> {
+/// Reads `secret`, such as a passphrase, from the terminal without echoing
+/// it, after showing `prompt`. It is wiped from memory once dropped.
+fn prompt_secret(prompt: &str, secret: &str) -> Result<Zeroizing<String>> {
     // Once the interface is up it owns the terminal, with its own raw mode and
     // its own reader; a second reader here would take the keystrokes meant for
     // it and hand the screen back in the wrong state. Reaching this means the
-    // key was not decrypted before the TUI started.
+    // secret was not asked for before the TUI started.
     if is_raw_mode_enabled().unwrap_or(false) {
-        bail!(
-            "{} is encrypted, and its passphrase cannot be asked for while the interface is running",
-            key_path.display()
-        );
+        bail!("{secret} cannot be asked for while the interface is running");
     }
 
     /// Leaves raw mode however the read ends, including on `?`.
@@ -102,13 +135,14 @@ fn prompt_passphrase(key_path: &Path) -> Result<String> {
         }
     }
 
-    eprint!("Enter passphrase for key '{}': ", key_path.display());
+    eprint!("{prompt}");
     std::io::stderr().flush()?;
 
-    enable_raw_mode().wrap_err("a terminal is needed to read the key passphrase")?;
+    enable_raw_mode().wrap_err_with(|| format!("a terminal is needed to read {secret}"))?;
     let _raw_mode = RawMode;
 
-    let mut passphrase = String::new();
+    // Room enough that the buffer is not moved, leaving a copy behind.
+    let mut passphrase = Zeroizing::new(String::with_capacity(1024));
     loop {
         let Event::Key(key) = event::read()? else {
             continue;
@@ -125,7 +159,7 @@ fn prompt_passphrase(key_path: &Path) -> Result<String> {
             (KeyCode::Esc, _) | (KeyCode::Char('c' | 'd'), KeyModifiers::CONTROL) => {
                 // Still in raw mode, so end the prompt line by hand.
                 eprint!("\r\n");
-                bail!("passphrase entry cancelled");
+                bail!("entering {secret} was cancelled");
             }
             (KeyCode::Char(c), modifiers) if !modifiers.contains(KeyModifiers::CONTROL) => {
                 passphrase.push(c);
@@ -169,7 +203,80 @@ impl client::Handler for Client {
 /// This struct is a convenience wrapper
 /// around a russh client
 pub struct Session {
-    session: client::Handle<Client>,
+    session: Arc<client::Handle<Client>>,
+    /// Set once the server has refused the sftp subsystem, so that later
+    /// sessions go straight to scp instead of asking again.
+    scp_fallback: bool,
+    /// Set by [`Session::elevate`].
+    elevation: Option<Arc<Elevation>>,
+}
+
+/// How an elevated [`Session`] runs its commands and SFTP servers.
+struct Elevation {
+    /// The user to run as, quoted for the shell.
+    user: String,
+    /// The command that starts an SFTP server.
+    server: String,
+    /// The password sudo asks for, if it asks for one.
+    password: Option<Zeroizing<String>>,
+}
+
+impl Elevation {
+    /// `command`, run through sudo as the user. With a password, sudo asks
+    /// for it with [`SUDO_PROMPT`]; `-k` has it ask even if it remembers one.
+    fn wrap(&self, command: &str) -> String {
+        let command = shell_escape::escape(command.into());
+        match self.password {
+            Some(_) => format!(
+                "sudo -k -S -p '{SUDO_PROMPT}' -u {} -- sh -c {command}",
+                self.user
+            ),
+            None => format!("sudo -n -u {} -- sh -c {command}", self.user),
+        }
+    }
+
+    /// Waits for sudo in `channel` to ask for the password, if it is to ask,
+    /// and sends it.
+    async fn authenticate(&self, channel: &mut Channel<client::Msg>) -> Result<()> {
+        let Some(password) = &self.password else {
+            return Ok(());
+        };
+        let mut stderr = Vec::new();
+        let asked = tokio::time::timeout(SUDO_PROMPT_WAIT, async {
+            while let Some(msg) = channel.wait().await {
+                match msg {
+                    ChannelMsg::ExtendedData { data, .. } => {
+                        stderr.extend_from_slice(&data);
+                        let prompt = SUDO_PROMPT.as_bytes();
+                        if stderr.windows(prompt.len()).any(|window| window == prompt) {
+                            return true;
+                        }
+                    }
+                    // Output before the prompt means sudo did not ask, and
+                    // the password would have gone to the command.
+                    ChannelMsg::Data { .. }
+                    | ChannelMsg::ExitStatus { .. }
+                    | ChannelMsg::Eof
+                    | ChannelMsg::Close => return false,
+                    _ => {}
+                }
+            }
+            false
+        })
+        .await;
+        match asked {
+            Ok(true) => {
+                let line = Zeroizing::new(format!("{}\n", password.as_str()));
+                channel.data(line.as_bytes()).await?;
+                Ok(())
+            }
+            Ok(false) => bail!(
+                "sudo did not ask for the password: {}",
+                String::from_utf8_lossy(&stderr).trim()
+            ),
+            Err(_) => bail!("sudo did not ask for the password in time"),
+        }
+    }
 }
 
 impl Session {
@@ -228,14 +335,164 @@ impl Session {
             }
         }
 
-        Ok(Self { session })
+        Ok(Self {
+            session: Arc::new(session),
+            scp_fallback: false,
+            elevation: None,
+        })
     }
 
+    /// Runs the SFTP server of every later session, and every later command,
+    /// as `user`, through sudo. The server is `sftp_server`, or else the first
+    /// of [`SFTP_SERVERS`] the host has. The password, if sudo wants one, is
+    /// asked for on the terminal the first time and kept for the rest of the
+    /// process.
+    pub async fn elevate(&mut self, user: &str, sftp_server: Option<&str>) -> Result<()> {
+        let user = shell_escape::escape(user.into());
+        let server = match sftp_server {
+            Some(path) => shell_escape::escape(path.into()).into_owned(),
+            None => {
+                let script = format!(
+                    "for server in {}; do [ -x \"$server\" ] && exec \"$server\"; done; \
+                     echo 'no sftp-server found' >&2; exit 127",
+                    SFTP_SERVERS.join(" ")
+                );
+                format!("sh -c {}", shell_escape::escape(script.into()))
+            }
+        };
+        let unasked = self
+            .exec(&format!("sudo -n -u {user} -- true"))
+            .await?
+            .output()
+            .await?;
+        let elevation = if unasked.status == Some(0) {
+            Arc::new(Elevation {
+                user: user.into_owned(),
+                server,
+                password: None,
+            })
+        } else {
+            self.sudo_password(&user, &server).await?
+        };
+        self.elevation = Some(elevation);
+        Ok(())
+    }
+
+    /// How to become `user` with the password sudo wants, as checked with
+    /// sudo itself.
+    async fn sudo_password(&self, user: &str, server: &str) -> Result<Arc<Elevation>> {
+        let check = |password: Zeroizing<String>| async move {
+            let elevation = Arc::new(Elevation {
+                user: user.to_string(),
+                server: server.to_string(),
+                password: Some(password),
+            });
+            let remote = Remote {
+                session: Arc::clone(&self.session),
+                elevation: Some(Arc::clone(&elevation)),
+            };
+            let output = remote.exec("true").await?.output().await?;
+            Ok::<_, color_eyre::Report>((elevation, output))
+        };
+        let remembered = remembered_sudo_password().clone();
+        if let Some(password) = remembered {
+            let (elevation, output) = check(password).await?;
+            if output.status == Some(0) {
+                return Ok(elevation);
+            }
+        }
+        for attempt in 1..=SUDO_ATTEMPTS {
+            let password = prompt_secret(
+                &format!("[sudo] password to run the SFTP server as {user}: "),
+                "the sudo password",
+            )?;
+            let (elevation, output) = check(password.clone()).await?;
+            if output.status == Some(0) {
+                *remembered_sudo_password() = Some(password);
+                return Ok(elevation);
+            }
+            let stderr = output.stderr();
+            // sudo asks again after a wrong password, and finds nothing more.
+            let wrong =
+                stderr.contains("Sorry, try again") || stderr.contains("incorrect password");
+            if !wrong || attempt == SUDO_ATTEMPTS {
+                bail!("sudo refused to run the SFTP server as {user}: {stderr}");
+            }
+            eprintln!("Sorry, try again.");
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    /// Starts the SFTP server through sudo, in a channel of its own.
+    async fn elevated_channel(&self, elevation: &Elevation) -> Result<Channel<client::Msg>> {
+        Ok(self.exec(&elevation.server).await?.into_channel())
+    }
+
+    /// Opens an SFTP session. Hosts without an sftp subsystem get one served
+    /// by [`crate::scp`] instead, over `scp` and `ls`.
     pub async fn sftp(&mut self) -> Result<SftpSession> {
-        let channel = self.session.channel_open_session().await?;
-        channel.request_subsystem(true, "sftp").await?;
-        let sftp = SftpSession::new(channel.into_stream()).await?;
-        Ok(sftp)
+        if let Some(elevation) = &self.elevation {
+            let channel = self.elevated_channel(elevation).await?;
+            return SftpSession::new(channel.into_stream())
+                .await
+                .wrap_err("could not start the SFTP server through sudo");
+        }
+        if !self.scp_fallback {
+            let mut channel = self.session.channel_open_session().await?;
+            channel.request_subsystem(true, "sftp").await?;
+            if subsystem_accepted(&mut channel).await {
+                let sftp = SftpSession::new(channel.into_stream()).await?;
+                return Ok(sftp);
+            }
+            warn!("the server refused the sftp subsystem, falling back to scp");
+            self.scp_fallback = true;
+        }
+        crate::scp::session(self.remote()).await
+    }
+
+    /// Opens an SFTP session of its own, without the high-level wrapper, if
+    /// the server lists `extension`: [`SftpSession`] has no way to send
+    /// requests it does not know. Hosts served over scp have no extensions.
+    pub async fn raw_sftp(&self, extension: &str) -> Result<Option<RawSftpSession>> {
+        if self.scp_fallback {
+            return Ok(None);
+        }
+        let channel = match &self.elevation {
+            Some(elevation) => self.elevated_channel(elevation).await?,
+            None => {
+                let mut channel = self.session.channel_open_session().await?;
+                channel.request_subsystem(true, "sftp").await?;
+                if !subsystem_accepted(&mut channel).await {
+                    return Ok(None);
+                }
+                channel
+            }
+        };
+        let sftp = RawSftpSession::new(channel.into_stream());
+        let version = sftp.init().await?;
+        if !version.extensions.contains_key(extension) {
+            return Ok(None);
+        }
+        Ok(Some(sftp))
+    }
+
+    /// Whether the server refused SFTP, and files go over scp instead.
+    pub fn uses_scp(&self) -> bool {
+        self.scp_fallback
+    }
+
+    /// Starts `command` on the remote host in a channel of its own, so the
+    /// session is free for other work while it runs. An elevated session runs
+    /// it through sudo.
+    pub async fn exec(&self, command: &str) -> Result<RemoteCommand> {
+        self.remote().exec(command).await
+    }
+
+    pub fn remote(&self) -> Remote {
+        Remote {
+            session: Arc::clone(&self.session),
+            elevation: self.elevation.clone(),
+        }
     }
 
     pub async fn close(&mut self) -> Result<()> {
@@ -245,3 +502,101 @@ impl Session {
         Ok(())
     }
 }
+
+/// Waits for the answer to a subsystem request. A server without the
+/// subsystem answers with a failure, or closes the channel.
+async fn subsystem_accepted(channel: &mut Channel<client::Msg>) -> bool {
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Success => return true,
+            ChannelMsg::Failure | ChannelMsg::Eof | ChannelMsg::Close => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The connection behind a [`Session`], for running commands from work that
+/// cannot hold the session's lock, such as the scp fallback serving SFTP.
+#[derive(Clone)]
+pub struct Remote {
+    session: Arc<client::Handle<Client>>,
+    elevation: Option<Arc<Elevation>>,
+}
+
+impl Remote {
+    /// See [`Session::exec`].
+    pub async fn exec(&self, command: &str) -> Result<RemoteCommand> {
+        let mut channel = self.session.channel_open_session().await?;
+        let wrapped = match &self.elevation {
+            Some(elevation) => Cow::Owned(elevation.wrap(command)),
+            None => Cow::Borrowed(command),
+        };
+        channel
+            .exec(true, wrapped.as_ref())
+            .await
+            .wrap_err_with(|| format!("could not run {command:?}"))?;
+        if let Some(elevation) = &self.elevation {
+            elevation.authenticate(&mut channel).await?;
+        }
+        Ok(RemoteCommand { channel })
+    }
+}
+
+/// A command started with [`Session::exec`].
+pub struct RemoteCommand {
+    channel: Channel<client::Msg>,
+}
+
+/// How a [`RemoteCommand`] ended.
+#[derive(Debug, Default)]
+pub struct ExecOutput {
+    /// `None` if the server closed the channel without reporting one, as it
+    /// does when the command was killed by a signal.
+    pub status: Option<u32>,
+    /// Empty when the output was streamed elsewhere.
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl ExecOutput {
+    pub fn stderr(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).trim().to_string()
+    }
+}
+
+impl RemoteCommand {
+    /// The channel the command runs in, for talking to it both ways.
+    pub fn into_channel(self) -> Channel<client::Msg> {
+        self.channel
+    }
+
+    /// Waits for the command to finish, collecting its output.
+    pub async fn output(self) -> Result<ExecOutput> {
+        let mut stdout = Vec::new();
+        let mut output = self.stream_to(&mut stdout).await?;
+        output.stdout = stdout;
+        Ok(output)
+    }
+
+    /// Waits for the command to finish, writing its standard output into
+    /// `stdout` as it arrives.
+    pub async fn stream_to<W: AsyncWrite + Unpin>(self, stdout: &mut W) -> Result<ExecOutput> {
+        let mut channel = self.channel;
+        // Nothing is sent to the command, so say so: one that reads its input,
+        // such as the sftp-server a `ForceCommand` runs, would wait forever.
+        channel.eof().await?;
+        let mut output = ExecOutput::default();
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => stdout.write_all(&data).await?,
+                ChannelMsg::ExtendedData { data, .. } => output.stderr.extend_from_slice(&data),
+                ChannelMsg::ExitStatus { exit_status } => output.status = Some(exit_status),
+                ChannelMsg::Failure => bail!("the server refused to run the command"),
+                _ => {}
+            }
+        }
+        stdout.flush().await?;
+        Ok(output)
+    }
+}
@@ -0,0 +1,466 @@
+//! Downloading files and directories as a single compressed tarball.
+//!
+//! Where the remote host has `tar`, it builds the archive and the bytes are
+//! streamed straight into the local file over an exec channel, which is far
+//! quicker than a round trip per file on a slow link. Otherwise the archive
+//! is put together here from a [`WalkParallel`] over SFTP.
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use async_lock::Mutex as AsyncMutex;
+use color_eyre::eyre::{Context, Result, bail};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileType;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+
+use super::conflict::{self, Conflict, ConflictResolver, Resolution, Stat};
+use super::ratelimit::{Limit, Throttled};
+use super::{Summary, SymlinkPolicy, TransferSettings, special_kind};
+use crate::files::FileEntry;
+use crate::par_dir_traversal::WalkParallel;
+use crate::ssh::Session;
+
+/// The `archive` key of the `[transfer]` section.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    /// `.tar.gz`, which opens anywhere.
+    #[default]
+    Gzip,
+    /// `.tar.zst`, smaller and quicker to make, where the remote host has
+    /// `zstd`. Falls back to gzip otherwise.
+    Zstd,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "tar.gz",
+            ArchiveFormat::Zstd => "tar.zst",
+        }
+    }
+}
+
+/// Entries of one remote directory to download together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Archive {
+    pub(crate) parent: String,
+    /// Names within `parent`; they become the top-level entries of the
+    /// archive.
+    pub(crate) names: Vec<String>,
+}
+
+impl Archive {
+    /// The archive's file name, without the extension: the entry's own name
+    /// for a single entry, and the directory's name for several.
+    fn stem(&self) -> String {
+        match self.names.as_slice() {
+            [name] => name.clone(),
+            _ => self
+                .parent
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .filter(|name| !name.is_empty() && *name != ".")
+                .unwrap_or("archive")
+                .to_string(),
+        }
+    }
+
+    /// The `tar` pipeline that writes the archive to standard output.
+    ///
+    /// A pipeline exits as its last command does, so with `zstd` tar's own
+    /// status is passed out through another descriptor and exited with;
+    /// a `zstd` failure exits with 2. `pipefail` would do, but not every
+    /// `sh` has it.
+    fn command(&self, format: ArchiveFormat) -> String {
+        let quote = |word: &str| shell_escape::unix::escape(word.into()).to_string();
+        let names = self
+            .names
+            .iter()
+            .map(|name| quote(name))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let parent = quote(&self.parent);
+        match format {
+            ArchiveFormat::Gzip => format!("tar -C {parent} -czf - -- {names}"),
+            ArchiveFormat::Zstd => format!(
+                "exec 4>&1; status=$({{ {{ tar -C {parent} -cf - -- {names}; echo $? >&3; }} \
+                 | zstd -q -c >&4; }} 3>&1) || exit 2; exit $status"
+            ),
+        }
+    }
+}
+
+/// Called with the number of bytes written so far.
+pub(crate) type OnProgress = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Downloads `archive` into `local_dir`, and returns where the archive was
+/// written along with anything left out of it, or `None` if an existing
+/// archive was kept.
+pub(crate) async fn download_archive(
+    session: &AsyncMutex<Session>,
+    archive: &Archive,
+    local_dir: &Path,
+    settings: TransferSettings,
+    limit: Limit,
+    conflicts: &ConflictResolver,
+    progress: OnProgress,
+) -> Result<Option<(PathBuf, Summary)>> {
+    let remote_format = remote_format(session, settings.archive).await;
+    // Only gzip can be made here.
+    let format = remote_format.unwrap_or(ArchiveFormat::Gzip);
+
+    let mut local = local_dir.join(format!("{}.{}", archive.stem(), format.extension()));
+    if let Ok(existing) = tokio::fs::metadata(&local).await {
+        let conflict = Conflict {
+            target: local.display().to_string(),
+            source: Stat::default(),
+            existing: Stat::from(&existing),
+        };
+        match conflicts.resolve(conflict).await? {
+            Resolution::Overwrite => {}
+            Resolution::Skip => {
+                info!(local = ?local.display(), "Skipped, already exists");
+                return Ok(None);
+            }
+            Resolution::KeepBoth => local = conflict::free_local_path(&local),
+        }
+    }
+    tokio::fs::create_dir_all(local_dir).await?;
+
+    let summary = match remote_format {
+        Some(format) => stream_remote(session, archive, format, &local, limit, progress).await,
+        None => {
+            let sftp = session.lock().await.sftp().await;
+            match sftp {
+                Ok(sftp) => {
+                    build_locally(Arc::new(sftp), archive, &local, settings, limit, progress).await
+                }
+                Err(error) => Err(error),
+            }
+        }
+    };
+    match summary {
+        Ok(summary) => {
+            info!(local = ?local.display(), remote = archive.parent, "Archive downloaded");
+            Ok(Some((local, summary)))
+        }
+        Err(error) => {
+            // Half an archive is worse than none.
+            let _ = tokio::fs::remove_file(&local).await;
+            Err(error)
+        }
+    }
+}
+
+/// The format the remote host can make an archive in, closest to `wanted`,
+/// or `None` if it cannot make one at all.
+async fn remote_format(
+    session: &AsyncMutex<Session>,
+    wanted: ArchiveFormat,
+) -> Option<ArchiveFormat> {
+    const PROBE: &str =
+        "for tool in tar gzip zstd; do command -v $tool >/dev/null 2>&1 && echo $tool; done";
+    let output = async {
+        let command = session.lock().await.exec(PROBE).await?;
+        command.output().await
+    }
+    .await;
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => {
+            warn!(%error, "Could not look for tar on the remote host");
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let tools = stdout.lines().map(str::trim).collect::<HashSet<_>>();
+    if !tools.contains("tar") {
+        info!("No tar on the remote host, building the archive locally");
+        return None;
+    }
+    match wanted {
+        ArchiveFormat::Zstd if tools.contains("zstd") => Some(ArchiveFormat::Zstd),
+        _ if tools.contains("gzip") => Some(ArchiveFormat::Gzip),
+        _ => None,
+    }
+}
+
+/// Has the remote `tar` write the archive, and streams it into `local`.
+async fn stream_remote(
+    session: &AsyncMutex<Session>,
+    archive: &Archive,
+    format: ArchiveFormat,
+    local: &Path,
+    limit: Limit,
+    progress: OnProgress,
+) -> Result<Summary> {
+    let command = archive.command(format);
+    info!(command, "Archiving on the remote host");
+    let remote = session.lock().await.exec(&command).await?;
+    let file = tokio::fs::File::create(local)
+        .await
+        .wrap_err_with(|| format!("could not create {}", local.display()))?;
+    let file = Throttled::new(file, limit);
+    let mut writer = Progress::new(file, progress);
+    let output = remote.stream_to(&mut writer).await?;
+    writer.inner.get_mut().sync_all().await?;
+
+    // GNU tar exits with 1 when files changed while being read, which still
+    // leaves a usable archive; the complaints are worth passing on either way.
+    let mut summary = Summary::default();
+    match output.status {
+        Some(0 | 1) => {}
+        status => bail!(
+            "remote tar failed ({}): {}",
+            status.map_or("killed".to_string(), |status| format!(
+                "exit status {status}"
+            )),
+            output.stderr()
+        ),
+    }
+    for line in output.stderr().lines().filter(|line| !line.is_empty()) {
+        summary.skip("", line);
+    }
+    summary.done = archive.names.len();
+    Ok(summary)
+}
+
+/// Walks each entry over SFTP and writes a `.tar.gz` of it into `local`.
+async fn build_locally(
+    sftp: Arc<SftpSession>,
+    archive: &Archive,
+    local: &Path,
+    settings: TransferSettings,
+    limit: Limit,
+    progress: OnProgress,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut entries = Vec::new();
+    for name in &archive.names {
+        let root = format!("{}/{}", archive.parent.trim_end_matches('/'), name);
+        let walker = WalkParallel {
+            filter: Arc::new(|_| true),
+            path: root.into(),
+            max_depth: None,
+            min_depth: None,
+            threads: 4,
+            sftp: Arc::clone(&sftp),
+            follow_links: settings.symlinks == SymlinkPolicy::Follow,
+        };
+        let (found, errors) = walker.collect().await;
+        entries.extend(found);
+        for error in errors {
+            summary.skip("", error);
+        }
+    }
+    // A directory sorts before everything in it, so it is extracted first.
+    entries.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let parent = archive.parent.trim_end_matches('/').to_string();
+    let file = tokio::fs::File::create(local)
+        .await
+        .wrap_err_with(|| format!("could not create {}", local.display()))?
+        .into_std()
+        .await;
+    let handle = Handle::current();
+    // The tar writer is synchronous, so it runs on a thread of its own and
+    // waits on the SFTP reads from there.
+    tokio::task::spawn_blocking(move || {
+        let file = Throttled::new(file, limit);
+        let writer = Progress::new(file, progress);
+        let mut builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+        for entry in entries {
+            let path = entry.name().clone();
+            let relative = path
+                .strip_prefix(&parent)
+                .unwrap_or(&path)
+                .trim_start_matches('/')
+                .to_string();
+            match append(
+                &mut builder,
+                &handle,
+                &sftp,
+                &entry,
+                &relative,
+                settings.symlinks,
+            ) {
+                Ok(()) => summary.done += 1,
+                Err(error) => summary.skip(path, error),
+            }
+        }
+        let mut writer = builder.into_inner()?.finish()?;
+        writer.flush()?;
+        writer.inner.get_mut().sync_all()?;
+        Ok(summary)
+    })
+    .await?
+}
+
+/// Adds one walked entry to the archive. Entries left out on purpose come
+/// back as errors too, saying why, for the summary.
+fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    handle: &Handle,
+    sftp: &SftpSession,
+    entry: &FileEntry,
+    relative: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<()> {
+    let attributes = &entry.attributes;
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(attributes.permissions.unwrap_or(0o644) & 0o7777);
+    header.set_mtime(attributes.mtime.unwrap_or_default().into());
+    header.set_uid(attributes.uid.unwrap_or_default().into());
+    header.set_gid(attributes.gid.unwrap_or_default().into());
+
+    match entry.type_ {
+        FileType::Dir => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, relative, std::io::empty())?;
+        }
+        FileType::Symlink => match symlinks {
+            SymlinkPolicy::Preserve => {
+                let target = handle.block_on(sftp.read_link(entry.name().as_str()))?;
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, relative, target)?;
+            }
+            SymlinkPolicy::Follow => bail!("dangling symbolic link"),
+            SymlinkPolicy::Skip => bail!("symbolic link"),
+        },
+        _ => {
+            if let Some(kind) = special_kind(attributes) {
+                bail!(kind);
+            }
+            let file = handle.block_on(sftp.open(entry.name().as_str()))?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(attributes.size.unwrap_or_default());
+            let reader = BlockingReader {
+                inner: file,
+                handle,
+            };
+            builder.append_data(&mut header, relative, reader)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads an async reader from a blocking thread.
+struct BlockingReader<'a, R> {
+    inner: R,
+    handle: &'a Handle,
+}
+
+impl<R: AsyncRead + Unpin> Read for BlockingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.handle.block_on(self.inner.read(buf))
+    }
+}
+
+/// Counts the bytes written through it, reporting every mebibyte or so.
+struct Progress<W> {
+    inner: W,
+    written: u64,
+    reported: u64,
+    on_progress: OnProgress,
+}
+
+impl<W> Progress<W> {
+    const STEP: u64 = 1 << 20;
+
+    fn new(inner: W, on_progress: OnProgress) -> Self {
+        Self {
+            inner,
+            written: 0,
+            reported: 0,
+            on_progress,
+        }
+    }
+
+    fn count(&mut self, written: usize) {
+        self.written += written as u64;
+        if self.written - self.reported >= Self::STEP {
+            self.reported = self.written;
+            (self.on_progress)(self.written);
+        }
+    }
+}
+
+impl<W: Write> Write for Progress<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Progress<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.count(written);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(parent: &str, names: &[&str]) -> Archive {
+        Archive {
+            parent: parent.to_string(),
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn archives_are_named_after_what_they_hold() {
+        assert_eq!(archive("/srv/www", &["site"]).stem(), "site");
+        assert_eq!(archive("/srv/www/", &["a", "b"]).stem(), "www");
+        assert_eq!(archive("/", &["etc", "var"]).stem(), "archive");
+    }
+
+    #[test]
+    fn remote_command_quotes_every_path() {
+        let archive = archive("/srv/my files", &["-rf", "it's"]);
+        assert_eq!(
+            archive.command(ArchiveFormat::Gzip),
+            "tar -C '/srv/my files' -czf - -- -rf 'it'\\''s'"
+        );
+        assert_eq!(
+            archive.command(ArchiveFormat::Zstd),
+            "exec 4>&1; status=$({ { tar -C '/srv/my files' -cf - -- -rf 'it'\\''s'; echo $? >&3; } \
+             | zstd -q -c >&4; } 3>&1) || exit 2; exit $status"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Changing permission bits through SFTP `setstat`.
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+use tracing::info;
+
+use super::Summary;
+use super::journal::Change;
+
+/// The execute bits for user, group and other.
+const EXECUTE: u32 = 0o111;
+
+/// The permission bits a chmod gives an entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ModeSpec {
+    /// Bits set outright, out of `0o7777`.
+    pub(crate) bits: u32,
+    /// Execute bits set only on directories and on files that already have
+    /// an execute bit, like the `X` of `chmod u=rwX`.
+    pub(crate) keep_x: u32,
+}
+
+impl ModeSpec {
+    pub(crate) fn new(bits: u32) -> Self {
+        Self {
+            bits: bits & 0o7777,
+            keep_x: 0,
+        }
+    }
+
+    /// The mode an entry currently at `current` ends up with.
+    pub(crate) fn apply(&self, current: u32, is_dir: bool) -> u32 {
+        if is_dir || current & EXECUTE != 0 {
+            self.bits | self.keep_x
+        } else {
+            self.bits
+        }
+    }
+}
+
+/// Reads a mode such as `755` or `2775`.
+pub(crate) fn parse_octal(text: &str) -> Result<u32> {
+    let text = text.trim();
+    if text.is_empty() || text.len() > 4 || !text.chars().all(|c| ('0'..='7').contains(&c)) {
+        bail!("{text:?} is not an octal mode such as 644 or 2775");
+    }
+    Ok(u32::from_str_radix(text, 8)?)
+}
+
+/// An entry a chmod starts from.
+#[derive(Debug, Clone)]
+pub(crate) struct Target {
+    pub(crate) path: String,
+    /// The permission bits it has now, if the server said.
+    pub(crate) mode: Option<u32>,
+    pub(crate) is_dir: bool,
+}
+
+/// Gives each of `targets` the mode `files` describes. With `dirs`, the
+/// trees below directories are changed too, directories to `dirs` and
+/// everything else to `files`. Links inside a tree are left alone, as
+/// `setstat` would change what they point to. Each mode changed is
+/// returned with the one it replaced, or `None` in place of them all if the
+/// mode some entry had is not known, and the change cannot be taken back.
+pub(crate) async fn chmod(
+    sftp: &SftpSession,
+    targets: &[Target],
+    files: ModeSpec,
+    dirs: Option<ModeSpec>,
+) -> Result<(Summary, Option<Vec<Change>>)> {
+    let mut summary = Summary::default();
+    let mut changes = Some(Vec::new());
+    for target in targets {
+        let spec = match dirs {
+            Some(dirs) if target.is_dir => dirs,
+            _ => files,
+        };
+        let mode = spec.apply(target.mode.unwrap_or_default(), target.is_dir);
+        set_mode(
+            sftp,
+            &target.path,
+            mode,
+            target.mode,
+            &mut summary,
+            &mut changes,
+        )
+        .await;
+        let Some(dirs) = dirs.filter(|_| target.is_dir) else {
+            continue;
+        };
+        let mut stack = vec![target.path.clone()];
+        while let Some(dir) = stack.pop() {
+            let entries = match sftp.read_dir(dir.as_str()).await {
+                Ok(entries) => entries,
+                Err(error) => {
+                    summary.skip(dir, error);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let attributes = entry.metadata();
+                if attributes.is_symlink() {
+                    continue;
+                }
+                let path = format!("{}/{}", dir.trim_end_matches('/'), entry.file_name());
+                let current = attributes.permissions;
+                let mode = if attributes.is_dir() {
+                    stack.push(path.clone());
+                    dirs.apply(current.unwrap_or_default(), true)
+                } else {
+                    files.apply(current.unwrap_or_default(), false)
+                };
+                set_mode(sftp, &path, mode, current, &mut summary, &mut changes).await;
+            }
+        }
+    }
+    Ok((summary, changes))
+}
+
+/// Gives `path` the `mode`, noting it in `changes` along with the `old` one,
+/// or forgetting them all if that is not known.
+async fn set_mode(
+    sftp: &SftpSession,
+    path: &str,
+    mode: u32,
+    old: Option<u32>,
+    summary: &mut Summary,
+    changes: &mut Option<Vec<Change>>,
+) {
+    let mut attributes = FileAttributes::empty();
+    attributes.permissions = Some(mode);
+    info!(path, mode = format!("{mode:04o}"), "Changing mode");
+    match sftp.set_metadata(path, attributes).await {
+        Ok(()) => {
+            summary.done += 1;
+            match (changes.as_mut(), old) {
+                (Some(changes), Some(old)) => changes.push(Change::SetMode {
+                    path: path.to_string(),
+                    mode,
+                    old,
+                }),
+                _ => *changes = None,
+            }
+        }
+        Err(error) => summary.skip(path, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capital_x_only_executes_directories_and_executables() {
+        let spec = ModeSpec {
+            bits: 0o644,
+            keep_x: 0o111,
+        };
+        assert_eq!(spec.apply(0o600, false), 0o644);
+        assert_eq!(spec.apply(0o700, false), 0o755);
+        assert_eq!(spec.apply(0o700, true), 0o755);
+        assert_eq!(ModeSpec::new(0o104755).apply(0o644, false), 0o4755);
+    }
+
+    #[test]
+    fn octal_modes_are_parsed() {
+        assert_eq!(parse_octal("755").unwrap(), 0o755);
+        assert_eq!(parse_octal(" 2775").unwrap(), 0o2775);
+        assert!(parse_octal("").is_err());
+        assert!(parse_octal("789").is_err());
+        assert!(parse_octal("07777").is_err());
+    }
+}
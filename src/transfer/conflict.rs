@@ -0,0 +1,253 @@
+//! What to do when a transfer would land on something that already exists.
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{Result, bail};
+use futures::future::BoxFuture;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// The `conflict` key of the `[transfer]` section.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ConflictPolicy {
+    /// Prompt for every conflict, offering to apply the answer to the rest.
+    #[default]
+    Ask,
+    Overwrite,
+    Skip,
+    /// Write next to the existing entry under a numbered name.
+    KeepBoth,
+    /// Overwrite only if the source was modified after the target.
+    OverwriteIfNewer,
+    /// Overwrite only if the source and the target differ in size.
+    OverwriteIfSizeDiffers,
+}
+
+/// What a conflict comes down to once the policy has been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    Overwrite,
+    Skip,
+    KeepBoth,
+}
+
+/// The size and modification time of one side of a conflict.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Stat {
+    pub(crate) size: Option<u64>,
+    pub(crate) mtime: Option<u32>,
+}
+
+impl From<&FileAttributes> for Stat {
+    fn from(attributes: &FileAttributes) -> Self {
+        Self {
+            size: attributes.size,
+            mtime: attributes.mtime,
+        }
+    }
+}
+
+impl From<&std::fs::Metadata> for Stat {
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        Stat::from(&FileAttributes::from(metadata))
+    }
+}
+
+/// A transfer target that already exists.
+#[derive(Debug, Clone)]
+pub(crate) struct Conflict {
+    /// The target, as it should be shown to the user.
+    pub(crate) target: String,
+    pub(crate) source: Stat,
+    pub(crate) existing: Stat,
+}
+
+/// An answer to a prompt: the policy to apply, and whether to apply it to
+/// every later conflict of the same operation too.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConflictChoice {
+    pub(crate) policy: ConflictPolicy,
+    pub(crate) apply_to_all: bool,
+}
+
+/// A conflict waiting on the user. Dropping every copy unanswered cancels the
+/// operation that raised it.
+#[derive(Debug, Clone)]
+pub(crate) struct ConflictPrompt {
+    pub(crate) conflict: Conflict,
+    pub(crate) reply: mpsc::Sender<ConflictChoice>,
+}
+
+/// Asks the user about a conflict, for [`ConflictPolicy::Ask`].
+pub(crate) type Ask =
+    Arc<dyn Fn(Conflict) -> BoxFuture<'static, Result<ConflictChoice>> + Send + Sync>;
+
+/// Applies a [`ConflictPolicy`] across all the conflicts of one operation,
+/// remembering an "apply to all" answer for the ones after it.
+pub(crate) struct ConflictResolver {
+    policy: Mutex<ConflictPolicy>,
+    ask: Option<Ask>,
+}
+
+impl ConflictResolver {
+    pub(crate) fn new(policy: ConflictPolicy, ask: Option<Ask>) -> Self {
+        Self {
+            policy: Mutex::new(policy),
+            ask,
+        }
+    }
+
+    pub(crate) async fn resolve(&self, conflict: Conflict) -> Result<Resolution> {
+        let policy = *self.policy.lock().unwrap_or_else(|p| p.into_inner());
+        let policy = match policy {
+            ConflictPolicy::Ask => {
+                let Some(ask) = &self.ask else {
+                    bail!("{} already exists", conflict.target);
+                };
+                let choice = ask(conflict.clone()).await?;
+                if choice.apply_to_all {
+                    *self.policy.lock().unwrap_or_else(|p| p.into_inner()) = choice.policy;
+                }
+                choice.policy
+            }
+            policy => policy,
+        };
+        Ok(policy.decide(&conflict))
+    }
+}
+
+impl ConflictPolicy {
+    fn decide(self, conflict: &Conflict) -> Resolution {
+        let Conflict {
+            source, existing, ..
+        } = conflict;
+        let overwrite_if = |condition: bool| {
+            if condition {
+                Resolution::Overwrite
+            } else {
+                Resolution::Skip
+            }
+        };
+        match self {
+            // A prompt answered with "ask" would ask again forever.
+            ConflictPolicy::Ask | ConflictPolicy::Skip => Resolution::Skip,
+            ConflictPolicy::Overwrite => Resolution::Overwrite,
+            ConflictPolicy::KeepBoth => Resolution::KeepBoth,
+            ConflictPolicy::OverwriteIfNewer => overwrite_if(source.mtime > existing.mtime),
+            ConflictPolicy::OverwriteIfSizeDiffers => overwrite_if(source.size != existing.size),
+        }
+    }
+}
+
+/// `name` with ` (n)` inserted before its extension: `file (1).txt`.
+/// A leading dot does not start an extension, so `.bashrc` becomes
+/// `.bashrc (1)`.
+pub(crate) fn numbered_name(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{} ({n}){}", &name[..dot], &name[dot..]),
+        _ => format!("{name} ({n})"),
+    }
+}
+
+/// The first numbered sibling of the local `path` that does not exist.
+pub(crate) fn free_local_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(numbered_name(&name, n)))
+        .find(|candidate| !candidate.exists())
+        .expect("an unbounded range")
+}
+
+/// The first numbered sibling of the remote `path` that does not exist.
+pub(crate) async fn free_remote_path(sftp: &SftpSession, path: &str) -> Result<String> {
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    for n in 1.. {
+        let candidate = if parent.is_empty() && !path.starts_with('/') {
+            numbered_name(name, n)
+        } else {
+            format!("{parent}/{}", numbered_name(name, n))
+        };
+        if !sftp.try_exists(candidate.as_str()).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("an unbounded range")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(source: (u64, u32), existing: (u64, u32)) -> Conflict {
+        Conflict {
+            target: "target".to_string(),
+            source: Stat {
+                size: Some(source.0),
+                mtime: Some(source.1),
+            },
+            existing: Stat {
+                size: Some(existing.0),
+                mtime: Some(existing.1),
+            },
+        }
+    }
+
+    #[test]
+    fn numbered_names_keep_the_extension() {
+        assert_eq!(numbered_name("file.txt", 1), "file (1).txt");
+        assert_eq!(numbered_name("Makefile", 2), "Makefile (2)");
+        assert_eq!(numbered_name(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(numbered_name("a.tar.gz", 3), "a.tar (3).gz");
+    }
+
+    #[test]
+    fn conditional_policies_compare_the_right_field() {
+        let newer = conflict((10, 200), (10, 100));
+        let older = conflict((10, 100), (10, 200));
+        let resized = conflict((11, 100), (10, 100));
+
+        assert_eq!(
+            ConflictPolicy::OverwriteIfNewer.decide(&newer),
+            Resolution::Overwrite
+        );
+        assert_eq!(
+            ConflictPolicy::OverwriteIfNewer.decide(&older),
+            Resolution::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::OverwriteIfSizeDiffers.decide(&newer),
+            Resolution::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::OverwriteIfSizeDiffers.decide(&resized),
+            Resolution::Overwrite
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_to_all_sticks_for_later_conflicts() {
+        let asked = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&asked);
+        let ask: Ask = Arc::new(move |_| {
+            *counter.lock().unwrap() += 1;
+            Box::pin(async {
+                Ok(ConflictChoice {
+                    policy: ConflictPolicy::KeepBoth,
+                    apply_to_all: true,
+                })
+            })
+        });
+        let resolver = ConflictResolver::new(ConflictPolicy::Ask, Some(ask));
+
+        for _ in 0..3 {
+            let resolution = resolver.resolve(conflict((1, 1), (1, 1))).await.unwrap();
+            assert_eq!(resolution, Resolution::KeepBoth);
+        }
+        assert_eq!(*asked.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,411 @@
+//! Copying files and directories on the remote host.
+//!
+//! The data stays on the server where it can: a file is copied by the SFTP
+//! server itself with the `copy-data` extension, or by `cp` run over exec, and
+//! is only streamed down and back up through filessh when neither works.
+use std::sync::Arc;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use russh_sftp::protocol::{FileAttributes, OpenFlags, Packet, StatusCode};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use super::conflict::{self, Conflict, ConflictResolver, Resolution, Stat};
+use super::{Summary, SymlinkPolicy, TransferSettings};
+use crate::files::FileEntry;
+use crate::par_dir_traversal::WalkParallel;
+use crate::ssh::Remote;
+
+/// The SFTP extension that has the server copy between two open handles.
+pub(crate) const COPY_DATA: &str = "copy-data";
+
+/// The body of a `copy-data` request. A length of 0 copies to the end of the
+/// file being read.
+#[derive(Serialize)]
+struct CopyData {
+    read_from_handle: String,
+    read_from_offset: u64,
+    read_data_length: u64,
+    write_to_handle: String,
+    write_to_offset: u64,
+}
+
+/// Makes copies on one host, choosing the cheapest way the host allows.
+pub(crate) struct Copier {
+    sftp: Arc<SftpSession>,
+    /// A session to send `copy-data` on, if the server offers it.
+    raw: Option<RawSftpSession>,
+    remote: Remote,
+    /// Cleared once `cp` turns out not to run, so that it is not tried again
+    /// for every file.
+    cp: bool,
+    settings: TransferSettings,
+}
+
+impl Copier {
+    pub(crate) fn new(
+        sftp: Arc<SftpSession>,
+        raw: Option<RawSftpSession>,
+        remote: Remote,
+        settings: TransferSettings,
+    ) -> Self {
+        Self {
+            sftp,
+            raw,
+            remote,
+            cp: true,
+            settings,
+        }
+    }
+
+    /// Copies the remote `source` to `dest`, or into `dest` if that is a
+    /// directory, and returns where the copy ended up, or `None` if an
+    /// existing file was kept. A copy onto itself gets a numbered name next to
+    /// the source: `file (1).txt`. Directories are only copied if `recursive`
+    /// is set.
+    pub(crate) async fn copy(
+        &mut self,
+        source: &str,
+        dest: &str,
+        recursive: bool,
+        conflicts: &ConflictResolver,
+    ) -> Result<Option<(String, Summary)>> {
+        let attributes = self.sftp.symlink_metadata(source).await?;
+        if attributes.is_dir() && !recursive {
+            bail!("{source} is a directory; add -r to copy it");
+        }
+        let mut dest = dest.to_string();
+        let mut existing = super::remote_metadata(&self.sftp, &dest).await?;
+        let into = target(source, &dest, existing.as_ref().is_some_and(|e| e.is_dir()));
+        if into != dest {
+            dest = into;
+            existing = super::remote_metadata(&self.sftp, &dest).await?;
+        }
+
+        if dest == source {
+            dest = conflict::free_remote_path(&self.sftp, &dest).await?;
+        } else if let Some(existing) = existing {
+            let conflict = Conflict {
+                target: dest.clone(),
+                source: Stat::from(&attributes),
+                existing: Stat::from(&existing),
+            };
+            match conflicts.resolve(conflict).await? {
+                Resolution::Overwrite if existing.is_dir() => {
+                    bail!("{dest} is a directory, and will not be replaced by a copy")
+                }
+                // A file is written over in place, but a directory cannot be.
+                Resolution::Overwrite if attributes.is_dir() => {
+                    self.sftp.remove_file(dest.as_str()).await?
+                }
+                Resolution::Overwrite => {}
+                Resolution::Skip => {
+                    info!(dest, "Skipped, already exists");
+                    return Ok(None);
+                }
+                Resolution::KeepBoth => {
+                    dest = conflict::free_remote_path(&self.sftp, &dest).await?
+                }
+            }
+        }
+        if inside(source, &dest) {
+            bail!("cannot copy {source} into itself");
+        }
+
+        let mut summary = Summary::default();
+        if attributes.is_dir() {
+            self.copy_tree(source, &dest, &mut summary).await?;
+        } else {
+            self.copy_entry(source, &dest, &attributes, &mut summary)
+                .await;
+        }
+        Ok(Some((dest, summary)))
+    }
+
+    /// Copies the directory `source` to `dest`, which does not exist yet.
+    async fn copy_tree(&mut self, source: &str, dest: &str, summary: &mut Summary) -> Result<()> {
+        let walker = WalkParallel {
+            filter: Arc::new(|_: &FileEntry| true),
+            path: source.into(),
+            max_depth: None,
+            min_depth: Some(1),
+            threads: 4,
+            sftp: Arc::clone(&self.sftp),
+            follow_links: self.settings.symlinks == SymlinkPolicy::Follow,
+        };
+        let (mut entries, errors) = walker.collect().await;
+        // Parents sort ahead of what is in them.
+        entries.sort_by(|a, b| a.name().cmp(b.name()));
+        let prefix = format!("{}/", source.trim_end_matches('/'));
+
+        // `cp` keeps links as links and copies pipes and devices, so it only
+        // does what is asked when there are none of the latter.
+        let specials = entries
+            .iter()
+            .any(|entry| super::special_kind(&entry.attributes).is_some());
+        if errors.is_empty()
+            && !specials
+            && self.settings.symlinks == SymlinkPolicy::Preserve
+            && self.cp(true, source, dest).await?
+        {
+            // Attributes go on as they do for a copy over SFTP, children
+            // first, and never through a link.
+            for entry in entries.iter().rev() {
+                if entry.attributes.is_symlink() {
+                    continue;
+                }
+                let relative = entry.name().strip_prefix(&prefix).unwrap_or(entry.name());
+                let target = format!("{}/{relative}", dest.trim_end_matches('/'));
+                if let Err(error) = self.apply_attributes(&target, &entry.attributes).await {
+                    summary.skip(entry.name().clone(), error);
+                }
+            }
+            let attributes = self.sftp.metadata(source).await?;
+            self.apply_attributes(dest, &attributes).await?;
+            summary.done += entries.len() + 1 - summary.skipped.len();
+            return Ok(());
+        }
+        for error in errors {
+            summary.skip("", error);
+        }
+
+        self.sftp.create_dir(dest).await?;
+        let mut dirs = vec![(source.to_string(), dest.to_string())];
+        for entry in entries {
+            let relative = entry.name().strip_prefix(&prefix).unwrap_or(entry.name());
+            let target = format!("{}/{relative}", dest.trim_end_matches('/'));
+            if entry.is_dir() {
+                match self.sftp.create_dir(target.as_str()).await {
+                    Ok(()) => dirs.push((entry.name().clone(), target)),
+                    Err(error) => summary.skip(entry.name().clone(), error),
+                }
+            } else {
+                self.copy_entry(entry.name(), &target, &entry.attributes, summary)
+                    .await;
+            }
+        }
+
+        // Children first, so that filling a directory does not bump the times
+        // just copied onto it.
+        for (source_dir, dest_dir) in dirs.iter().rev() {
+            match self.sftp.metadata(source_dir.as_str()).await {
+                Ok(attributes) => self.apply_attributes(dest_dir, &attributes).await?,
+                Err(error) => summary.skip(source_dir.clone(), error),
+            }
+        }
+        summary.done += dirs.len();
+        Ok(())
+    }
+
+    /// Copies one entry that is not a directory, following the symlink
+    /// policy, and notes the outcome in `summary`.
+    async fn copy_entry(
+        &mut self,
+        source: &str,
+        dest: &str,
+        attributes: &FileAttributes,
+        summary: &mut Summary,
+    ) {
+        let result = if attributes.is_symlink() {
+            match self.settings.symlinks {
+                SymlinkPolicy::Skip => {
+                    summary.skip(source, "symbolic link");
+                    return;
+                }
+                SymlinkPolicy::Preserve => match self.sftp.read_link(source).await {
+                    Ok(target) => super::create_remote_symlink(&self.sftp, dest, &target).await,
+                    Err(error) => Err(error.into()),
+                },
+                // A link still seen as one with `Follow` leads nowhere.
+                SymlinkPolicy::Follow => match self.sftp.metadata(source).await {
+                    Ok(attributes) if attributes.is_dir() => {
+                        summary.skip(source, "links to a directory, copy that instead");
+                        return;
+                    }
+                    Ok(attributes) => self.copy_file(source, dest, &attributes).await,
+                    Err(_) => {
+                        summary.skip(source, "dangling symbolic link");
+                        return;
+                    }
+                },
+            }
+        } else if let Some(kind) = super::special_kind(attributes) {
+            summary.skip(source, kind);
+            return;
+        } else {
+            self.copy_file(source, dest, attributes).await
+        };
+        match result {
+            Ok(()) => summary.done += 1,
+            Err(error) => summary.skip(source, error),
+        }
+    }
+
+    /// Copies the regular file `source` over or as `dest`.
+    async fn copy_file(
+        &mut self,
+        source: &str,
+        dest: &str,
+        attributes: &FileAttributes,
+    ) -> Result<()> {
+        if let Some(raw) = &self.raw {
+            match copy_data(raw, source, dest).await {
+                Ok(()) => return self.apply_attributes(dest, attributes).await,
+                Err(error) => debug!(source, %error, "copy-data failed, trying cp"),
+            }
+        }
+        if self.cp(false, source, dest).await? {
+            return self.apply_attributes(dest, attributes).await;
+        }
+        let mut reader = self.sftp.open(source).await?;
+        let mut writer = self
+            .sftp
+            .open_with_flags(
+                dest,
+                OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE,
+            )
+            .await?;
+        tokio::io::copy(&mut reader, &mut writer).await?;
+        tokio::io::AsyncWriteExt::shutdown(&mut writer).await?;
+        self.apply_attributes(dest, attributes).await
+    }
+
+    /// Copies with `cp` on the remote host, and returns whether it did: `false`
+    /// means `cp` cannot be run there, and the copy has to be made another way.
+    async fn cp(&mut self, recursive: bool, source: &str, dest: &str) -> Result<bool> {
+        if !self.cp {
+            return Ok(false);
+        }
+        let quote = |word: &str| shell_escape::unix::escape(word.into()).to_string();
+        // Only the data is copied here; the caller gives the copy its
+        // attributes, the same way whichever way it was made.
+        let flags = if recursive { "-RP" } else { "-P" };
+        let command = format!("cp {flags} -- {} {}", quote(source), quote(dest));
+        let output = match self.remote.exec(&command).await {
+            Ok(command) => command.output().await,
+            Err(error) => Err(error),
+        };
+        match output {
+            Ok(output) if output.status == Some(0) => Ok(true),
+            // Not found, or not runnable: a host with no shell access.
+            Ok(output) if matches!(output.status, Some(126 | 127) | None) => {
+                debug!(command, stderr = output.stderr(), "cp is not available");
+                self.cp = false;
+                Ok(false)
+            }
+            Ok(output) => Err(eyre!("cp failed: {}", output.stderr())),
+            Err(error) => {
+                debug!(command, %error, "cp could not be run");
+                self.cp = false;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Gives the copy `dest` the permissions of the source, and its times and
+    /// owner if the settings ask for them.
+    async fn apply_attributes(&self, dest: &str, source: &FileAttributes) -> Result<()> {
+        let mut attributes = FileAttributes::empty();
+        attributes.permissions = source.permissions.map(|mode| mode & 0o7777);
+        if self.settings.preserve {
+            attributes.atime = source.atime;
+            attributes.mtime = source.mtime;
+        }
+        self.sftp.set_metadata(dest, attributes).await?;
+        if self.settings.preserve_ownership {
+            let mut ownership = FileAttributes::empty();
+            ownership.uid = source.uid;
+            ownership.gid = source.gid;
+            if let Err(error) = self.sftp.set_metadata(dest, ownership).await {
+                warn!(dest, %error, "Could not preserve ownership");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a copy of `source` asked for at `dest` goes: into `dest` if that is
+/// a directory, unless it is the source itself, which is copied beside itself.
+fn target(source: &str, dest: &str, dest_is_dir: bool) -> String {
+    if dest_is_dir && dest != source {
+        let name = source.rsplit('/').next().unwrap_or(source);
+        format!("{}/{name}", dest.trim_end_matches('/'))
+    } else {
+        dest.to_string()
+    }
+}
+
+/// Whether `dest` lies below `source`, where a copy of it would never end.
+fn inside(source: &str, dest: &str) -> bool {
+    dest.starts_with(&format!("{}/", source.trim_end_matches('/')))
+}
+
+/// Has the server copy `source` to `dest` with the `copy-data` extension.
+async fn copy_data(raw: &RawSftpSession, source: &str, dest: &str) -> Result<()> {
+    let reader = raw
+        .open(source, OpenFlags::READ, FileAttributes::empty())
+        .await?
+        .handle;
+    let writer = match raw
+        .open(
+            dest,
+            OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE,
+            FileAttributes::empty(),
+        )
+        .await
+    {
+        Ok(writer) => writer.handle,
+        Err(error) => {
+            let _ = raw.close(reader).await;
+            return Err(error.into());
+        }
+    };
+    let request = CopyData {
+        read_from_handle: reader.clone(),
+        read_from_offset: 0,
+        read_data_length: 0,
+        write_to_handle: writer.clone(),
+        write_to_offset: 0,
+    };
+    let result = raw
+        .extended(COPY_DATA, russh_sftp::ser::to_bytes(&request)?.to_vec())
+        .await;
+    let _ = raw.close(reader).await;
+    raw.close(writer).await?;
+    match result? {
+        Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+        Packet::Status(status) => Err(SftpError::Status(status).into()),
+        _ => Err(SftpError::UnexpectedPacket.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_go_into_directories() {
+        assert_eq!(target("/srv/a.txt", "/tmp", true), "/tmp/a.txt");
+        assert_eq!(target("/srv/a.txt", "/tmp/", true), "/tmp/a.txt");
+        assert_eq!(target("/srv/a.txt", "/tmp/b.txt", false), "/tmp/b.txt");
+        assert_eq!(target("/srv/site", "/backup", true), "/backup/site");
+    }
+
+    #[test]
+    fn a_directory_copied_onto_itself_is_not_nested() {
+        // The copy keeps the source's name here, and is then given a free
+        // numbered one beside it rather than going inside.
+        assert_eq!(target("/srv/site", "/srv/site", true), "/srv/site");
+        assert_eq!(target("/srv/site", "/srv", true), "/srv/site");
+    }
+
+    #[test]
+    fn copies_into_themselves_are_caught() {
+        assert!(inside("/srv/site", "/srv/site/site"));
+        assert!(inside("/srv/site/", "/srv/site/a/b"));
+        assert!(!inside("/srv/site", "/srv/site"));
+        assert!(!inside("/srv/site", "/srv/site2/site"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Deleting remote entries: finding everything a deletion takes, to show
+//! before it is confirmed, and removing it with many requests in flight.
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::stream::{self, StreamExt};
+use russh_sftp::client::SftpSession;
+
+use super::{Summary, SymlinkPolicy};
+use crate::files::{FileEntry, JoinablePaths, human_readable_size};
+use crate::par_dir_traversal::WalkParallel;
+
+/// How many removals are sent before waiting for their answers.
+const IN_FLIGHT: usize = 16;
+
+/// The size of a deletion.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Preview {
+    pub(crate) files: usize,
+    pub(crate) dirs: usize,
+    pub(crate) bytes: u64,
+}
+
+impl Preview {
+    pub(crate) fn of(entries: &[FileEntry]) -> Self {
+        let mut preview = Self::default();
+        for entry in entries {
+            if entry.is_dir() {
+                preview.dirs += 1;
+            } else {
+                preview.files += 1;
+                preview.bytes += entry.attributes.size.unwrap_or_default();
+            }
+        }
+        preview
+    }
+}
+
+impl Display for Preview {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize, one: &str, many: &str| match count {
+            1 => format!("1 {one}"),
+            count => format!("{count} {many}"),
+        };
+        let files = plural(self.files, "file", "files");
+        match self.dirs {
+            0 => write!(f, "{files}")?,
+            dirs => write!(
+                f,
+                "{files} and {}",
+                plural(dirs, "directory", "directories")
+            )?,
+        }
+        write!(f, ", {}", human_readable_size(self.bytes))
+    }
+}
+
+/// Everything deleting `targets`, listed in `dir`, removes, each named by
+/// its full path, along with what could not be looked into or is left in
+/// place. Directories are walked in parallel; links in them are listed as
+/// links, or left in place with [`SymlinkPolicy::Skip`]. A link among the
+/// targets was chosen as itself, and is always removed.
+pub(crate) async fn plan(
+    sftp: &Arc<SftpSession>,
+    dir: &str,
+    targets: &[FileEntry],
+    symlinks: SymlinkPolicy,
+) -> (Vec<FileEntry>, Summary) {
+    let mut entries = Vec::new();
+    let mut summary = Summary::default();
+    for target in targets {
+        let path = dir.to_string().join(target.name());
+        if !target.is_dir() {
+            entries.push(FileEntry::from_file(
+                path,
+                *target.type_(),
+                target.attributes.clone(),
+            ));
+            continue;
+        }
+        let walker = WalkParallel {
+            filter: Arc::new(|_| true),
+            path: path.into(),
+            max_depth: None,
+            min_depth: None,
+            threads: 4,
+            sftp: Arc::clone(sftp),
+            follow_links: false,
+        };
+        let (found, errors) = walker.collect().await;
+        for entry in found {
+            if entry.is_symlink() && symlinks == SymlinkPolicy::Skip {
+                summary.skip(entry.name().clone(), "symbolic link, left in place");
+            } else {
+                entries.push(entry);
+            }
+        }
+        for error in errors {
+            summary.skip("", error);
+        }
+    }
+    (entries, summary)
+}
+
+/// Removes `entries`, as [`plan`] lists them: everything but directories
+/// first, then directories deepest first, so that each is empty by its
+/// turn. `on_removed` is given the count so far after each removal. Once
+/// `cancel` is set, nothing more is removed, and what is left is reported
+/// as skipped.
+pub(crate) async fn delete<F>(
+    sftp: &SftpSession,
+    entries: Vec<FileEntry>,
+    cancel: &AtomicBool,
+    on_removed: impl Fn(usize) -> F,
+) -> Summary
+where
+    F: Future<Output = ()>,
+{
+    let mut summary = Summary::default();
+    let (dirs, files): (Vec<_>, Vec<_>) = entries.into_iter().partition(FileEntry::is_dir);
+    let files = files
+        .into_iter()
+        .map(|file| file.name().clone())
+        .collect::<Vec<_>>();
+    let depth = |path: &String| path.matches('/').count();
+    let mut dirs = dirs
+        .into_iter()
+        .map(|dir| dir.name().clone())
+        .collect::<Vec<_>>();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(depth(dir)));
+
+    let mut left = 0;
+    let mut batches = vec![(files, false)];
+    batches.extend(
+        dirs.chunk_by(|a, b| depth(a) == depth(b))
+            .map(|level| (level.to_vec(), true)),
+    );
+    for (paths, are_dirs) in batches {
+        if cancel.load(Ordering::Relaxed) {
+            left += paths.len();
+            continue;
+        }
+        let mut removals = stream::iter(paths)
+            .map(|path| async move {
+                if cancel.load(Ordering::Relaxed) {
+                    return (path, None);
+                }
+                let removed = if are_dirs {
+                    sftp.remove_dir(path.as_str()).await
+                } else {
+                    sftp.remove_file(path.as_str()).await
+                };
+                (path, Some(removed))
+            })
+            .buffer_unordered(IN_FLIGHT);
+        while let Some((path, removed)) = removals.next().await {
+            match removed {
+                Some(Ok(())) => {
+                    summary.done += 1;
+                    on_removed(summary.done).await;
+                }
+                Some(Err(error)) => summary.skip(path, error),
+                None => left += 1,
+            }
+        }
+    }
+    if left > 0 {
+        summary.skip("", format!("cancelled, with {left} entries left in place"));
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_sftp::protocol::FileType;
+
+    #[test]
+    fn preview_counts_files_directories_and_bytes() {
+        let entries = [
+            FileEntry::sample("/srv/site", FileType::Dir, 4096, None),
+            FileEntry::sample("/srv/site/index.html", FileType::File, 1024, None),
+            FileEntry::sample("/srv/site/style.css", FileType::File, 1024, None),
+        ];
+        let preview = Preview::of(&entries);
+        assert_eq!(
+            preview,
+            Preview {
+                files: 2,
+                dirs: 1,
+                bytes: 2048,
+            }
+        );
+        assert_eq!(preview.to_string(), "2 files and 1 directory, 2.00 KB");
+        assert_eq!(Preview::of(&entries[1..2]).to_string(), "1 file, 1.00 KB");
+    }
+}
@@ -0,0 +1,107 @@
+//! Include and exclude patterns for recursive downloads.
+use color_eyre::eyre::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+
+/// The `[download]` section of the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct DownloadSettings {
+    /// Only files matching one of these are downloaded. Empty means all.
+    #[serde(default)]
+    pub(crate) include: Vec<String>,
+    /// Files and directories matching any of these are skipped; a skipped
+    /// directory is not walked at all.
+    #[serde(default)]
+    pub(crate) exclude: Vec<String>,
+    /// How many levels below the chosen directory to descend. Unset means
+    /// the whole tree.
+    #[serde(default)]
+    pub(crate) max_depth: Option<usize>,
+}
+
+/// Decides which entries of a tree a recursive download takes.
+///
+/// A pattern with a `/` in it is matched against the path relative to the
+/// root of the download, any other pattern against the entry's name alone,
+/// so `.git` and `*.log` match at any depth while `build/*.o` only matches
+/// directly under `build`. Includes only narrow down files: directories are
+/// always entered unless excluded, since what they hold may be included.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl PathFilter {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Pattern::new(pattern).wrap_err_with(|| format!("invalid pattern {pattern:?}"))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Whether the entry at `relative`, a path below the download root,
+    /// should be taken.
+    pub(crate) fn accepts(&self, relative: &str, is_dir: bool) -> bool {
+        let relative = relative.trim_start_matches('/');
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        let matches = |pattern: &Pattern| {
+            let subject = if pattern.as_str().contains('/') {
+                relative
+            } else {
+                name
+            };
+            pattern.matches_with(subject, MATCH_OPTIONS)
+        };
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+        let owned = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PathFilter::new(&owned(include), &owned(exclude)).unwrap()
+    }
+
+    #[test]
+    fn names_match_at_any_depth_and_paths_from_the_root() {
+        let filter = filter(&[], &["node_modules", ".git", "*.log", "build/*.o"]);
+
+        assert!(!filter.accepts("web/node_modules", true));
+        assert!(!filter.accepts("/.git", true));
+        assert!(!filter.accepts("logs/today.log", false));
+        assert!(!filter.accepts("build/main.o", false));
+        assert!(filter.accepts("src/build/main.o", false));
+        assert!(filter.accepts("src/main.rs", false));
+    }
+
+    #[test]
+    fn includes_narrow_files_but_not_directories() {
+        let filter = filter(&["*.rs"], &["target"]);
+
+        assert!(filter.accepts("src", true));
+        assert!(filter.accepts("src/main.rs", false));
+        assert!(!filter.accepts("README.md", false));
+        assert!(!filter.accepts("target", true));
+    }
+}
@@ -0,0 +1,253 @@
+//! Finding entries below a directory, as `find` does: by a name pattern, and
+//! by type, size and age.
+//!
+//! A query is written on one line, such as `*.log type:f size:+10M mtime:-7d`.
+//! The name is a glob matched against the last part of each path, or a
+//! regular expression between slashes, such as `/^access\.log\.\d+$/`.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Result, bail};
+use regex::Regex;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileType;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::Summary;
+use crate::files::FileEntry;
+use crate::par_dir_traversal::{WalkParallel, WalkState};
+
+/// How many directories are read at once.
+const THREADS: usize = 4;
+
+/// What names a query takes.
+#[derive(Debug)]
+enum Name {
+    Any,
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+/// A bound on a number, from a `+N` or `-N` predicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Over(u64),
+    Under(u64),
+}
+
+impl Bound {
+    fn parse(value: &str, unit: impl Fn(&str) -> Option<u64>, what: &str) -> Result<Self> {
+        let (over, amount) = match value.split_at_checked(1) {
+            Some(("+", amount)) => (true, amount),
+            Some(("-", amount)) => (false, amount),
+            _ => bail!("{what} needs + for more or - for less, such as {what}:+{value}"),
+        };
+        let digits = amount.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let (Ok(number), Some(unit)) = (digits.parse::<u64>(), unit(&amount[digits.len()..]))
+        else {
+            bail!("{what}:{value} is not a {what} this understands");
+        };
+        let number = number.saturating_mul(unit);
+        Ok(if over {
+            Self::Over(number)
+        } else {
+            Self::Under(number)
+        })
+    }
+
+    fn admits(self, value: u64) -> bool {
+        match self {
+            Self::Over(bound) => value > bound,
+            Self::Under(bound) => value < bound,
+        }
+    }
+}
+
+/// What to look for.
+#[derive(Debug)]
+pub(crate) struct Query {
+    name: Name,
+    type_: Option<FileType>,
+    size: Option<Bound>,
+    /// How long ago the entry was modified, in seconds.
+    age: Option<Bound>,
+}
+
+impl Query {
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut query = Self {
+            name: Name::Any,
+            type_: None,
+            size: None,
+            age: None,
+        };
+        let mut name = Vec::new();
+        for word in text.split_whitespace() {
+            match word.split_once(':') {
+                Some(("type", value)) => {
+                    query.type_ = Some(match value {
+                        "f" => FileType::File,
+                        "d" => FileType::Dir,
+                        "l" => FileType::Symlink,
+                        _ => bail!("type:{value} is not f, d or l"),
+                    });
+                }
+                Some(("size", value)) => query.size = Some(Bound::parse(value, size_unit, "size")?),
+                Some(("mtime", value)) => {
+                    query.age = Some(Bound::parse(value, age_unit, "mtime")?);
+                }
+                _ => name.push(word),
+            }
+        }
+        let name = name.join(" ");
+        query.name = match name
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            Some(expression) => Name::Regex(Regex::new(expression)?),
+            None if name.is_empty() => Name::Any,
+            None => Name::Glob(glob::Pattern::new(&name)?),
+        };
+        Ok(query)
+    }
+
+    /// Whether `entry`, named by its full path, is one looked for, at `now`
+    /// in seconds since the epoch.
+    pub(crate) fn matches(&self, entry: &FileEntry, now: u64) -> bool {
+        let name = entry.name.rsplit('/').next().unwrap_or(&entry.name);
+        let named = match &self.name {
+            Name::Any => true,
+            Name::Glob(pattern) => pattern.matches(name),
+            Name::Regex(expression) => expression.is_match(name),
+        };
+        let attributes = &entry.attributes;
+        named
+            && self.type_.is_none_or(|type_| entry.type_ == type_)
+            && self.size.is_none_or(|bound| {
+                !entry.is_dir() && bound.admits(attributes.size.unwrap_or_default())
+            })
+            && self.age.is_none_or(|bound| {
+                attributes
+                    .mtime
+                    .is_some_and(|mtime| bound.admits(now.saturating_sub(mtime.into())))
+            })
+    }
+}
+
+/// Bytes in a `K`, `M` or `G`, or none for bytes.
+fn size_unit(unit: &str) -> Option<u64> {
+    match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => Some(1),
+        "K" => Some(1 << 10),
+        "M" => Some(1 << 20),
+        "G" => Some(1 << 30),
+        _ => None,
+    }
+}
+
+/// Seconds in an `m`inute, `h`our, `d`ay or `w`eek; a bare number is days,
+/// as with `find -mtime`.
+fn age_unit(unit: &str) -> Option<u64> {
+    match unit {
+        "m" => Some(60),
+        "h" => Some(60 * 60),
+        "" | "d" => Some(24 * 60 * 60),
+        "w" => Some(7 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+/// Walks everything below `root`, sending each entry `query` matches to
+/// `found` as soon as it is seen, until the walk ends or `cancel` is set.
+/// Returns the directories that could not be read.
+pub(crate) async fn find(
+    sftp: Arc<SftpSession>,
+    root: &str,
+    query: Arc<Query>,
+    cancel: Arc<AtomicBool>,
+    found: UnboundedSender<FileEntry>,
+) -> Summary {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let walker = WalkParallel {
+        filter: Arc::new(|_: &FileEntry| true),
+        path: root.into(),
+        max_depth: None,
+        min_depth: Some(1),
+        threads: THREADS,
+        sftp,
+        follow_links: false,
+    };
+    let summary = Arc::new(std::sync::Mutex::new(Summary::default()));
+    let shared = Arc::clone(&summary);
+    walker
+        .run(move || {
+            let (query, cancel, found) = (Arc::clone(&query), Arc::clone(&cancel), found.clone());
+            let summary = Arc::clone(&shared);
+            Box::new(move |entry| {
+                if cancel.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                match entry {
+                    Ok(entry) if query.matches(&entry, now) => {
+                        // The receiver is gone once the results are closed.
+                        if found.send(entry).is_err() {
+                            return WalkState::Quit;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(error) => summary
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .skip("", error),
+                }
+                WalkState::Continue
+            })
+        })
+        .await;
+    std::mem::take(
+        &mut summary
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_types_sizes_and_ages_narrow_the_results() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        let log = FileEntry::sample(
+            "/var/log/app/access.log",
+            FileType::File,
+            20 << 20,
+            Some(now as u32 - day as u32),
+        );
+        let old = FileEntry::sample(
+            "/var/log/app/access.log.1",
+            FileType::File,
+            1 << 10,
+            Some(0),
+        );
+        let dir = FileEntry::sample("/var/log/app", FileType::Dir, 4096, Some(now as u32));
+
+        let query = Query::parse("*.log type:f size:+10M mtime:-7d").unwrap();
+        assert!(query.matches(&log, now));
+        assert!(!query.matches(&old, now));
+        assert!(!query.matches(&dir, now));
+
+        let query = Query::parse(r"/\.log\.\d+$/ mtime:+30").unwrap();
+        assert!(query.matches(&old, now));
+        assert!(!query.matches(&log, now));
+
+        assert!(Query::parse("type:d").unwrap().matches(&dir, now));
+        assert!(Query::parse("size:10M").is_err());
+        assert!(Query::parse("type:x").is_err());
+        assert!(Query::parse("mtime:-3y").is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! A record of past transfers, kept in `history.jsonl` in the data directory
+//! as one JSON object per line, oldest first.
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use color_eyre::Report;
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::Summary;
+use super::archive::Archive;
+use crate::files::JoinablePaths;
+use crate::logging::get_data_dir;
+
+/// How many of the latest transfers the history view shows.
+const SHOWN: usize = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Kind {
+    Download,
+    /// A directory downloaded as one compressed archive.
+    Archive,
+    Upload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    Done,
+    /// Finished, but left some entries out.
+    Partial,
+    Failed,
+}
+
+/// One transfer. Remote paths are absolute; for an archive, `destination` is
+/// the directory the archive was saved into, and `source` the one entry it
+/// holds, or the directory of the `names` it holds when there are several.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub(crate) kind: Kind,
+    pub(crate) host: String,
+    pub(crate) source: String,
+    pub(crate) destination: String,
+    pub(crate) is_dir: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) names: Vec<String>,
+    pub(crate) bytes: u64,
+    /// Seconds since the epoch.
+    pub(crate) started: u64,
+    pub(crate) duration_ms: u64,
+    pub(crate) outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl Record {
+    /// The local end of the transfer.
+    pub(crate) fn local_path(&self) -> &Path {
+        match self.kind {
+            Kind::Download | Kind::Archive => Path::new(&self.destination),
+            Kind::Upload => Path::new(&self.source),
+        }
+    }
+
+    /// What an archive transfer archived, as it was asked for.
+    pub(crate) fn archive(&self) -> Archive {
+        if !self.names.is_empty() {
+            return Archive {
+                parent: self.source.clone(),
+                names: self.names.clone(),
+            };
+        }
+        let (parent, name) = split_remote(&self.source);
+        Archive {
+            parent,
+            names: vec![name],
+        }
+    }
+}
+
+/// Splits a remote path into its parent and its last name.
+pub(crate) fn split_remote(path: &str) -> (String, String) {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/".to_string(), name.to_string()),
+        Some((parent, name)) => (parent.to_string(), name.to_string()),
+        None => (".".to_string(), path.to_string()),
+    }
+}
+
+/// A transfer under way, to be added to the history once it ends.
+pub(crate) struct Transfer {
+    record: Record,
+    timer: Instant,
+}
+
+impl Transfer {
+    pub(crate) fn start(
+        kind: Kind,
+        host: &str,
+        source: impl Into<String>,
+        destination: impl Into<String>,
+        is_dir: bool,
+    ) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            record: Record {
+                kind,
+                host: host.to_string(),
+                source: source.into(),
+                destination: destination.into(),
+                is_dir,
+                names: Vec::new(),
+                bytes: 0,
+                started,
+                duration_ms: 0,
+                outcome: Outcome::Done,
+                error: None,
+            },
+            timer: Instant::now(),
+        }
+    }
+
+    /// Notes how much was sent, and what was left out on the way.
+    pub(crate) fn finished(&mut self, bytes: u64, summary: &Summary) {
+        self.record.bytes = bytes;
+        if let Some(first) = summary.skipped.first() {
+            self.record.outcome = Outcome::Partial;
+            self.record.error = Some(match summary.skipped.len() {
+                1 if first.path.is_empty() => first.reason.clone(),
+                1 => format!("{}: {}", first.path, first.reason),
+                skipped => format!("{skipped} entries left out"),
+            });
+        }
+    }
+
+    /// Starts an archive transfer of `archive`, to be saved into `destination`.
+    pub(crate) fn archive(host: &str, archive: &Archive, destination: impl Into<String>) -> Self {
+        let (source, names) = match archive.names.as_slice() {
+            [name] => (archive.parent.join(name), Vec::new()),
+            names => (archive.parent.clone(), names.to_vec()),
+        };
+        let mut transfer = Self::start(Kind::Archive, host, source, destination, true);
+        transfer.record.names = names;
+        transfer
+    }
+
+    /// Where the local end turned out to be, when it is only known at the end.
+    pub(crate) fn set_destination(&mut self, destination: impl Into<String>) {
+        self.record.destination = destination.into();
+    }
+
+    /// Adds the transfer to the history, as failed if `error` is given. Not
+    /// being able to write the history is logged rather than returned, as it
+    /// should not fail the transfer itself.
+    pub(crate) fn save(mut self, error: Option<&Report>) {
+        self.record.duration_ms = self.timer.elapsed().as_millis() as u64;
+        if let Some(error) = error {
+            self.record.outcome = Outcome::Failed;
+            self.record.error = Some(error.to_string());
+        }
+        if let Err(error) = append(&history_path(), &self.record) {
+            warn!(%error, "Could not record the transfer in the history");
+        }
+    }
+}
+
+pub(crate) fn history_path() -> PathBuf {
+    get_data_dir().join("history.jsonl")
+}
+
+fn append(path: &Path, record: &Record) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("could not open {}", path.display()))?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// The latest transfers, newest first. Lines that cannot be read, say from a
+/// newer version, are skipped.
+pub(crate) fn load() -> Result<Vec<Record>> {
+    load_from(&history_path())
+}
+
+fn load_from(path: &Path) -> Result<Vec<Record>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).wrap_err_with(|| format!("could not open {}", path.display()));
+        }
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(error) => warn!(%error, line, "Skipping an unreadable history entry"),
+        }
+    }
+    records.reverse();
+    records.truncate(SHOWN);
+    Ok(records)
+}
+
+/// Shows `path` in the desktop's file manager: selected in its folder where
+/// the platform allows, otherwise by opening the folder that holds it.
+pub(crate) fn reveal(path: &Path) -> Result<()> {
+    let folder = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("explorer");
+        command.arg(format!("/select,{}", path.display()));
+        command
+    } else {
+        let mut command = std::process::Command::new("xdg-open");
+        command.arg(folder);
+        command
+    };
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .wrap_err_with(|| format!("could not open a file manager at {}", folder.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_reads_back_newest_first() {
+        let path = std::env::temp_dir().join(format!("filessh-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(load_from(&path).unwrap().is_empty());
+
+        let mut first = Transfer::start(Kind::Download, "example.com", "/srv/a", "/tmp/a", false);
+        first.finished(10, &Summary::default());
+        let mut partial = Summary::default();
+        partial.skip("/srv/b/fifo", "named pipe");
+        let mut second = Transfer::start(Kind::Upload, "example.com", "/tmp/b", "/srv/b", true);
+        second.finished(20, &partial);
+        for transfer in [first, second] {
+            append(&path, &transfer.record).unwrap();
+        }
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+
+        let records = load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].kind, Kind::Upload);
+        assert_eq!(records[0].outcome, Outcome::Partial);
+        assert_eq!(records[0].error.as_deref(), Some("/srv/b/fifo: named pipe"));
+        assert_eq!(records[0].local_path(), Path::new("/tmp/b"));
+        assert_eq!(records[1].bytes, 10);
+        assert_eq!(records[1].outcome, Outcome::Done);
+        assert_eq!(records[1].local_path(), Path::new("/tmp/a"));
+    }
+
+    #[test]
+    fn archives_keep_every_name() {
+        let path =
+            std::env::temp_dir().join(format!("filessh-history-archive-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let several = Archive {
+            parent: "/srv".to_string(),
+            names: vec!["a".to_string(), "b c".to_string()],
+        };
+        let one = Archive {
+            parent: "/srv".to_string(),
+            names: vec!["a".to_string()],
+        };
+        for archive in [&several, &one] {
+            let transfer = Transfer::archive("example.com", archive, "/tmp");
+            append(&path, &transfer.record).unwrap();
+        }
+
+        let records = load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records[0].source, "/srv/a");
+        assert_eq!(records[0].archive(), one);
+        assert_eq!(records[1].archive(), several);
+    }
+}
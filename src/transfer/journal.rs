@@ -0,0 +1,352 @@
+//! A journal of what this session changed on the remote host, so that the
+//! last change can be undone, and what was undone redone.
+//!
+//! Each operation is recorded once it has gone through, as the changes it
+//! made along with what they replaced: undoing one makes the opposite
+//! changes, last first. Operations that destroy what they replace, such as
+//! deleting, are recorded too, only to say that they cannot be undone.
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use super::Summary;
+use super::save::{Backup, Content};
+
+/// How many operations are kept for undoing.
+const KEPT: usize = 100;
+
+/// The largest file whose content is kept, so that writing over it can be
+/// undone.
+pub(crate) const MAX_KEPT_CONTENT: u64 = 16 * 1024 * 1024;
+
+/// How much file content the journal keeps across all its operations. The
+/// oldest go once it holds more, though the last one is always kept.
+const KEPT_BYTES: usize = 64 * 1024 * 1024;
+
+/// What a created entry is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Kind {
+    File,
+    Dir,
+    /// A link, to the target given.
+    Symlink(String),
+}
+
+/// One change to one remote path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Change {
+    Rename {
+        from: String,
+        to: String,
+    },
+    Create {
+        path: String,
+        kind: Kind,
+    },
+    /// The opposite of creating: an empty file or directory, or a link, is
+    /// removed. One that has been filled since is left alone.
+    Remove {
+        path: String,
+        kind: Kind,
+    },
+    SetMode {
+        path: String,
+        mode: u32,
+        old: u32,
+    },
+    SetOwner {
+        path: String,
+        owner: (u32, u32),
+        old: (u32, u32),
+    },
+    Write {
+        path: String,
+        content: Vec<u8>,
+        old: Vec<u8>,
+    },
+}
+
+impl Change {
+    /// The change that takes this one back.
+    fn inverse(&self) -> Self {
+        match self.clone() {
+            Self::Rename { from, to } => Self::Rename { from: to, to: from },
+            Self::Create { path, kind } => Self::Remove { path, kind },
+            Self::Remove { path, kind } => Self::Create { path, kind },
+            Self::SetMode { path, mode, old } => Self::SetMode {
+                path,
+                mode: old,
+                old: mode,
+            },
+            Self::SetOwner { path, owner, old } => Self::SetOwner {
+                path,
+                owner: old,
+                old: owner,
+            },
+            Self::Write { path, content, old } => Self::Write {
+                path,
+                content: old,
+                old: content,
+            },
+        }
+    }
+
+    fn path(&self) -> &str {
+        match self {
+            Self::Rename { from: path, .. }
+            | Self::Create { path, .. }
+            | Self::Remove { path, .. }
+            | Self::SetMode { path, .. }
+            | Self::SetOwner { path, .. }
+            | Self::Write { path, .. } => path,
+        }
+    }
+
+    async fn apply(&self, sftp: &SftpSession) -> Result<()> {
+        match self {
+            Self::Rename { from, to } => {
+                if sftp.symlink_metadata(to.as_str()).await.is_ok() {
+                    bail!("{to} has been taken since");
+                }
+                sftp.rename(from.as_str(), to.as_str()).await?;
+            }
+            Self::Create { path, kind } => match kind {
+                Kind::File => {
+                    let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+                    sftp.open_with_flags(path.as_str(), flags)
+                        .await?
+                        .shutdown()
+                        .await?;
+                }
+                Kind::Dir => sftp.create_dir(path.as_str()).await?,
+                Kind::Symlink(target) => super::create_remote_symlink(sftp, path, target).await?,
+            },
+            Self::Remove { path, kind } => match kind {
+                Kind::File => {
+                    if sftp.metadata(path.as_str()).await?.size.unwrap_or_default() > 0 {
+                        bail!("it has been written to since it was created");
+                    }
+                    sftp.remove_file(path.as_str()).await?;
+                }
+                Kind::Dir => sftp.remove_dir(path.as_str()).await?,
+                Kind::Symlink(_) => sftp.remove_file(path.as_str()).await?,
+            },
+            Self::SetMode { path, mode, .. } => {
+                let mut attributes = FileAttributes::empty();
+                attributes.permissions = Some(*mode);
+                sftp.set_metadata(path.as_str(), attributes).await?;
+            }
+            Self::SetOwner { path, owner, .. } => {
+                let mut attributes = FileAttributes::empty();
+                (attributes.uid, attributes.gid) = (Some(owner.0), Some(owner.1));
+                sftp.set_metadata(path.as_str(), attributes).await?;
+            }
+            Self::Write { path, content, .. } => {
+                super::save::save(sftp, None, path, Content::Bytes(content), Backup::None).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One operation, as the journal keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    /// What the operation did, such as `Rename a.txt to b.txt`.
+    pub(crate) label: String,
+    changes: Vec<Change>,
+    /// Why the operation cannot be undone, for one that cannot.
+    irreversible: Option<String>,
+}
+
+impl Record {
+    pub(crate) fn new(label: impl Into<String>, changes: Vec<Change>) -> Self {
+        Self {
+            label: label.into(),
+            changes,
+            irreversible: None,
+        }
+    }
+
+    /// An operation that cannot be undone, and why.
+    pub(crate) fn irreversible(label: impl Into<String>, why: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            changes: Vec::new(),
+            irreversible: Some(why.into()),
+        }
+    }
+
+    /// Why this cannot be undone, if it cannot.
+    pub(crate) fn why_irreversible(&self) -> Option<&str> {
+        self.irreversible.as_deref()
+    }
+
+    /// The file content kept to undo or redo writes.
+    fn kept_bytes(&self) -> usize {
+        self.changes
+            .iter()
+            .map(|change| match change {
+                Change::Write { content, old, .. } => content.len() + old.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Takes the operation back, last change first.
+    pub(crate) async fn undo(&self, sftp: &SftpSession) -> Summary {
+        let inverse = self.changes.iter().rev().map(Change::inverse);
+        apply(sftp, inverse, "Undoing").await
+    }
+
+    /// Makes the operation again, after it was undone.
+    pub(crate) async fn redo(&self, sftp: &SftpSession) -> Summary {
+        apply(sftp, self.changes.iter().cloned(), "Redoing").await
+    }
+}
+
+async fn apply(sftp: &SftpSession, changes: impl Iterator<Item = Change>, doing: &str) -> Summary {
+    let mut summary = Summary::default();
+    for change in changes {
+        info!(path = change.path(), "{doing}");
+        match change.apply(sftp).await {
+            Ok(()) => summary.done += 1,
+            Err(error) => summary.skip(change.path(), error),
+        }
+    }
+    summary
+}
+
+/// The operations of this session, for undoing and redoing.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    done: Vec<Record>,
+    undone: Vec<Record>,
+}
+
+impl Journal {
+    /// Records an operation that has just gone through. What was undone can
+    /// no longer be redone after it.
+    pub(crate) fn record(&mut self, record: Record) {
+        self.undone.clear();
+        self.done.push(record);
+        if self.done.len() > KEPT {
+            self.done.remove(0);
+        }
+        let mut kept: usize = self.done.iter().map(Record::kept_bytes).sum();
+        while kept > KEPT_BYTES && self.done.len() > 1 {
+            kept -= self.done.remove(0).kept_bytes();
+        }
+    }
+
+    /// The last operation, left where it is.
+    pub(crate) fn last(&self) -> Option<&Record> {
+        self.done.last()
+    }
+
+    /// The last operation, taken off the journal to be undone. One that
+    /// cannot be undone stays, and is all there is to undo until something
+    /// else is done after it.
+    pub(crate) fn undo(&mut self) -> Option<Record> {
+        if self.done.last()?.irreversible.is_some() {
+            return None;
+        }
+        self.done.pop()
+    }
+
+    /// The last operation undone, taken off the journal to be redone.
+    pub(crate) fn redo(&mut self) -> Option<Record> {
+        self.undone.pop()
+    }
+
+    /// Puts back an operation that was undone, for redoing.
+    pub(crate) fn undone(&mut self, record: Record) {
+        self.undone.push(record);
+    }
+
+    /// Puts back an operation that was redone, for undoing again.
+    pub(crate) fn redone(&mut self, record: Record) {
+        self.done.push(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_taken_back_by_their_inverse_and_new_ones_end_redoing() {
+        let rename = Change::Rename {
+            from: "/srv/a".to_string(),
+            to: "/srv/b".to_string(),
+        };
+        let mode = Change::SetMode {
+            path: "/srv/b".to_string(),
+            mode: 0o755,
+            old: 0o644,
+        };
+        assert_eq!(rename.inverse().inverse(), rename);
+        assert_eq!(
+            mode.inverse(),
+            Change::SetMode {
+                path: "/srv/b".to_string(),
+                mode: 0o644,
+                old: 0o755,
+            }
+        );
+        assert_eq!(
+            Change::Create {
+                path: "/srv/c".to_string(),
+                kind: Kind::Dir,
+            }
+            .inverse(),
+            Change::Remove {
+                path: "/srv/c".to_string(),
+                kind: Kind::Dir,
+            }
+        );
+
+        let mut journal = Journal::default();
+        journal.record(Record::new("Rename a to b", vec![rename.clone()]));
+        journal.record(Record::irreversible("Delete c", "deleted for good"));
+        // The delete stays in the way of what came before it.
+        assert_eq!(journal.undo(), None);
+        assert_eq!(
+            journal.last().and_then(Record::why_irreversible),
+            Some("deleted for good")
+        );
+        journal.record(Record::new("Rename b to a", Vec::new()));
+        journal.undo().unwrap();
+        assert!(journal.undo().is_none());
+
+        let mut journal = Journal::default();
+        journal.record(Record::new("Rename a to b", vec![rename]));
+        let rename = journal.undo().unwrap();
+        journal.undone(rename.clone());
+        assert_eq!(journal.redo(), Some(rename.clone()));
+        journal.undone(rename);
+        journal.record(Record::new("Chmod b", vec![mode]));
+        assert_eq!(journal.redo(), None);
+    }
+
+    #[test]
+    fn old_writes_go_once_too_much_content_is_kept() {
+        let write = |label: &str| {
+            let change = Change::Write {
+                path: "/srv/big".to_string(),
+                content: vec![0; MAX_KEPT_CONTENT as usize],
+                old: vec![0; MAX_KEPT_CONTENT as usize],
+            };
+            Record::new(label, vec![change])
+        };
+        let mut journal = Journal::default();
+        for label in ["1", "2", "3"] {
+            journal.record(write(label));
+        }
+        journal.record(Record::new("Chmod", Vec::new()));
+        assert_eq!(journal.done.len(), 3);
+        assert_eq!(journal.done[0].label, "2");
+    }
+}
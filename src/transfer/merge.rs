@@ -0,0 +1,108 @@
+//! Merging an edit with changes made to the same file by someone else, and
+//! showing how the two differ, with `git` or else `diff3` and `diff` on this
+//! machine.
+use std::path::PathBuf;
+use std::process::Command;
+
+use color_eyre::eyre::{Result, bail};
+
+/// A three-way merge of `mine` and `theirs`, which both started from `base`.
+/// Where they change the same lines, both are kept between conflict markers,
+/// with the base in the middle, as `git merge-file --diff3` writes them.
+pub(crate) fn merge(base: &[u8], mine: &[u8], theirs: &[u8]) -> Result<Vec<u8>> {
+    let files = Scratch::new(&[("mine", mine), ("base", base), ("theirs", theirs)])?;
+    let [mine, base, theirs] = [&files.paths[0], &files.paths[1], &files.paths[2]];
+    let labels = ["-L", "yours", "-L", "opened", "-L", "on the server"];
+    let mut git = Command::new("git");
+    git.args(["merge-file", "-p", "--diff3"])
+        .args(labels)
+        .args([mine, base, theirs]);
+    let mut diff3 = Command::new("diff3");
+    diff3.arg("-m").args(labels).args([mine, base, theirs]);
+    // Conflicts are no error: git exits with how many there are, and diff3
+    // with 1 for any.
+    run([(git, 127), (diff3, 1)])
+}
+
+/// A unified diff from `theirs` to `mine`.
+pub(crate) fn diff(mine: &[u8], theirs: &[u8]) -> Result<Vec<u8>> {
+    let files = Scratch::new(&[("theirs", theirs), ("mine", mine)])?;
+    let [theirs, mine] = [&files.paths[0], &files.paths[1]];
+    let mut git = Command::new("git");
+    git.args(["diff", "--no-index", "--no-color", "--"])
+        .args([theirs, mine]);
+    let mut diff = Command::new("diff");
+    diff.args(["-u", "--label", "on the server", "--label", "yours"])
+        .args([theirs, mine]);
+    // Both exit with 1 when the files differ.
+    run([(git, 1), (diff, 1)])
+}
+
+/// Runs the first of `commands` that can be started, and returns what it
+/// printed, if it exited with a code no higher than the one given with it.
+fn run<const N: usize>(commands: [(Command, i32); N]) -> Result<Vec<u8>> {
+    for (mut command, highest) in commands {
+        let output = match command.output() {
+            Ok(output) => output,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error.into()),
+        };
+        match output.status.code() {
+            Some(code) if (0..=highest).contains(&code) => {
+                return Ok(output.stdout);
+            }
+            _ => bail!(
+                "{:?} failed: {}",
+                command.get_program(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+    }
+    bail!("merging and comparing need git, or diff3 and diff, on this machine")
+}
+
+/// Files in the local temporary directory, removed once dropped.
+struct Scratch {
+    paths: Vec<PathBuf>,
+}
+
+impl Scratch {
+    fn new(contents: &[(&str, &[u8])]) -> Result<Self> {
+        let stamp = chrono::Local::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default();
+        let mut scratch = Self { paths: Vec::new() };
+        for (name, content) in contents {
+            let path = std::env::temp_dir()
+                .join(format!("filessh-{}-{stamp:x}-{name}", std::process::id()));
+            std::fs::write(&path, content)?;
+            scratch.paths.push(path);
+        }
+        Ok(scratch)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs git or diff3, which are not dependencies"]
+    fn separate_changes_merge_and_shared_ones_conflict() {
+        let merged = merge(b"a\nb\nc\n", b"A\nb\nc\n", b"a\nb\nC\n").unwrap();
+        assert_eq!(merged, b"A\nb\nC\n");
+        let conflicted = String::from_utf8(merge(b"a\n", b"mine\n", b"theirs\n").unwrap()).unwrap();
+        assert!(conflicted.contains("<<<<<<< yours\nmine\n"));
+        assert!(conflicted.contains(">>>>>>> on the server\n"));
+        let diff = String::from_utf8(diff(b"a\nB\n", b"a\nb\n").unwrap()).unwrap();
+        assert!(diff.contains("-b\n+B\n"));
+    }
+}
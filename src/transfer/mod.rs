@@ -0,0 +1,631 @@
+//! Copying file contents between the remote host and this machine.
+//!
+//! Everything that reads a remote file into a local one, or the other way
+//! round, goes through here, so that options such as preserving metadata
+//! apply the same way to every path that moves bytes.
+use std::borrow::Cow;
+use std::fs::FileTimes;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use color_eyre::eyre::{Context, Result, bail};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use self::archive::ArchiveFormat;
+use self::conflict::{Conflict, ConflictPolicy, ConflictResolver, Resolution, Stat};
+use self::ratelimit::{Limit, Rate, Throttled};
+
+pub(crate) mod archive;
+pub(crate) mod chmod;
+pub(crate) mod conflict;
+pub(crate) mod copy;
+pub(crate) mod delete;
+pub(crate) mod filter;
+pub(crate) mod find;
+pub(crate) mod history;
+pub(crate) mod journal;
+pub(crate) mod merge;
+pub(crate) mod open;
+pub(crate) mod owners;
+pub(crate) mod prompt;
+pub(crate) mod ratelimit;
+pub(crate) mod rename;
+pub(crate) mod save;
+pub(crate) mod sync;
+pub(crate) mod trash;
+
+/// The `[transfer]` section of the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub(crate) struct TransferSettings {
+    /// Carry permissions, modification and access times across, like `scp -p`.
+    #[serde(default)]
+    pub(crate) preserve: bool,
+    /// Also carry the owner and group across. Only root can usually give a
+    /// file away, so this is best effort and only takes effect with `preserve`.
+    #[serde(default)]
+    pub(crate) preserve_ownership: bool,
+    /// What to do when the destination already exists.
+    #[serde(default)]
+    pub(crate) conflict: ConflictPolicy,
+    /// How recursive operations treat symbolic links inside the tree.
+    #[serde(default)]
+    pub(crate) symlinks: SymlinkPolicy,
+    /// What to compress archive downloads with.
+    #[serde(default)]
+    pub(crate) archive: ArchiveFormat,
+    /// The most all downloads together may take, or `None` for no limit.
+    #[serde(default)]
+    pub(crate) download_limit: Option<Rate>,
+    /// The most all uploads together may take, or `None` for no limit.
+    #[serde(default)]
+    pub(crate) upload_limit: Option<Rate>,
+}
+
+/// The `symlinks` key of the `[transfer]` section.
+///
+/// Deletion never goes through a link, so for it `Follow` and `Preserve`
+/// both remove the link itself.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum SymlinkPolicy {
+    /// Transfer what the link points to, entering linked directories unless
+    /// that would lead back into a directory already being walked.
+    Follow,
+    /// Recreate the link itself on the other side.
+    #[default]
+    Preserve,
+    /// Leave links out.
+    Skip,
+}
+
+/// An entry a recursive operation left out, and why.
+#[derive(Debug, Clone)]
+pub(crate) struct Skipped {
+    /// Empty where the reason already names the entry.
+    pub(crate) path: String,
+    pub(crate) reason: String,
+}
+
+/// What a recursive operation got through, and what it left out.
+#[derive(Debug, Default)]
+pub(crate) struct Summary {
+    pub(crate) done: usize,
+    /// Bytes written, for the operations that count them.
+    pub(crate) bytes: u64,
+    pub(crate) skipped: Vec<Skipped>,
+}
+
+impl Summary {
+    pub(crate) fn skip(&mut self, path: impl Into<String>, reason: impl ToString) {
+        let skipped = Skipped {
+            path: path.into(),
+            reason: reason.to_string(),
+        };
+        warn!(path = skipped.path, reason = skipped.reason, "Skipped");
+        self.skipped.push(skipped);
+    }
+
+    /// A message listing what `operation` left out, if it left anything out.
+    pub(crate) fn report(&self, operation: &str) -> Option<String> {
+        const SHOWN: usize = 20;
+        if self.skipped.is_empty() {
+            return None;
+        }
+        let mut report = format!(
+            "{operation} finished with {} done and {} skipped:",
+            self.done,
+            self.skipped.len()
+        );
+        for Skipped { path, reason } in self.skipped.iter().take(SHOWN) {
+            if path.is_empty() {
+                report.push_str(&format!("\n{reason}"));
+            } else {
+                report.push_str(&format!("\n{path}: {reason}"));
+            }
+        }
+        if self.skipped.len() > SHOWN {
+            report.push_str(&format!("\n...and {} more", self.skipped.len() - SHOWN));
+        }
+        Some(report)
+    }
+}
+
+/// What kind of special file the remote `attributes` describe, or `None` for
+/// files, directories and links. Reading a FIFO or a device can block
+/// forever, so recursive transfers leave these out.
+pub(crate) fn special_kind(attributes: &FileAttributes) -> Option<&'static str> {
+    const S_IFMT: u32 = 0o170000;
+    match attributes.permissions? & S_IFMT {
+        0o010000 => Some("named pipe"),
+        0o020000 => Some("character device"),
+        0o060000 => Some("block device"),
+        0o140000 => Some("socket"),
+        _ => None,
+    }
+}
+
+/// Like [`special_kind`], for a local file type.
+pub(crate) fn local_special_kind(file_type: &std::fs::FileType) -> Option<&'static str> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some("named pipe");
+        } else if file_type.is_char_device() {
+            return Some("character device");
+        } else if file_type.is_block_device() {
+            return Some("block device");
+        } else if file_type.is_socket() {
+            return Some("socket");
+        }
+    }
+    let _ = file_type;
+    None
+}
+
+/// Creates a remote symbolic link at `link` pointing to `target`.
+pub(crate) async fn create_remote_symlink(
+    sftp: &SftpSession,
+    link: &str,
+    target: &str,
+) -> Result<()> {
+    // OpenSSH's sftp-server has always read the two paths of SSH_FXP_SYMLINK
+    // in the opposite order to the draft, and every client follows it.
+    sftp.symlink(target, link).await?;
+    Ok(())
+}
+
+/// Creates a local symbolic link at `link` pointing to `target`.
+pub(crate) fn create_local_symlink(link: &Path, target: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        bail!(
+            "cannot create a symbolic link at {} on this platform",
+            link.display()
+        )
+    }
+}
+
+/// Copies the remote file `remote` to `local`, creating the local parent
+/// directories, and returns the number of bytes written, or `None` if an
+/// existing `local` was kept. `limit` is the transfer's, shared by every
+/// file it moves.
+pub(crate) async fn download_file(
+    sftp: &SftpSession,
+    remote: &str,
+    local: &Path,
+    settings: TransferSettings,
+    limit: &Limit,
+    conflicts: &ConflictResolver,
+) -> Result<Option<u64>> {
+    let mut remote_file = sftp
+        .open(remote)
+        .await
+        .wrap_err_with(|| format!("could not open {remote}"))?;
+    let mut local = Cow::Borrowed(local);
+    if let Ok(existing) = tokio::fs::metadata(&local).await {
+        let conflict = Conflict {
+            target: local.display().to_string(),
+            source: Stat::from(&remote_file.metadata().await?),
+            existing: Stat::from(&existing),
+        };
+        match conflicts.resolve(conflict).await? {
+            Resolution::Overwrite => {}
+            Resolution::Skip => {
+                info!(local = ?local.display(), "Skipped, already exists");
+                return Ok(None);
+            }
+            Resolution::KeepBoth => local = Cow::Owned(conflict::free_local_path(&local)),
+        }
+    }
+    let local = local.as_ref();
+    if let Some(parent) = local.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let local_file = tokio::fs::File::create(local)
+        .await
+        .wrap_err_with(|| format!("could not create {}", local.display()))?;
+    let mut local_file = Throttled::new(local_file, limit.clone());
+    let written = tokio::io::copy(&mut remote_file, &mut local_file).await?;
+    local_file.flush().await?;
+    local_file.get_mut().sync_all().await?;
+    drop(local_file);
+    info!(remote, local = ?local.display(), written, "Downloaded");
+
+    if settings.preserve {
+        let attributes = remote_file.metadata().await?;
+        apply_local_attributes(local, &attributes, settings)?;
+    }
+    Ok(Some(written))
+}
+
+/// Copies the local file `local` to `remote`, and returns the number of bytes
+/// written, or `None` if an existing `remote` was kept.
+pub(crate) async fn upload_file(
+    sftp: &SftpSession,
+    local: &Path,
+    remote: &str,
+    settings: TransferSettings,
+    limit: &Limit,
+    conflicts: &ConflictResolver,
+) -> Result<Option<u64>> {
+    let mut local_file = tokio::fs::File::open(local)
+        .await
+        .wrap_err_with(|| format!("could not open {}", local.display()))?;
+    let mut remote = Cow::Borrowed(remote);
+    if let Some(existing) = remote_metadata(sftp, &remote).await? {
+        let conflict = Conflict {
+            target: remote.to_string(),
+            source: Stat::from(&local_file.metadata().await?),
+            existing: Stat::from(&existing),
+        };
+        match conflicts.resolve(conflict).await? {
+            Resolution::Overwrite => {}
+            Resolution::Skip => {
+                info!(%remote, "Skipped, already exists");
+                return Ok(None);
+            }
+            Resolution::KeepBoth => {
+                remote = Cow::Owned(conflict::free_remote_path(sftp, &remote).await?);
+            }
+        }
+    }
+    let remote = remote.as_ref();
+    let remote_file = sftp
+        .create(remote)
+        .await
+        .wrap_err_with(|| format!("could not create {remote}"))?;
+    let mut remote_file = Throttled::new(remote_file, limit.clone());
+    let written = tokio::io::copy(&mut local_file, &mut remote_file).await?;
+    remote_file.flush().await?;
+    remote_file.shutdown().await?;
+    info!(local = ?local.display(), remote, written, "Uploaded");
+
+    if settings.preserve {
+        let metadata = local_file.metadata().await?;
+        apply_remote_attributes(sftp, remote, &metadata, settings).await?;
+    }
+    Ok(Some(written))
+}
+
+/// Renames the remote `old` to `new`, and returns where it ended up, or
+/// `None` if an existing `new` was kept.
+pub(crate) async fn move_entry(
+    sftp: &SftpSession,
+    old: &str,
+    new: &str,
+    conflicts: &ConflictResolver,
+) -> Result<Option<String>> {
+    if old == new {
+        return Ok(None);
+    }
+    let mut new = new.to_string();
+    // A link in the way is the entry in conflict, whatever it points to.
+    let existing = match sftp.symlink_metadata(new.as_str()).await {
+        Ok(attributes) => Some(attributes),
+        Err(russh_sftp::client::error::Error::Status(status))
+            if status.status_code == StatusCode::NoSuchFile =>
+        {
+            None
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if let Some(existing) = existing {
+        let conflict = Conflict {
+            target: new.clone(),
+            source: Stat::from(&sftp.symlink_metadata(old).await?),
+            existing: Stat::from(&existing),
+        };
+        match conflicts.resolve(conflict).await? {
+            Resolution::Overwrite if existing.is_dir() => {
+                bail!("{new} is a directory, and will not be replaced by a move")
+            }
+            // SFTP v3 renames refuse to replace the target, so clear it first.
+            Resolution::Overwrite => sftp.remove_file(new.as_str()).await?,
+            Resolution::Skip => {
+                info!(new, "Skipped, already exists");
+                return Ok(None);
+            }
+            Resolution::KeepBoth => new = conflict::free_remote_path(sftp, &new).await?,
+        }
+    }
+    sftp.rename(old, new.as_str()).await?;
+    Ok(Some(new))
+}
+
+/// The directory `path` is in, and its own name.
+pub(crate) fn split_parent(path: &str) -> (&str, &str) {
+    let path = match path.trim_end_matches('/') {
+        "" => path,
+        trimmed => trimmed,
+    };
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => (".", path),
+    }
+}
+
+/// The attributes of the remote `path`, or `None` if nothing is there.
+pub(crate) async fn remote_metadata(
+    sftp: &SftpSession,
+    path: &str,
+) -> Result<Option<FileAttributes>> {
+    match sftp.metadata(path).await {
+        Ok(attributes) => Ok(Some(attributes)),
+        Err(russh_sftp::client::error::Error::Status(status))
+            if status.status_code == StatusCode::NoSuchFile =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Recursively uploads the local directory `local` as `remote`. Directories
+/// are merged into existing ones; `conflicts` decides about the files, and
+/// `settings.symlinks` about links below `local`.
+pub(crate) async fn upload_dir(
+    sftp: &SftpSession,
+    local: &Path,
+    remote: &str,
+    settings: TransferSettings,
+    limit: &Limit,
+    conflicts: &ConflictResolver,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    // Directories are finished children first, so that writing into them
+    // does not bump the times just copied onto them.
+    let mut finished = Vec::new();
+    // Each directory is walked with its canonical path, to notice a followed
+    // link leading back into a directory that is being walked.
+    let real = tokio::fs::canonicalize(local).await?;
+    let mut stack = vec![(local.to_path_buf(), remote.to_string(), real)];
+
+    while let Some((local_dir, remote_dir, real_dir)) = stack.pop() {
+        if !sftp.try_exists(remote_dir.as_str()).await? {
+            sftp.create_dir(remote_dir.as_str()).await?;
+        }
+        let mut entries = tokio::fs::read_dir(&local_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            let shown = path.display().to_string();
+            let remote_child = format!("{}/{}", remote_dir.trim_end_matches('/'), name);
+            let mut file_type = entry.file_type().await?;
+            let mut real_child = real_dir.join(&name);
+
+            if file_type.is_symlink() {
+                match settings.symlinks {
+                    SymlinkPolicy::Skip => {
+                        summary.skip(shown, "symbolic link");
+                        continue;
+                    }
+                    SymlinkPolicy::Preserve => {
+                        let target = tokio::fs::read_link(&path).await?;
+                        match create_remote_symlink(sftp, &remote_child, &target.to_string_lossy())
+                            .await
+                        {
+                            Ok(()) => summary.done += 1,
+                            Err(error) => summary.skip(shown, error),
+                        }
+                        continue;
+                    }
+                    SymlinkPolicy::Follow => {
+                        let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                            summary.skip(shown, "dangling symbolic link");
+                            continue;
+                        };
+                        file_type = metadata.file_type();
+                        real_child = tokio::fs::canonicalize(&path).await?;
+                        if file_type.is_dir() && real_dir.starts_with(&real_child) {
+                            summary.skip(shown, "symbolic link loop");
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            if file_type.is_dir() {
+                stack.push((path, remote_child, real_child));
+            } else if let Some(kind) = local_special_kind(&file_type) {
+                summary.skip(shown, kind);
+            } else if let Some(written) =
+                upload_file(sftp, &path, &remote_child, settings, limit, conflicts).await?
+            {
+                summary.done += 1;
+                summary.bytes += written;
+            }
+        }
+        finished.push((local_dir, remote_dir));
+    }
+
+    if settings.preserve {
+        for (local_dir, remote_dir) in finished.iter().rev() {
+            let metadata = tokio::fs::metadata(local_dir).await?;
+            apply_remote_attributes(sftp, remote_dir, &metadata, settings).await?;
+        }
+    }
+    Ok(summary)
+}
+
+/// Removes the remote directory `root` and everything below it. Links are
+/// removed as links, never followed, unless `symlinks` says to skip them, in
+/// which case they and the directories holding them stay.
+pub(crate) async fn remove_dir_recursive(
+    sftp: &SftpSession,
+    root: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut stack = vec![root.to_string()];
+    // Directories in the order they were reached, to be removed in reverse
+    // once everything inside them is gone.
+    let mut dirs = Vec::new();
+
+    while let Some(path) = stack.pop() {
+        let entries = match sftp.read_dir(&path).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                summary.skip(path, err);
+                continue;
+            }
+        };
+        for entry in entries {
+            let attrs = entry.metadata();
+            let name = entry.file_name();
+            let child_path = format!("{}/{}", path, name);
+            if attrs.is_dir() {
+                // Push directory for later deletion
+                stack.push(child_path.clone());
+            } else if attrs.is_symlink() && symlinks == SymlinkPolicy::Skip {
+                summary.skip(child_path, "symbolic link, left in place");
+            } else {
+                match sftp.remove_file(&child_path).await {
+                    Ok(()) => summary.done += 1,
+                    Err(err) => summary.skip(child_path, err),
+                }
+            }
+        }
+        dirs.push(path);
+    }
+
+    for dir in dirs.into_iter().rev() {
+        match sftp.remove_dir(&dir).await {
+            Ok(()) => summary.done += 1,
+            Err(err) => summary.skip(dir, err),
+        }
+    }
+    Ok(summary)
+}
+
+/// Gives the local file at `path` the permissions, times and, if asked for,
+/// ownership that the remote side reported in `attributes`.
+pub(crate) fn apply_local_attributes(
+    path: &Path,
+    attributes: &FileAttributes,
+    settings: TransferSettings,
+) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = attributes.permissions {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+
+    let mut times = FileTimes::new();
+    if let Some(atime) = attributes.atime {
+        times = times.set_accessed(UNIX_EPOCH + Duration::from_secs(atime.into()));
+    }
+    if let Some(mtime) = attributes.mtime {
+        times = times.set_modified(UNIX_EPOCH + Duration::from_secs(mtime.into()));
+    }
+    // A read-only handle is enough to set times as the owner, and unlike a
+    // writable one it can be opened on directories too.
+    std::fs::File::open(path)?.set_times(times)?;
+
+    #[cfg(unix)]
+    if settings.preserve_ownership
+        && let Err(error) = std::os::unix::fs::chown(path, attributes.uid, attributes.gid)
+    {
+        warn!(path = ?path.display(), %error, "Could not preserve ownership");
+    }
+    Ok(())
+}
+
+/// Gives the remote file at `path` the permissions, times and, if asked for,
+/// ownership of the local file described by `metadata`.
+pub(crate) async fn apply_remote_attributes(
+    sftp: &SftpSession,
+    path: &str,
+    metadata: &std::fs::Metadata,
+    settings: TransferSettings,
+) -> Result<()> {
+    let local = FileAttributes::from(metadata);
+    let mut attributes = FileAttributes::empty();
+    attributes.permissions = local.permissions.map(|mode| mode & 0o7777);
+    attributes.atime = local.atime;
+    attributes.mtime = local.mtime;
+    sftp.set_metadata(path, attributes).await?;
+
+    if settings.preserve_ownership {
+        let mut ownership = FileAttributes::empty();
+        ownership.uid = local.uid;
+        ownership.gid = local.gid;
+        if let Err(error) = sftp.set_metadata(path, ownership).await {
+            warn!(path, %error, "Could not preserve ownership");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_files_are_told_apart_by_mode() {
+        let with_mode = |mode| {
+            let mut attributes = FileAttributes::empty();
+            attributes.permissions = Some(mode);
+            attributes
+        };
+        assert_eq!(special_kind(&with_mode(0o010644)), Some("named pipe"));
+        assert_eq!(special_kind(&with_mode(0o140755)), Some("socket"));
+        assert_eq!(special_kind(&with_mode(0o020660)), Some("character device"));
+        assert_eq!(special_kind(&with_mode(0o100644)), None);
+        assert_eq!(special_kind(&with_mode(0o040755)), None);
+        assert_eq!(special_kind(&with_mode(0o120777)), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_files_take_the_remote_mode_and_times() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let path = std::env::temp_dir().join(format!("filessh-attributes-{}", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        let mut attributes = FileAttributes::empty();
+        attributes.permissions = Some(0o100750);
+        (attributes.atime, attributes.mtime) = (Some(1_600_000_000), Some(1_500_000_000));
+
+        let applied = apply_local_attributes(&path, &attributes, TransferSettings::default());
+        let metadata = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        applied.unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o750);
+        assert_eq!(metadata.mtime(), 1_500_000_000);
+        assert_eq!(metadata.atime(), 1_600_000_000);
+    }
+
+    #[test]
+    fn report_lists_what_was_skipped() {
+        let mut summary = Summary::default();
+        assert_eq!(summary.report("Download"), None);
+
+        summary.done = 3;
+        summary.skip("/srv/fifo", "named pipe");
+        summary.skip("", "/srv/private: Permission denied");
+        assert_eq!(
+            summary.report("Download").unwrap(),
+            "Download finished with 3 done and 2 skipped:\n\
+             /srv/fifo: named pipe\n\
+             /srv/private: Permission denied"
+        );
+    }
+
+    #[test]
+    fn entries_are_named_from_their_parent() {
+        assert_eq!(split_parent("/srv/www/current"), ("/srv/www", "current"));
+        assert_eq!(split_parent("/srv/www/current/"), ("/srv/www", "current"));
+        assert_eq!(split_parent("/notes"), ("/", "notes"));
+        assert_eq!(split_parent("notes"), (".", "notes"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Opening remote files in local applications, through a per-host cache.
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::logging::{get_data_dir, project_directory};
+
+/// The `[open]` section of the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct OpenSettings {
+    /// Commands to open files with, keyed by extension (`pdf`), MIME type
+    /// (`image/png`) or MIME family (`image/*`), tried in that order. The
+    /// path replaces a `{}` argument, or is added at the end.
+    #[serde(default)]
+    pub(crate) handlers: BTreeMap<String, String>,
+    /// The command for anything `handlers` does not name. Unset means the
+    /// desktop's own choice, through `xdg-open` or `open`.
+    #[serde(default)]
+    pub(crate) default: Option<String>,
+}
+
+impl OpenSettings {
+    /// The command that opens `path`.
+    pub(crate) fn handler_for(&self, path: &Path) -> String {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let mime = extension.as_deref().and_then(mime_type);
+        let family = mime
+            .and_then(|mime| mime.split_once('/'))
+            .map(|(family, _)| format!("{family}/*"));
+        [extension.as_deref(), mime, family.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.handlers.get(key))
+            .or(self.default.as_ref())
+            .cloned()
+            .unwrap_or_else(|| system_opener().to_string())
+    }
+}
+
+fn system_opener() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    }
+}
+
+/// The MIME type usually meant by a file extension, for the common cases.
+fn mime_type(extension: &str) -> Option<&'static str> {
+    let mime = match extension {
+        "pdf" => "application/pdf",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "svg" => "image/svg+xml",
+        "ico" => "image/vnd.microsoft.icon",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "md" => "text/markdown",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Where the cached copy of the remote `path` on `host` lives. Only the
+/// normal components of `path` are kept, so it cannot lead out of the cache.
+pub(crate) fn cache_path(host: &str, path: &str) -> PathBuf {
+    let root = project_directory()
+        .map(|dirs| dirs.cache_dir().to_path_buf())
+        .unwrap_or_else(|| get_data_dir().join("cache"));
+    let mut cached = root.join(host.replace(['/', '\\', ':'], "_"));
+    for component in Path::new(path).components() {
+        if let Component::Normal(part) = component {
+            cached.push(part);
+        }
+    }
+    cached
+}
+
+/// A handler that exits sooner than this has only passed the file on, to an
+/// application that may still have it open.
+pub(crate) const HANDED_OFF: Duration = Duration::from_secs(3);
+
+/// How long a file passed on that way is still watched for without a save.
+pub(crate) const IDLE_WATCH: Duration = Duration::from_secs(30 * 60);
+
+/// Starts `command` on `path` without waiting for it, detached from the
+/// terminal the interface is drawn on. The handler is returned, to tell when
+/// it is done with the file.
+pub(crate) fn launch(command: &str, path: &Path) -> Result<tokio::process::Child> {
+    let mut words =
+        shell_words::split(command).wrap_err_with(|| format!("could not parse `{command}`"))?;
+    let shown = path.display().to_string();
+    if words.is_empty() {
+        bail!("the handler for {shown} is empty");
+    }
+    if words.iter().any(|word| word.contains("{}")) {
+        for word in &mut words {
+            *word = word.replace("{}", &shown);
+        }
+    } else {
+        words.push(shown);
+    }
+    tokio::process::Command::new(&words[0])
+        .args(&words[1..])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .wrap_err_with(|| format!("could not run `{command}`"))
+}
+
+/// Lets the owner write the cached copy at `path`, whatever mode it was given
+/// from the remote file, so that it can be edited and fetched over again.
+pub(crate) async fn keep_writable(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = tokio::fs::metadata(path).await?.permissions();
+        if permissions.mode() & 0o200 == 0 {
+            permissions.set_mode(permissions.mode() | 0o200);
+            tokio::fs::set_permissions(path, permissions).await?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = tokio::fs::metadata(path).await?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            tokio::fs::set_permissions(path, permissions).await?;
+        }
+    }
+    Ok(())
+}
+
+/// What a cached copy looked like when last checked, to notice saves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    /// `None` once the file is gone.
+    pub(crate) async fn of(path: &Path) -> Option<Self> {
+        let metadata = tokio::fs::metadata(path).await.ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handlers_go_from_extension_to_mime_type_to_default() {
+        let mut settings = OpenSettings::default();
+        let system = system_opener().to_string();
+        assert_eq!(settings.handler_for(Path::new("a.pdf")), system);
+
+        settings.default = Some("less".to_string());
+        settings
+            .handlers
+            .insert("image/*".to_string(), "feh".to_string());
+        settings
+            .handlers
+            .insert("image/svg+xml".to_string(), "inkscape".to_string());
+        settings
+            .handlers
+            .insert("png".to_string(), "gimp {}".to_string());
+        assert_eq!(settings.handler_for(Path::new("a.PNG")), "gimp {}");
+        assert_eq!(settings.handler_for(Path::new("a.svg")), "inkscape");
+        assert_eq!(settings.handler_for(Path::new("a.jpeg")), "feh");
+        assert_eq!(settings.handler_for(Path::new("a.pdf")), "less");
+        assert_eq!(settings.handler_for(Path::new("Makefile")), "less");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cached_copies_stay_writable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("filessh-open-{}", std::process::id()));
+        std::fs::write(&path, "x").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+        keep_writable(&path).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o644);
+    }
+
+    #[test]
+    fn cache_paths_stay_in_the_cache() {
+        let cached = cache_path("example.com", "/srv/../etc/./passwd");
+        assert!(cached.ends_with("example.com/srv/etc/passwd"));
+        assert!(!cached.components().any(|c| c == Component::ParentDir));
+    }
+}
@@ -0,0 +1,228 @@
+//! User and group names for the numeric ids SFTP reports, and changing who
+//! owns entries through `setstat`.
+//!
+//! SFTP v3 only carries a `uid` and `gid`, and the client library drops the
+//! `ls -l` style long names servers send with a listing, so names come from
+//! the remote account databases: `getent` run over exec, which also sees
+//! directory services such as LDAP, or else `/etc/passwd` and `/etc/group`
+//! read over SFTP.
+use std::collections::HashMap;
+
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::FileAttributes;
+use tracing::{info, warn};
+
+use super::Summary;
+use super::journal::Change;
+use crate::files::FileEntry;
+use crate::ssh::Remote;
+
+/// The remote host's user and group names, by id.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Owners {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Owners {
+    /// Reads the remote account databases. A host that allows neither way
+    /// gives no names, and ids are shown as they are.
+    pub(crate) async fn load(sftp: &SftpSession, remote: &Remote) -> Self {
+        let mut owners = Self::default();
+        for (database, names) in [("passwd", &mut owners.users), ("group", &mut owners.groups)] {
+            let text = match getent(remote, database).await {
+                Some(text) => text,
+                None => match sftp.read(format!("/etc/{database}")).await {
+                    Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                    Err(error) => {
+                        warn!(%error, database, "Could not read the remote account database");
+                        continue;
+                    }
+                },
+            };
+            *names = parse(&text);
+        }
+        info!(
+            users = owners.users.len(),
+            groups = owners.groups.len(),
+            "Loaded remote owner names"
+        );
+        owners
+    }
+
+    /// Fills in the names of the entry's owner and group, where known.
+    pub(crate) fn name(&self, entry: &mut FileEntry) {
+        let attributes = &mut entry.attributes;
+        if let Some(user) = attributes.uid.and_then(|uid| self.users.get(&uid)) {
+            attributes.user = Some(user.clone());
+        }
+        if let Some(group) = attributes.gid.and_then(|gid| self.groups.get(&gid)) {
+            attributes.group = Some(group.clone());
+        }
+    }
+
+    /// The id of a user given by name or number.
+    pub(crate) fn uid(&self, user: &str) -> Result<u32> {
+        find(&self.users, user, "user")
+    }
+
+    /// The id of a group given by name or number.
+    pub(crate) fn gid(&self, group: &str) -> Result<u32> {
+        find(&self.groups, group, "group")
+    }
+}
+
+/// The output of `getent DATABASE`, or `None` if it could not be run.
+async fn getent(remote: &Remote, database: &str) -> Option<String> {
+    let output = remote
+        .exec(&format!("getent {database}"))
+        .await
+        .ok()?
+        .output()
+        .await
+        .ok()?;
+    if output.status != Some(0) || output.stdout.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads `name:password:id:...` lines, as `passwd` and `group` both have.
+/// The first name given for an id wins, as it does for `ls`.
+fn parse(text: &str) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for line in text.lines() {
+        let mut fields = line.split(':');
+        let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if let Ok(id) = id.parse() {
+            names.entry(id).or_insert_with(|| name.to_string());
+        }
+    }
+    names
+}
+
+fn find(names: &HashMap<u32, String>, wanted: &str, kind: &str) -> Result<u32> {
+    if let Ok(id) = wanted.parse() {
+        return Ok(id);
+    }
+    match names.iter().find(|(_, name)| *name == wanted) {
+        Some((id, _)) => Ok(*id),
+        None => bail!("there is no {kind} named {wanted:?} on the remote host"),
+    }
+}
+
+/// Gives each of `paths` the owner `uid` and group `gid`, keeping whichever
+/// is not given. With `recursive`, everything below directories changes too,
+/// except what is behind links inside the tree. Each owner changed is
+/// returned with the one it replaced.
+pub(crate) async fn chown(
+    sftp: &SftpSession,
+    paths: &[String],
+    uid: Option<u32>,
+    gid: Option<u32>,
+    recursive: bool,
+) -> Result<(Summary, Vec<Change>)> {
+    let mut summary = Summary::default();
+    let mut changes = Vec::new();
+    for path in paths {
+        let attributes = match sftp.metadata(path.as_str()).await {
+            Ok(attributes) => attributes,
+            Err(error) => {
+                summary.skip(path.as_str(), error);
+                continue;
+            }
+        };
+        changes.extend(set_owner(sftp, path, &attributes, uid, gid, &mut summary).await);
+        if !recursive || !attributes.is_dir() {
+            continue;
+        }
+        let mut stack = vec![path.clone()];
+        while let Some(dir) = stack.pop() {
+            let entries = match sftp.read_dir(dir.as_str()).await {
+                Ok(entries) => entries,
+                Err(error) => {
+                    summary.skip(dir, error);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let attributes = entry.metadata();
+                if attributes.is_symlink() {
+                    continue;
+                }
+                let path = format!("{}/{}", dir.trim_end_matches('/'), entry.file_name());
+                if attributes.is_dir() {
+                    stack.push(path.clone());
+                }
+                changes.extend(set_owner(sftp, &path, &attributes, uid, gid, &mut summary).await);
+            }
+        }
+    }
+    Ok((summary, changes))
+}
+
+/// SFTP sets the owner and group together, so the one not being changed is
+/// sent back as it was.
+async fn set_owner(
+    sftp: &SftpSession,
+    path: &str,
+    current: &FileAttributes,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    summary: &mut Summary,
+) -> Option<Change> {
+    let (Some(old_uid), Some(old_gid)) = (current.uid, current.gid) else {
+        summary.skip(path, "the server did not report its owner");
+        return None;
+    };
+    let (uid, gid) = (uid.unwrap_or(old_uid), gid.unwrap_or(old_gid));
+    let mut attributes = FileAttributes::empty();
+    attributes.uid = Some(uid);
+    attributes.gid = Some(gid);
+    info!(path, uid, gid, "Changing owner");
+    match sftp.set_metadata(path, attributes).await {
+        Ok(()) => {
+            summary.done += 1;
+            Some(Change::SetOwner {
+                path: path.to_string(),
+                owner: (uid, gid),
+                old: (old_uid, old_gid),
+            })
+        }
+        Err(error) => {
+            summary.skip(path, error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_sftp::protocol::FileType;
+
+    #[test]
+    fn names_and_ids_resolve_both_ways() {
+        let owners = Owners {
+            users: parse(
+                "root:x:0:0:root:/root:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\ntoor:x:0:0::/:/bin/sh\n# comment\n",
+            ),
+            groups: parse("wheel:x:10:alice\nstaff:*:50:\n"),
+        };
+        assert_eq!(owners.uid("alice").unwrap(), 1000);
+        assert_eq!(owners.uid("1234").unwrap(), 1234);
+        assert!(owners.uid("bob").is_err());
+        assert_eq!(owners.gid("staff").unwrap(), 50);
+
+        let mut attributes = FileAttributes::empty();
+        attributes.uid = Some(0);
+        attributes.gid = Some(99);
+        let mut entry = FileEntry::from_file("a".to_string(), FileType::File, attributes);
+        owners.name(&mut entry);
+        assert_eq!(entry.attributes.user.as_deref(), Some("root"));
+        assert_eq!(entry.attributes.group, None);
+    }
+}
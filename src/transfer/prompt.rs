@@ -0,0 +1,356 @@
+//! Parsing what is typed into the transfer, sync, copy, owner and link
+//! prompts.
+use color_eyre::eyre::{Result, bail};
+
+use super::ratelimit::Rate;
+
+/// What was typed into a transfer prompt: a path followed by options,
+/// for example `~/backup -x node_modules -x '*.log' -l 2M`.
+///
+/// - `--include`/`-i PATTERN` and `--exclude`/`-x PATTERN` narrow down a
+///   directory download, and may be repeated.
+/// - `--limit`/`-l RATE` caps this transfer alone, on top of the shared
+///   limits.
+///
+/// The path need not be quoted when it comes before the options: words up
+/// to the first option are taken as one path, with single spaces between.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct TransferPrompt {
+    pub(crate) path: String,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) limit: Option<Rate>,
+}
+
+impl TransferPrompt {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let mut prompt = TransferPrompt::default();
+        let mut path = Vec::new();
+        let mut options = false;
+        let mut words = split_words(input)?.into_iter();
+        while let Some(word) = words.next() {
+            let word = word.as_str();
+            if !matches!(
+                word,
+                "-i" | "--include" | "-x" | "--exclude" | "-l" | "--limit"
+            ) {
+                if options {
+                    bail!("unexpected {word:?} after the options");
+                }
+                path.push(word.to_string());
+                continue;
+            }
+            options = true;
+            let Some(value) = words.next() else {
+                bail!("{word} needs a value");
+            };
+            match word {
+                "-i" | "--include" => prompt.include.push(value),
+                "-x" | "--exclude" => prompt.exclude.push(value),
+                _ => prompt.limit = Some(value.parse()?),
+            }
+        }
+        prompt.path = path.join(" ");
+        if prompt.path.is_empty() {
+            bail!("no path given");
+        }
+        Ok(prompt)
+    }
+
+    /// Rejects patterns, for prompts where there is no tree to filter.
+    pub(crate) fn without_patterns(self) -> Result<Self> {
+        if !self.include.is_empty() || !self.exclude.is_empty() {
+            bail!("--include and --exclude only apply to directory downloads");
+        }
+        Ok(self)
+    }
+}
+
+/// What was typed into the sync prompt: a local directory followed by
+/// options, for example `~/site --delete -x .git`.
+///
+/// - `--pull` makes the local directory match the remote one, instead of the
+///   other way round.
+/// - `--delete` removes what the other side has in excess.
+/// - `--dry-run`/`-n` only shows the plan.
+/// - `--checksum`/`-c` compares contents rather than times.
+/// - `--delta` sends changed files through `rsync` where it can.
+/// - `--include`/`-i` and `--exclude`/`-x` work as they do for downloads.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct SyncPrompt {
+    pub(crate) path: String,
+    pub(crate) pull: bool,
+    pub(crate) delete: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) checksum: bool,
+    pub(crate) delta: bool,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+}
+
+impl SyncPrompt {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let mut prompt = SyncPrompt::default();
+        let mut words = split_words(input)?.into_iter();
+        while let Some(word) = words.next() {
+            match word.as_str() {
+                "--pull" => prompt.pull = true,
+                "--delete" => prompt.delete = true,
+                "-n" | "--dry-run" => prompt.dry_run = true,
+                "-c" | "--checksum" => prompt.checksum = true,
+                "--delta" => prompt.delta = true,
+                flag @ ("-i" | "--include" | "-x" | "--exclude") => {
+                    let Some(value) = words.next() else {
+                        bail!("{flag} needs a value");
+                    };
+                    match flag {
+                        "-i" | "--include" => prompt.include.push(value),
+                        _ => prompt.exclude.push(value),
+                    }
+                }
+                flag if flag.starts_with('-') => bail!("unknown option {flag:?}"),
+                _ if !prompt.path.is_empty() => bail!("unexpected {word:?} after the path"),
+                _ => prompt.path = word,
+            }
+        }
+        if prompt.path.is_empty() {
+            bail!("no path given");
+        }
+        Ok(prompt)
+    }
+}
+
+/// What was typed into the copy prompt: where to copy to, and `-r` to copy
+/// a directory with everything in it. No destination, or the directory the
+/// source is in, copies it alongside under a numbered name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CopyPrompt {
+    pub(crate) path: Option<String>,
+    pub(crate) recursive: bool,
+}
+
+impl CopyPrompt {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let mut prompt = CopyPrompt::default();
+        for word in split_words(input)? {
+            match word.as_str() {
+                "-r" | "-R" | "--recursive" => prompt.recursive = true,
+                flag if flag.starts_with('-') => bail!("unknown option {flag:?}"),
+                _ if prompt.path.is_some() => bail!("unexpected {word:?} after the path"),
+                _ => prompt.path = Some(word),
+            }
+        }
+        Ok(prompt)
+    }
+}
+
+/// What was typed into the owner prompt: `USER`, `USER:GROUP` or `:GROUP`,
+/// by name or id, and `-R` to change everything below directories too.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct OwnerPrompt {
+    pub(crate) user: Option<String>,
+    pub(crate) group: Option<String>,
+    pub(crate) recursive: bool,
+}
+
+impl OwnerPrompt {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let mut prompt = OwnerPrompt::default();
+        let mut owner = None;
+        for word in split_words(input)? {
+            match word.as_str() {
+                "-r" | "-R" | "--recursive" => prompt.recursive = true,
+                flag if flag.starts_with('-') => bail!("unknown option {flag:?}"),
+                _ if owner.is_some() => bail!("unexpected {word:?} after the owner"),
+                _ => owner = Some(word),
+            }
+        }
+        let Some(owner) = owner else {
+            bail!("no owner given");
+        };
+        let (user, group) = match owner.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (owner.as_str(), None),
+        };
+        prompt.user = Some(user.to_string()).filter(|user| !user.is_empty());
+        prompt.group = group.filter(|group| !group.is_empty()).map(str::to_string);
+        if prompt.user.is_none() && prompt.group.is_none() {
+            bail!("no owner given");
+        }
+        Ok(prompt)
+    }
+}
+
+/// What was typed into the symlink prompt: the target the link points to,
+/// then the link's name, as for `ln -s`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct LinkPrompt {
+    pub(crate) target: String,
+    pub(crate) name: String,
+}
+
+impl LinkPrompt {
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        match <[String; 2]>::try_from(split_words(input)?) {
+            Ok([target, name]) => Ok(LinkPrompt { target, name }),
+            Err(_) => bail!("give the link's target, then its name"),
+        }
+    }
+}
+
+/// Splits `input` into words the way a shell would, so that paths with
+/// spaces and patterns can be quoted: `'my files/*.log'`, `"a b"` or `a\ b`.
+/// A backslash only escapes a space, a quote or another backslash, so that
+/// Windows paths such as `C:\Users\me` come through as typed.
+fn split_words(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' | '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(inner) => word.push(inner),
+                        None => bail!("unterminated {c} in {input:?}"),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars
+                    .next_if(|&next| next.is_whitespace() || matches!(next, '\'' | '"' | '\\'));
+                word.get_or_insert_default().push(escaped.unwrap_or(c));
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_takes_a_path_and_options() {
+        let prompt = TransferPrompt::parse(
+            "~/backup -x node_modules --exclude '*.log' -i \"*.rs\" --limit 2M",
+        )
+        .unwrap();
+        assert_eq!(
+            prompt,
+            TransferPrompt {
+                path: "~/backup".to_string(),
+                include: vec!["*.rs".to_string()],
+                exclude: vec!["node_modules".to_string(), "*.log".to_string()],
+                limit: Some(Rate(2 << 20)),
+            }
+        );
+
+        assert_eq!(
+            TransferPrompt::parse(r#"'my files' -x "a b" -x c\ d"#).unwrap(),
+            TransferPrompt {
+                path: "my files".to_string(),
+                exclude: vec!["a b".to_string(), "c d".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            TransferPrompt::parse(r"C:\Users\me\My\ Backups -x a\\b -x \*.log").unwrap(),
+            TransferPrompt {
+                path: r"C:\Users\me\My Backups".to_string(),
+                exclude: vec![r"a\b".to_string(), r"\*.log".to_string()],
+                ..Default::default()
+            }
+        );
+        assert!(TransferPrompt::parse("'my files").is_err());
+        assert!(TransferPrompt::parse("-x .git").is_err());
+        assert!(TransferPrompt::parse("~/backup -x").is_err());
+        assert_eq!(
+            TransferPrompt::parse("~/My Documents -l 1M").unwrap().path,
+            "~/My Documents"
+        );
+        assert!(TransferPrompt::parse("~/a -x .git ~/b").is_err());
+        assert!(TransferPrompt::parse("~/a -l fast").is_err());
+        assert!(
+            TransferPrompt::parse("~/a -x .git")
+                .unwrap()
+                .without_patterns()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn sync_prompt_takes_a_path_and_flags() {
+        assert_eq!(
+            SyncPrompt::parse("~/site --delete -n -x .git --pull").unwrap(),
+            SyncPrompt {
+                path: "~/site".to_string(),
+                pull: true,
+                delete: true,
+                dry_run: true,
+                exclude: vec![".git".to_string()],
+                ..Default::default()
+            }
+        );
+        assert!(SyncPrompt::parse("--delete").is_err());
+        assert!(SyncPrompt::parse("~/site --force").is_err());
+        assert!(SyncPrompt::parse("~/site -i").is_err());
+    }
+
+    #[test]
+    fn owner_prompt_takes_a_user_and_group() {
+        assert_eq!(
+            OwnerPrompt::parse("alice:staff -R").unwrap(),
+            OwnerPrompt {
+                user: Some("alice".to_string()),
+                group: Some("staff".to_string()),
+                recursive: true,
+            }
+        );
+        assert_eq!(
+            OwnerPrompt::parse(":50").unwrap(),
+            OwnerPrompt {
+                group: Some("50".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            OwnerPrompt::parse("alice:").unwrap().user.as_deref(),
+            Some("alice")
+        );
+        assert!(OwnerPrompt::parse(":").is_err());
+        assert!(OwnerPrompt::parse("-R").is_err());
+        assert!(OwnerPrompt::parse("alice bob").is_err());
+    }
+
+    #[test]
+    fn link_prompt_takes_a_target_and_name() {
+        assert_eq!(
+            LinkPrompt::parse("'../shared logs' logs").unwrap(),
+            LinkPrompt {
+                target: "../shared logs".to_string(),
+                name: "logs".to_string(),
+            }
+        );
+        assert!(LinkPrompt::parse("logs").is_err());
+        assert!(LinkPrompt::parse("a b c").is_err());
+    }
+
+    #[test]
+    fn copy_prompt_takes_an_optional_path() {
+        assert_eq!(
+            CopyPrompt::parse("-r 'backup copy'").unwrap(),
+            CopyPrompt {
+                path: Some("backup copy".to_string()),
+                recursive: true,
+            }
+        );
+        assert_eq!(CopyPrompt::parse("").unwrap(), CopyPrompt::default());
+        assert!(CopyPrompt::parse("a b").is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Bandwidth limits, like `scp -l` or `rsync --bwlimit`.
+//!
+//! Every transfer in one direction draws from the same global token bucket,
+//! so the configured limit holds however many run at once. A transfer can
+//! also carry a lower limit of its own, which it is held to as well.
+use std::fmt;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::{Context as TaskContext, Poll, ready};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Report, Result, bail};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+use tokio::time::Sleep;
+
+/// A transfer rate in bytes per second.
+///
+/// Written as a size per second with an optional `K`, `M` or `G` suffix in
+/// powers of 1024, such as `500K` or `1.5M`. A bare number is in KiB, as
+/// with `rsync --bwlimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Rate(pub(crate) u64);
+
+impl FromStr for Rate {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '/');
+        let unit = s[digits.len()..].to_ascii_uppercase();
+        // `K`, `KB`, `KiB` and `K/s` all mean the same; a lone `B` is bytes.
+        let unit = unit.trim_end_matches("/S");
+        let unit = if unit.len() > 1 {
+            unit.trim_end_matches('B').trim_end_matches('I')
+        } else {
+            unit
+        };
+        let multiplier: u64 = match unit {
+            "" | "K" => 1 << 10,
+            "B" => 1,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            _ => bail!("unknown unit in rate {s:?}; use B, K, M or G"),
+        };
+        let Ok(value) = digits.trim().parse::<f64>() else {
+            bail!("invalid rate {s:?}");
+        };
+        let rate = (value * multiplier as f64) as u64;
+        if !value.is_finite() || rate == 0 {
+            bail!("rate {s:?} must be positive");
+        }
+        Ok(Rate(rate))
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Rate> for String {
+    fn from(rate: Rate) -> Self {
+        rate.to_string()
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            rate if rate % (1 << 30) == 0 => write!(f, "{}G", rate >> 30),
+            rate if rate % (1 << 20) == 0 => write!(f, "{}M", rate >> 20),
+            rate if rate % (1 << 10) == 0 => write!(f, "{}K", rate >> 10),
+            rate => write!(f, "{rate}B"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Download,
+    Upload,
+}
+
+/// A token bucket holding up to a second's worth of bytes. Taking more than
+/// it holds runs it into debt, and the caller waits for the debt to clear.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    /// Bytes per second, or 0 for no limit.
+    rate: AtomicU64,
+    /// Tokens available, and when that was last worked out.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<Rate>) -> Self {
+        let rate = rate.map_or(0, |rate| rate.0);
+        Self {
+            rate: AtomicU64::new(rate),
+            state: Mutex::new((rate as f64, Instant::now())),
+        }
+    }
+
+    fn set_rate(&self, rate: Option<Rate>) {
+        self.rate
+            .store(rate.map_or(0, |rate| rate.0), Ordering::Relaxed);
+    }
+
+    /// Takes `bytes` tokens, and returns how long to wait before going on.
+    fn take(&self, bytes: usize) -> Duration {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 {
+            return Duration::ZERO;
+        }
+        let rate = rate as f64;
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+        let (tokens, last) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+        *last = now;
+        *tokens -= bytes as f64;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / rate)
+        }
+    }
+}
+
+static DOWNLOADS: LazyLock<TokenBucket> = LazyLock::new(|| TokenBucket::new(None));
+static UPLOADS: LazyLock<TokenBucket> = LazyLock::new(|| TokenBucket::new(None));
+
+fn global(direction: Direction) -> &'static TokenBucket {
+    match direction {
+        Direction::Download => &DOWNLOADS,
+        Direction::Upload => &UPLOADS,
+    }
+}
+
+/// Sets the limit shared by every transfer in `direction`.
+pub(crate) fn set_global_limit(direction: Direction, rate: Option<Rate>) {
+    global(direction).set_rate(rate);
+}
+
+/// The limits one transfer is held to. Clones share the transfer's own
+/// bucket, so every file of a directory transfer draws from the same one.
+#[derive(Debug, Clone)]
+pub(crate) struct Limit {
+    global: &'static TokenBucket,
+    own: Option<Arc<TokenBucket>>,
+}
+
+impl Limit {
+    pub(crate) fn new(direction: Direction, own: Option<Rate>) -> Self {
+        Self {
+            global: global(direction),
+            own: own.map(|rate| Arc::new(TokenBucket::new(Some(rate)))),
+        }
+    }
+
+    fn take(&self, bytes: usize) -> Duration {
+        let own = self
+            .own
+            .as_ref()
+            .map_or(Duration::ZERO, |own| own.take(bytes));
+        self.global.take(bytes).max(own)
+    }
+}
+
+/// A writer that keeps to a [`Limit`], pausing after each write for as long
+/// as the limit asks.
+pub(crate) struct Throttled<W> {
+    inner: W,
+    limit: Limit,
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W> Throttled<W> {
+    pub(crate) fn new(inner: W, limit: Limit) -> Self {
+        Self {
+            inner,
+            limit,
+            pause: None,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Throttled<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(pause) = &mut this.pause {
+            ready!(pause.as_mut().poll(cx));
+            this.pause = None;
+        }
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        let wait = this.limit.take(written);
+        if !wait.is_zero() {
+            this.pause = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        std::thread::sleep(self.limit.take(written));
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_parse_like_bwlimit() {
+        let rate = |s: &str| s.parse::<Rate>().unwrap().0;
+        assert_eq!(rate("500"), 500 << 10);
+        assert_eq!(rate("500K"), 500 << 10);
+        assert_eq!(rate("1.5M"), 3 << 19);
+        assert_eq!(rate("2MiB/s"), 2 << 20);
+        assert_eq!(rate("100b"), 100);
+        assert_eq!(rate("64KB"), 64 << 10);
+        assert!("fast".parse::<Rate>().is_err());
+        assert!("0".parse::<Rate>().is_err());
+        assert!("5X".parse::<Rate>().is_err());
+
+        assert_eq!(Rate(3 << 19).to_string(), "1536K");
+        assert_eq!(Rate(2 << 20).to_string(), "2M");
+    }
+
+    #[test]
+    fn bucket_allows_a_second_of_burst_then_paces() {
+        let bucket = TokenBucket::new(Some(Rate(1000)));
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        let wait = bucket.take(500);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+
+        let unlimited = TokenBucket::new(None);
+        assert_eq!(unlimited.take(usize::MAX), Duration::ZERO);
+    }
+
+    #[test]
+    fn clones_of_a_limit_share_its_bucket() {
+        let limit = Limit::new(Direction::Upload, Some(Rate(1000)));
+        let other_file = limit.clone();
+        assert_eq!(limit.take(1000), Duration::ZERO);
+        assert!(other_file.take(500) > Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,231 @@
+//! Renaming many entries of a directory at once, from a list of their names
+//! edited as text: line by line, each name becomes what its line says.
+//!
+//! Renames are ordered so that none lands on a name still in use, and a
+//! cycle of them, such as two names swapped, goes through a temporary name.
+//! A rename onto a name that stays taken is left out, and reported.
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::SftpSession;
+use tracing::info;
+
+use super::Summary;
+use super::journal::Change;
+use crate::files::JoinablePaths;
+
+/// One rename, of a name in the directory to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Step {
+    pub(crate) from: String,
+    pub(crate) to: String,
+    /// Whether `to` is a temporary name, taken to break a cycle.
+    pub(crate) temporary: bool,
+}
+
+/// The renames an edited list asks for.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Plan {
+    /// Each rename asked for, in the order of the list, for confirming.
+    pub(crate) renames: Vec<(String, String)>,
+    /// The renames left out, with why.
+    pub(crate) collisions: Vec<(String, String)>,
+    /// The renames in the order they are done, with temporary names.
+    pub(crate) steps: Vec<Step>,
+}
+
+impl Plan {
+    /// Compares `edited` with the `names` it was written from, one per line.
+    /// `existing` holds every name in the directory, listed or not.
+    pub(crate) fn new(names: &[String], edited: &str, existing: &HashSet<String>) -> Result<Self> {
+        let lines = edited.lines().collect::<Vec<_>>();
+        if lines.len() != names.len() {
+            bail!(
+                "the list has {} lines for {} names; names can be changed, but not added or removed",
+                lines.len(),
+                names.len()
+            );
+        }
+        let mut wanted = Vec::new();
+        for (name, line) in names.iter().zip(lines) {
+            if line == name {
+                continue;
+            }
+            if line.is_empty() || line == "." || line == ".." || line.contains('/') {
+                bail!("{line:?}, for {name}, is not a name a file can have here");
+            }
+            wanted.push((name.clone(), line.to_string()));
+        }
+
+        let mut plan = Self::default();
+        let mut targets = HashMap::<&str, usize>::new();
+        for (_, to) in &wanted {
+            *targets.entry(to).or_default() += 1;
+        }
+        let mut left_out = HashSet::new();
+        for (from, to) in &wanted {
+            if targets[to.as_str()] > 1 {
+                plan.collisions
+                    .push((from.clone(), format!("{to} is asked for more than once")));
+                left_out.insert(from.clone());
+            }
+        }
+        // Leaving a rename out keeps its name taken, which may leave another
+        // without a place to go.
+        loop {
+            let moving = wanted
+                .iter()
+                .filter(|(from, _)| !left_out.contains(from))
+                .map(|(from, _)| from.as_str())
+                .collect::<HashSet<_>>();
+            let blocked = wanted
+                .iter()
+                .filter(|(from, to)| {
+                    !left_out.contains(from)
+                        && existing.contains(to)
+                        && !moving.contains(to.as_str())
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if blocked.is_empty() {
+                break;
+            }
+            for (from, to) in blocked {
+                plan.collisions.push((from.clone(), format!("{to} exists")));
+                left_out.insert(from);
+            }
+        }
+        plan.renames = wanted
+            .into_iter()
+            .filter(|(from, _)| !left_out.contains(from))
+            .collect();
+        plan.steps = order(&plan.renames, existing);
+        Ok(plan)
+    }
+}
+
+/// Orders `renames` so that each goes to a free name: a rename waits while
+/// its new name is one still to be renamed away, and when all that are left
+/// wait on each other, one of them steps aside to a temporary name first.
+fn order(renames: &[(String, String)], existing: &HashSet<String>) -> Vec<Step> {
+    let mut pending = renames.to_vec();
+    let mut steps = Vec::new();
+    let mut counter = 0;
+    while !pending.is_empty() {
+        let sources = pending
+            .iter()
+            .map(|(from, _)| from.clone())
+            .collect::<HashSet<_>>();
+        if let Some(free) = pending.iter().position(|(_, to)| !sources.contains(to)) {
+            let (from, to) = pending.remove(free);
+            steps.push(Step {
+                from,
+                to,
+                temporary: false,
+            });
+            continue;
+        }
+        let temporary = loop {
+            counter += 1;
+            let name = format!(".filessh-rename-{counter}");
+            let taken = existing.contains(&name)
+                || renames
+                    .iter()
+                    .any(|(from, to)| *from == name || *to == name);
+            if !taken {
+                break name;
+            }
+        };
+        let from = std::mem::replace(&mut pending[0].0, temporary.clone());
+        steps.push(Step {
+            from,
+            to: temporary,
+            temporary: true,
+        });
+    }
+    steps
+}
+
+/// Does the `steps` of a plan in `dir`, one after the other, and returns
+/// those that went through. An entry whose move to a temporary name failed is
+/// not moved on from there.
+pub(crate) async fn apply(sftp: &SftpSession, dir: &str, steps: &[Step]) -> (Summary, Vec<Change>) {
+    let mut summary = Summary::default();
+    let mut changes = Vec::new();
+    let mut missing = HashSet::new();
+    for step in steps {
+        if missing.contains(&step.from) {
+            continue;
+        }
+        let from = dir.to_string().join(&step.from);
+        let to = dir.to_string().join(&step.to);
+        info!(from, to, "Renaming");
+        match sftp.rename(from.as_str(), to.as_str()).await {
+            Ok(()) => {
+                summary.done += usize::from(!step.temporary);
+                changes.push(Change::Rename { from, to });
+            }
+            Err(error) if step.temporary => {
+                missing.insert(step.to.clone());
+                summary.skip(from, error);
+            }
+            Err(error) => summary.skip(from, error),
+        }
+    }
+    (summary, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn swaps_go_through_a_temporary_name_and_collisions_are_left_out() {
+        let listed = names(&["a", "b", "c", "d", "e"]);
+        let existing = names(&["a", "b", "c", "d", "e", "keep"])
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let plan = Plan::new(&listed, "b\na\nc2\nkeep\nd\n", &existing).unwrap();
+
+        // d cannot take the name of keep, which stays, so e cannot take d.
+        assert_eq!(
+            plan.collisions,
+            vec![
+                ("d".to_string(), "keep exists".to_string()),
+                ("e".to_string(), "d exists".to_string()),
+            ]
+        );
+        assert_eq!(
+            plan.renames,
+            vec![
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "a".to_string()),
+                ("c".to_string(), "c2".to_string()),
+            ]
+        );
+        let steps = plan
+            .steps
+            .iter()
+            .map(|step| (step.from.as_str(), step.to.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            [
+                ("c", "c2"),
+                ("a", ".filessh-rename-1"),
+                ("b", "a"),
+                (".filessh-rename-1", "b"),
+            ]
+        );
+
+        let duplicate = Plan::new(&listed[..2], "x\nx\n", &existing).unwrap();
+        assert!(duplicate.renames.is_empty());
+        assert_eq!(duplicate.collisions.len(), 2);
+        assert!(Plan::new(&listed, "a\nb\n", &existing).is_err());
+        assert!(Plan::new(&listed[..1], "sub/a\n", &existing).is_err());
+    }
+}
@@ -0,0 +1,434 @@
+//! Saving new content over a remote file so that it is never seen half
+//! written: the content goes to a temporary file next to it, which is then
+//! renamed over it.
+//!
+//! OpenSSH servers rename over an existing file in one step with the
+//! `posix-rename@openssh.com` extension. Plain SFTP renames refuse to replace
+//! their target, so elsewhere the old file is first renamed aside, leaving a
+//! moment in which the path is missing, though still none in which it is
+//! half written.
+//!
+//! What a file looked like when it was read is kept as a [`Fingerprint`], so
+//! that a save can tell whether someone else changed it in the meantime.
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::fs::File;
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use russh_sftp::protocol::{FileAttributes, OpenFlags, Packet, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{info, warn};
+
+use super::journal::MAX_KEPT_CONTENT;
+
+/// The extension that renames over an existing file.
+pub(crate) const POSIX_RENAME: &str = "posix-rename@openssh.com";
+
+/// How much of a file is read at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// The `backup` key of the `[editor]` section: what is kept of a file a save
+/// replaces.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Backup {
+    /// Nothing.
+    #[default]
+    None,
+    /// The last version, as `NAME.bak`.
+    Bak,
+    /// Every version, as `NAME.YYYYMMDD-HHMMSS.bak`.
+    Timestamped,
+}
+
+/// The `[editor]` section of the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub(crate) struct EditorSettings {
+    #[serde(default)]
+    pub(crate) backup: Backup,
+}
+
+/// The modification time, size and content hash of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    mtime: Option<u32>,
+    size: Option<u64>,
+    hash: [u8; 32],
+}
+
+impl Fingerprint {
+    /// Reads the remote `path` through, copying it to `local` if given, and
+    /// returns its fingerprint, with its content if that is no larger than
+    /// [`MAX_KEPT_CONTENT`].
+    pub(crate) async fn read(
+        sftp: &SftpSession,
+        path: &str,
+        mut local: Option<&mut tokio::fs::File>,
+    ) -> Result<(Self, Option<Vec<u8>>)> {
+        let mut file = sftp.open(path).await?;
+        let attributes = file.metadata().await?;
+        let mut hasher = Sha256::new();
+        let mut kept = Some(Vec::new());
+        let mut chunk = vec![0; CHUNK];
+        loop {
+            let read = file.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&chunk[..read]);
+            if let Some(local) = local.as_mut() {
+                local.write_all(&chunk[..read]).await?;
+            }
+            kept = kept
+                .filter(|kept| (kept.len() + read) as u64 <= MAX_KEPT_CONTENT)
+                .map(|mut kept| {
+                    kept.extend_from_slice(&chunk[..read]);
+                    kept
+                });
+        }
+        if let Some(local) = local {
+            local.flush().await?;
+        }
+        let fingerprint = Self {
+            mtime: attributes.mtime,
+            size: attributes.size,
+            hash: hasher.finalize().into(),
+        };
+        Ok((fingerprint, kept))
+    }
+
+    /// When the file was last modified, as seconds since the epoch.
+    pub(crate) fn mtime(&self) -> Option<u32> {
+        self.mtime
+    }
+
+    /// Whether the local file `path` has the content this was taken from.
+    pub(crate) fn matches(&self, path: &Path) -> Result<bool> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hasher)?;
+        let hash: [u8; 32] = hasher.finalize().into();
+        Ok(self.size.is_none_or(|known| known == size) && hash == self.hash)
+    }
+
+    /// What the remote `path` holds now, as [`Fingerprint::read`] has it, if
+    /// its content is not what this was taken from: nothing if it is gone. A
+    /// file that was only touched counts as unchanged.
+    pub(crate) async fn changed(
+        &self,
+        sftp: &SftpSession,
+        path: &str,
+    ) -> Result<Option<(Self, Option<Vec<u8>>)>> {
+        let (now, content) = match Self::read(sftp, path, None).await {
+            Ok(read) => read,
+            Err(error) => match error.downcast_ref::<SftpError>() {
+                Some(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                    let gone = Self {
+                        mtime: None,
+                        size: Some(0),
+                        hash: Sha256::digest([]).into(),
+                    };
+                    return Ok(Some((gone, Some(Vec::new()))));
+                }
+                _ => return Err(error),
+            },
+        };
+        let same = (now.size, now.hash) == (self.size, self.hash);
+        Ok((!same).then_some((now, content)))
+    }
+}
+
+/// An edit on its way to the remote file it was made to.
+#[derive(Debug, Clone)]
+pub(crate) struct Edit {
+    pub(crate) path: String,
+    /// The edited copy.
+    pub(crate) local: PathBuf,
+    /// The content the edit started from, if small enough to keep.
+    pub(crate) base: Option<Vec<u8>>,
+    /// What the file looked like when `base` was read from it.
+    pub(crate) read_as: Fingerprint,
+}
+
+impl Edit {
+    /// Downloads the remote `path` to edit it, into a directory of its own in
+    /// the local temporary directory, under the same name so that editors
+    /// can tell what kind of file it is.
+    pub(crate) async fn download(sftp: &SftpSession, path: &str) -> Result<Self> {
+        let local = local_path(path);
+        if let Some(dir) = local.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut file = tokio::fs::File::create(&local).await?;
+        let (read_as, base) = Fingerprint::read(sftp, path, Some(&mut file)).await?;
+        Ok(Self {
+            path: path.to_string(),
+            local,
+            base,
+            read_as,
+        })
+    }
+
+    /// The edited content, if it is small enough to keep.
+    pub(crate) fn mine(&self) -> Result<Option<Vec<u8>>> {
+        if std::fs::metadata(&self.local)?.len() > MAX_KEPT_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(std::fs::read(&self.local)?))
+    }
+
+    /// Removes the edited copy, once it is no longer needed.
+    pub(crate) fn discard(&self) {
+        if let Some(dir) = self.local.parent() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// An edit to a file that changed on the server since it was read.
+#[derive(Debug, Clone)]
+pub(crate) struct EditConflict {
+    pub(crate) edit: Edit,
+    /// What the server has now, if small enough to keep.
+    pub(crate) theirs: Option<Vec<u8>>,
+    pub(crate) now: Fingerprint,
+    /// Set once the edited copy holds a merge with what the server has.
+    pub(crate) merged: bool,
+}
+
+impl EditConflict {
+    /// The edit, as if made over what the server has now.
+    pub(crate) fn rebased(&self) -> Edit {
+        Edit {
+            path: self.edit.path.clone(),
+            local: self.edit.local.clone(),
+            base: self.theirs.clone(),
+            read_as: self.now.clone(),
+        }
+    }
+}
+
+/// What a save writes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Content<'a> {
+    Bytes(&'a [u8]),
+    /// The content of a local file.
+    File(&'a Path),
+}
+
+impl Content<'_> {
+    async fn write_to(&self, file: &mut File) -> Result<()> {
+        match self {
+            Content::Bytes(bytes) => file.write_all(bytes).await?,
+            Content::File(path) => {
+                let mut local = tokio::fs::File::open(path).await?;
+                tokio::io::copy(&mut local, file).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The body of a `posix-rename@openssh.com` request.
+#[derive(Serialize)]
+struct PosixRename {
+    oldpath: String,
+    newpath: String,
+}
+
+/// How a save went.
+#[derive(Debug, Default)]
+pub(crate) struct Saved {
+    /// Where the old content was kept, if it was.
+    pub(crate) backup: Option<String>,
+    /// Set when the file was written in place instead, as the replacement
+    /// could not be given its owner.
+    pub(crate) in_place: bool,
+}
+
+/// Replaces the content of the remote `path` with `content`, keeping its
+/// permissions and owner, and a backup if asked. A link is followed, and the
+/// file it points to saved. `raw`, if given, is a session of a server that
+/// supports [`POSIX_RENAME`].
+pub(crate) async fn save(
+    sftp: &SftpSession,
+    raw: Option<&RawSftpSession>,
+    path: &str,
+    content: Content<'_>,
+    backup: Backup,
+) -> Result<Saved> {
+    let path = sftp
+        .canonicalize(path)
+        .await
+        .unwrap_or_else(|_| path.to_string());
+    let current = match sftp.metadata(path.as_str()).await {
+        Ok(current) => current,
+        Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+            write(sftp, &path, content).await?;
+            return Ok(Saved::default());
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let mut saved = Saved::default();
+    if let Some(backup) = backup_path(&path, backup, Local::now()) {
+        let mut old = sftp.open(path.as_str()).await?;
+        let mut file = sftp.create(backup.as_str()).await?;
+        keep_attributes(sftp, &backup, &current).await;
+        tokio::io::copy(&mut old, &mut file).await?;
+        file.shutdown().await?;
+        info!(path, backup, "Kept a backup");
+        saved.backup = Some(backup);
+    }
+
+    // The attributes are set before anything is written, so that the
+    // content is never readable by more than it was.
+    let temporary = temporary_path(&path);
+    let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+    let mut file = sftp.open_with_flags(temporary.as_str(), flags).await?;
+    if !keep_attributes(sftp, &temporary, &current).await {
+        // Taking the file from its owner would be worse than a save that is
+        // not atomic, and the backup, if any, is there by now.
+        warn!(
+            path,
+            "Could not keep the owner of the file, writing it in place"
+        );
+        drop(file);
+        let _ = sftp.remove_file(temporary.as_str()).await;
+        write(sftp, &path, content).await?;
+        saved.in_place = true;
+        return Ok(saved);
+    }
+    let written = async {
+        content.write_to(&mut file).await?;
+        file.sync_all().await?;
+        file.shutdown().await?;
+        Ok::<_, color_eyre::Report>(())
+    }
+    .await;
+    let replaced = match written {
+        Ok(()) => replace(sftp, raw, &temporary, &path).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = replaced {
+        let _ = sftp.remove_file(temporary.as_str()).await;
+        return Err(error);
+    }
+    info!(path, "Saved");
+    Ok(saved)
+}
+
+/// Renames `temporary` over `path`.
+async fn replace(
+    sftp: &SftpSession,
+    raw: Option<&RawSftpSession>,
+    temporary: &str,
+    path: &str,
+) -> Result<()> {
+    if let Some(raw) = raw {
+        let request = PosixRename {
+            oldpath: temporary.to_string(),
+            newpath: path.to_string(),
+        };
+        let reply = raw
+            .extended(POSIX_RENAME, russh_sftp::ser::to_bytes(&request)?.to_vec())
+            .await?;
+        return match reply {
+            Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+            Packet::Status(status) => Err(SftpError::Status(status).into()),
+            _ => Err(SftpError::UnexpectedPacket.into()),
+        };
+    }
+    let aside = format!("{temporary}.old");
+    sftp.rename(path, aside.as_str()).await?;
+    if let Err(error) = sftp.rename(temporary, path).await {
+        if sftp.rename(aside.as_str(), path).await.is_err() {
+            bail!("could not save over {path}, whose old content is at {aside}: {error}");
+        }
+        return Err(error.into());
+    }
+    sftp.remove_file(aside.as_str()).await?;
+    Ok(())
+}
+
+/// Gives `path` the owner and permissions of `current`, in that order, as a
+/// change of owner may clear setuid bits, and tells whether the owner is as
+/// it was.
+async fn keep_attributes(sftp: &SftpSession, path: &str, current: &FileAttributes) -> bool {
+    let owner_kept = match sftp.metadata(path).await {
+        Ok(new) if (new.uid, new.gid) == (current.uid, current.gid) => true,
+        Ok(_) => {
+            let mut attributes = FileAttributes::empty();
+            (attributes.uid, attributes.gid) = (current.uid, current.gid);
+            sftp.set_metadata(path, attributes).await.is_ok()
+        }
+        Err(_) => false,
+    };
+    let mut attributes = FileAttributes::empty();
+    attributes.permissions = current.permissions.map(|mode| mode & 0o7777);
+    if let Err(error) = sftp.set_metadata(path, attributes).await {
+        warn!(path, %error, "Could not keep the permissions");
+    }
+    owner_kept
+}
+
+/// Writes `content` over `path` in place, truncating it first.
+async fn write(sftp: &SftpSession, path: &str, content: Content<'_>) -> Result<()> {
+    let mut file = sftp.create(path).await?;
+    content.write_to(&mut file).await?;
+    file.shutdown().await?;
+    Ok(())
+}
+
+/// A hidden name next to `path`, unlikely to be taken.
+fn temporary_path(path: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let stamp = Local::now().timestamp_nanos_opt().unwrap_or_default();
+    format!("{dir}/.{name}.{stamp:x}.filessh-save")
+}
+
+/// Where the remote `path` is downloaded to for editing.
+fn local_path(path: &str) -> PathBuf {
+    let stamp = Local::now().timestamp_nanos_opt().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or(path);
+    std::env::temp_dir()
+        .join(format!("filessh-edit-{}-{stamp:x}", std::process::id()))
+        .join(name)
+}
+
+fn backup_path(path: &str, backup: Backup, now: DateTime<Local>) -> Option<String> {
+    match backup {
+        Backup::None => None,
+        Backup::Bak => Some(format!("{path}.bak")),
+        Backup::Timestamped => Some(format!("{path}.{}.bak", now.format("%Y%m%d-%H%M%S"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn backups_and_temporary_files_sit_next_to_the_file() {
+        let now = Local.with_ymd_and_hms(2026, 10, 18, 9, 41, 0).unwrap();
+        let path = "/etc/nginx/nginx.conf";
+        assert_eq!(backup_path(path, Backup::None, now), None);
+        assert_eq!(
+            backup_path(path, Backup::Bak, now).as_deref(),
+            Some("/etc/nginx/nginx.conf.bak")
+        );
+        assert_eq!(
+            backup_path(path, Backup::Timestamped, now).as_deref(),
+            Some("/etc/nginx/nginx.conf.20261018-094100.bak")
+        );
+        let temporary = temporary_path(path);
+        assert!(temporary.starts_with("/etc/nginx/.nginx.conf."));
+        assert!(temporary.ends_with(".filessh-save"));
+        let local = local_path(path);
+        assert_eq!(local.file_name().unwrap(), "nginx.conf");
+        assert!(local.starts_with(std::env::temp_dir()));
+    }
+}
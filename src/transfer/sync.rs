@@ -0,0 +1,890 @@
+//! Making one directory tree match another, as `rsync -rt` would.
+//!
+//! Both trees are listed up front and compared by size and modification time,
+//! or by checksum, into a [`Plan`] that can be shown as a dry run or carried
+//! out. Synced files take the source's times, so that the next comparison
+//! finds them unchanged.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result, bail};
+use russh_sftp::client::SftpSession;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use super::conflict::{ConflictPolicy, ConflictResolver};
+use super::filter::PathFilter;
+use super::ratelimit::{Direction, Limit};
+use super::{Summary, SymlinkPolicy, TransferSettings};
+use crate::files::{FileEntry, human_readable_size};
+use crate::par_dir_traversal::WalkParallel;
+use crate::ssh::Remote;
+
+/// How many paths go into one remote `sha256sum`, to keep the command line
+/// well within what the remote shell takes.
+const CHECKSUM_BATCH: usize = 200;
+
+/// The flag that runs filessh as `rsync`'s remote shell, followed by the
+/// socket to reach the session through, then what rsync passes an `ssh`.
+#[cfg(unix)]
+pub(crate) const RSYNC_SHELL: &str = "--rsync-shell";
+
+/// What a sync should do besides copying what changed.
+#[derive(Debug, Clone)]
+pub(crate) struct SyncOptions {
+    /// `Upload` makes the remote tree match the local one, `Download` the
+    /// other way round.
+    pub(crate) direction: Direction,
+    /// Also delete what the destination has and the source does not.
+    pub(crate) delete: bool,
+    /// Compare files of the same size by content instead of by time.
+    pub(crate) checksum: bool,
+    /// Entries this rejects are neither copied nor deleted.
+    pub(crate) filter: PathFilter,
+    /// Send changed files through `rsync`, which transfers only the parts
+    /// that differ, when both ends have it. It runs over the session's own
+    /// connection, so it logs in however the session did.
+    pub(crate) delta: bool,
+}
+
+/// An entry of a tree being synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Node {
+    pub(crate) is_dir: bool,
+    /// Links are never synced, but one in the destination is in the way of
+    /// what the source has there, and is replaced rather than written through.
+    pub(crate) is_link: bool,
+    pub(crate) size: u64,
+    pub(crate) mtime: Option<u32>,
+}
+
+/// A tree by path relative to its root, with `/` between the components.
+pub(crate) type Tree = BTreeMap<String, Node>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Add,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) action: Action,
+    pub(crate) path: String,
+    pub(crate) is_dir: bool,
+    /// How much would be sent, for files being added or updated.
+    pub(crate) size: u64,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = match self.action {
+            Action::Add => '+',
+            Action::Update => '~',
+            Action::Delete => '-',
+        };
+        let slash = if self.is_dir { "/" } else { "" };
+        write!(f, "{sign} {}{slash}", self.path)?;
+        if self.action != Action::Delete && !self.is_dir {
+            write!(f, " ({})", human_readable_size(self.size))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a sync would change, and what it had to leave out while looking.
+#[derive(Debug, Default)]
+pub(crate) struct Plan {
+    pub(crate) changes: Vec<Change>,
+    pub(crate) skipped: Summary,
+    pub(crate) local_root: PathBuf,
+    pub(crate) remote_root: String,
+}
+
+impl Plan {
+    /// A line per change, then a line of totals.
+    pub(crate) fn describe(&self) -> String {
+        if self.changes.is_empty() {
+            return "Already in sync".to_string();
+        }
+        let count = |action| {
+            self.changes
+                .iter()
+                .filter(|change| change.action == action)
+                .count()
+        };
+        let bytes: u64 = self
+            .changes
+            .iter()
+            .filter(|change| change.action != Action::Delete)
+            .map(|change| change.size)
+            .sum();
+        let mut description = self
+            .changes
+            .iter()
+            .map(|change| format!("{change}\n"))
+            .collect::<String>();
+        description.push_str(&format!(
+            "{} to add, {} to update, {} to delete, {} to send",
+            count(Action::Add),
+            count(Action::Update),
+            count(Action::Delete),
+            human_readable_size(bytes)
+        ));
+        description
+    }
+}
+
+/// Compares the trees, listing what it takes to make `dest` match `source`.
+///
+/// Files differ if `differs` says so. Deletions come first, then additions
+/// and updates with every directory ahead of what goes in it. Within a
+/// deleted directory, only the directory itself is listed.
+pub(crate) fn compare(
+    source: &Tree,
+    dest: &Tree,
+    delete: bool,
+    differs: impl Fn(&str, &Node, &Node) -> bool,
+) -> Vec<Change> {
+    let mut deletions = Vec::new();
+    let mut changes = Vec::new();
+    let change = |action, path: &str, node: &Node| Change {
+        action,
+        path: path.to_string(),
+        is_dir: node.is_dir,
+        size: if node.is_dir { 0 } else { node.size },
+    };
+
+    for (path, node) in source {
+        match dest.get(path) {
+            None => changes.push(change(Action::Add, path, node)),
+            // Something of the other kind is in the way, and has to go.
+            Some(existing) if existing.is_dir != node.is_dir || existing.is_link => {
+                deletions.push(change(Action::Delete, path, existing));
+                changes.push(change(Action::Add, path, node));
+            }
+            Some(existing) if !node.is_dir && differs(path, node, existing) => {
+                changes.push(change(Action::Update, path, node));
+            }
+            Some(_) => {}
+        }
+    }
+    if delete {
+        for (path, node) in dest {
+            if !source.contains_key(path) {
+                deletions.push(change(Action::Delete, path, node));
+            }
+        }
+    }
+
+    // A directory that goes takes everything in it along.
+    let deleted_dirs: HashSet<&str> = deletions
+        .iter()
+        .filter(|change| change.is_dir)
+        .map(|change| change.path.as_str())
+        .collect();
+    let inside_deleted = |path: &str| {
+        let mut parent = path;
+        while let Some((up, _)) = parent.rsplit_once('/') {
+            if deleted_dirs.contains(up) {
+                return true;
+            }
+            parent = up;
+        }
+        false
+    };
+    let mut plan: Vec<Change> = deletions
+        .iter()
+        .filter(|change| !inside_deleted(&change.path))
+        .cloned()
+        .collect();
+    plan.sort_by(|a, b| a.path.cmp(&b.path));
+    plan.extend(changes);
+    plan
+}
+
+/// The quick check `rsync` makes: files of the same size and modification
+/// time are taken to be the same.
+fn quick_check(_: &str, source: &Node, dest: &Node) -> bool {
+    source.size != dest.size || source.mtime != dest.mtime
+}
+
+/// Lists the local tree at `root`, or nothing if it does not exist. Links
+/// are listed as links, without following them; special files are left out,
+/// and noted in `skipped`.
+async fn local_tree(root: &Path, filter: &PathFilter, skipped: &mut Summary) -> Result<Tree> {
+    let mut tree = Tree::new();
+    if tokio::fs::symlink_metadata(root).await.is_err() {
+        return Ok(tree);
+    }
+    let mut stack = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, relative_dir)) = stack.pop() {
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .wrap_err_with(|| format!("could not list {}", dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let relative = if relative_dir.is_empty() {
+                name
+            } else {
+                format!("{relative_dir}/{name}")
+            };
+            let metadata = entry.metadata().await?;
+            let file_type = metadata.file_type();
+            if !filter.accepts(&relative, file_type.is_dir()) {
+                continue;
+            }
+            if let Some(kind) = super::local_special_kind(&file_type) {
+                skipped.skip(entry.path().display().to_string(), kind);
+                continue;
+            }
+            let node = Node {
+                is_dir: file_type.is_dir(),
+                is_link: file_type.is_symlink(),
+                size: metadata.len(),
+                mtime: russh_sftp::protocol::FileAttributes::from(&metadata).mtime,
+            };
+            if node.is_dir {
+                stack.push((entry.path(), relative.clone()));
+            }
+            tree.insert(relative, node);
+        }
+    }
+    Ok(tree)
+}
+
+/// Lists the remote tree at `root`, or nothing if it does not exist, the
+/// same way as [`local_tree`].
+async fn remote_tree(
+    sftp: Arc<SftpSession>,
+    root: &str,
+    filter: PathFilter,
+    skipped: &mut Summary,
+) -> Result<Tree> {
+    let mut tree = Tree::new();
+    if super::remote_metadata(&sftp, root).await?.is_none() {
+        return Ok(tree);
+    }
+    let prefix = format!("{}/", root.trim_end_matches('/'));
+    let relative = move |entry: &FileEntry| {
+        entry
+            .name()
+            .strip_prefix(&prefix)
+            .unwrap_or(entry.name())
+            .to_string()
+    };
+    let accept = relative.clone();
+    let walker = WalkParallel {
+        filter: Arc::new(move |entry: &FileEntry| filter.accepts(&accept(entry), entry.is_dir())),
+        path: root.into(),
+        max_depth: None,
+        min_depth: Some(1),
+        threads: 4,
+        sftp,
+        follow_links: false,
+    };
+    let (entries, errors) = walker.collect().await;
+    for error in errors {
+        skipped.skip("", error);
+    }
+    for entry in entries {
+        if let Some(kind) = super::special_kind(&entry.attributes) {
+            skipped.skip(entry.name().clone(), kind);
+        } else {
+            let node = Node {
+                is_dir: entry.is_dir(),
+                is_link: entry.is_symlink(),
+                size: entry.attributes.size.unwrap_or(0),
+                mtime: entry.attributes.mtime,
+            };
+            tree.insert(relative(&entry), node);
+        }
+    }
+    Ok(tree)
+}
+
+/// SHA-256 sums of the local `paths` below `root`, or `None` for the files
+/// that could not be read, which are noted in `skipped`.
+async fn local_checksums(
+    root: &Path,
+    paths: Vec<String>,
+    skipped: &mut Summary,
+) -> Result<HashMap<String, Option<String>>> {
+    let root = root.to_path_buf();
+    let sums = tokio::task::spawn_blocking(move || {
+        let sum = |path: &Path| -> std::io::Result<String> {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        };
+        paths
+            .into_iter()
+            .map(|path| {
+                let full = root.join(&path);
+                let sum = sum(&full).map_err(|error| (full.display().to_string(), error));
+                (path, sum)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    let mut found = HashMap::new();
+    for (path, sum) in sums {
+        match sum {
+            Ok(sum) => found.insert(path, Some(sum)),
+            Err((full, error)) => {
+                skipped.skip(full, error);
+                found.insert(path, None)
+            }
+        };
+    }
+    Ok(found)
+}
+
+/// SHA-256 sums of the remote `paths` below `root`, worked out on the remote
+/// host with `sha256sum` so that nothing has to be downloaded.
+async fn remote_checksums(
+    remote: &Remote,
+    root: &str,
+    paths: Vec<String>,
+) -> Result<HashMap<String, String>> {
+    let quote = |word: &str| shell_escape::unix::escape(word.into()).to_string();
+    let mut sums = HashMap::new();
+    for batch in paths.chunks(CHECKSUM_BATCH) {
+        let files = batch.iter().map(|path| quote(path)).collect::<Vec<_>>();
+        let command = format!("cd {} && sha256sum -- {}", quote(root), files.join(" "));
+        let output = remote.exec(&command).await?.output().await?;
+        if output.status == Some(127) {
+            bail!("--checksum needs sha256sum on the remote host");
+        }
+        // A file that could not be read is missing here, and so counts as
+        // changed. So do names `sha256sum` had to escape, which start the
+        // line with a backslash.
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some((sum, path)) = line.split_once(' ')
+                && !sum.starts_with('\\')
+            {
+                let path = path.strip_prefix([' ', '*']).unwrap_or(path);
+                sums.insert(path.to_string(), sum.to_string());
+            }
+        }
+    }
+    Ok(sums)
+}
+
+/// Lists both trees and works out what a sync would change.
+pub(crate) async fn plan(
+    sftp: Arc<SftpSession>,
+    remote: &Remote,
+    local_root: &Path,
+    remote_root: &str,
+    options: &SyncOptions,
+) -> Result<Plan> {
+    // A missing source would read as empty, and with `delete` wipe the
+    // destination.
+    let source_is_dir = match options.direction {
+        Direction::Upload => tokio::fs::metadata(local_root)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir()),
+        Direction::Download => super::remote_metadata(&sftp, remote_root)
+            .await?
+            .is_some_and(|attributes| attributes.is_dir()),
+    };
+    if !source_is_dir {
+        match options.direction {
+            Direction::Upload => bail!("{} is not a directory", local_root.display()),
+            Direction::Download => bail!("{remote_root} is not a directory"),
+        }
+    }
+
+    let mut skipped = Summary::default();
+    let mut local = local_tree(local_root, &options.filter, &mut skipped).await?;
+    let mut remote_files =
+        remote_tree(sftp, remote_root, options.filter.clone(), &mut skipped).await?;
+    // Links in the source are left out, along with whatever the destination
+    // has in their place. A link in the destination is kept, to be replaced
+    // where the source has something else.
+    let source_links: Vec<String> = match options.direction {
+        Direction::Upload => &local,
+        Direction::Download => &remote_files,
+    }
+    .iter()
+    .filter(|(_, node)| node.is_link)
+    .map(|(path, _)| path.clone())
+    .collect();
+    for link in source_links {
+        let full = match options.direction {
+            Direction::Upload => local_root.join(&link).display().to_string(),
+            Direction::Download => format!("{}/{link}", remote_root.trim_end_matches('/')),
+        };
+        skipped.skip(full, "symbolic link");
+        let below = format!("{link}/");
+        for tree in [&mut local, &mut remote_files] {
+            tree.retain(|path, _| *path != link && !path.starts_with(&below));
+        }
+    }
+    let (source, dest) = match options.direction {
+        Direction::Upload => (&local, &remote_files),
+        Direction::Download => (&remote_files, &local),
+    };
+    let changes = if options.checksum {
+        // Only files of the same size need their contents compared.
+        let same_size = source
+            .iter()
+            .filter(|(path, node)| {
+                !node.is_dir && dest.get(*path).is_some_and(|other| other.size == node.size)
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        let local_sums = local_checksums(local_root, same_size.clone(), &mut skipped).await?;
+        let remote_sums = remote_checksums(remote, remote_root, same_size).await?;
+        // A local file that could not be read is left as it is, either way.
+        compare(source, dest, options.delete, |path, source, dest| {
+            source.size != dest.size
+                || match local_sums.get(path) {
+                    Some(Some(sum)) => remote_sums.get(path) != Some(sum),
+                    Some(None) => false,
+                    None => true,
+                }
+        })
+    } else {
+        compare(source, dest, options.delete, quick_check)
+    };
+    Ok(Plan {
+        changes,
+        skipped,
+        local_root: local_root.to_path_buf(),
+        remote_root: remote_root.trim_end_matches('/').to_string(),
+    })
+}
+
+/// Carries out `plan`, calling `progress` before each change, and returns
+/// how many changes were made along with the ones that failed.
+pub(crate) async fn apply(
+    sftp: &SftpSession,
+    remote: &Remote,
+    plan: &Plan,
+    options: &SyncOptions,
+    settings: TransferSettings,
+    progress: impl Fn(&Change),
+) -> Result<Summary> {
+    // Times have to come across for the next quick check to pass.
+    let settings = TransferSettings {
+        preserve: true,
+        ..settings
+    };
+    let overwrite = ConflictResolver::new(ConflictPolicy::Overwrite, None);
+    let limit = Limit::new(options.direction, None);
+    let (local_root, remote_root) = (plan.local_root.as_path(), plan.remote_root.as_str());
+    let remote_path = |path: &str| format!("{remote_root}/{path}");
+    let local_path = |path: &str| local_root.join(path);
+    let mut summary = Summary::default();
+
+    match options.direction {
+        Direction::Upload if !sftp.try_exists(remote_root).await? => {
+            sftp.create_dir(remote_root).await?;
+        }
+        Direction::Download => tokio::fs::create_dir_all(local_root).await?,
+        Direction::Upload => {}
+    }
+
+    let delta = options.delta && rsync_available(remote).await;
+    if options.delta && !delta {
+        info!("rsync is missing on one end, sending whole files");
+    }
+    let mut by_rsync = Vec::new();
+
+    for change in &plan.changes {
+        if delta && change.action == Action::Update {
+            by_rsync.push(change);
+            continue;
+        }
+        progress(change);
+        let result = match (options.direction, change.action, change.is_dir) {
+            (Direction::Upload, Action::Delete, true) => {
+                let removed = super::remove_dir_recursive(
+                    sftp,
+                    &remote_path(&change.path),
+                    SymlinkPolicy::Preserve,
+                )
+                .await?;
+                summary.skipped.extend(removed.skipped);
+                Ok(())
+            }
+            (Direction::Upload, Action::Delete, false) => sftp
+                .remove_file(remote_path(&change.path))
+                .await
+                .map_err(Into::into),
+            (Direction::Upload, _, true) => sftp
+                .create_dir(remote_path(&change.path))
+                .await
+                .map_err(Into::into),
+            (Direction::Upload, _, false) => super::upload_file(
+                sftp,
+                &local_path(&change.path),
+                &remote_path(&change.path),
+                settings,
+                &limit,
+                &overwrite,
+            )
+            .await
+            .map(drop),
+            (Direction::Download, Action::Delete, true) => {
+                tokio::fs::remove_dir_all(local_path(&change.path))
+                    .await
+                    .map_err(Into::into)
+            }
+            (Direction::Download, Action::Delete, false) => {
+                tokio::fs::remove_file(local_path(&change.path))
+                    .await
+                    .map_err(Into::into)
+            }
+            (Direction::Download, _, true) => tokio::fs::create_dir_all(local_path(&change.path))
+                .await
+                .map_err(Into::into),
+            (Direction::Download, _, false) => super::download_file(
+                sftp,
+                &remote_path(&change.path),
+                &local_path(&change.path),
+                settings,
+                &limit,
+                &overwrite,
+            )
+            .await
+            .map(drop),
+        };
+        match result {
+            Ok(()) => summary.done += 1,
+            Err(error) => summary.skip(change.path.clone(), error),
+        }
+    }
+
+    if delta && !by_rsync.is_empty() {
+        by_rsync.iter().for_each(|change| progress(change));
+        let paths = by_rsync.iter().map(|change| change.path.clone()).collect();
+        match rsync(
+            remote,
+            options.direction,
+            local_root,
+            &remote_path(""),
+            paths,
+        )
+        .await
+        {
+            Ok(()) => summary.done += by_rsync.len(),
+            Err(error) => summary.skip("", error),
+        }
+    }
+    Ok(summary)
+}
+
+/// Whether `rsync` can be run both here and on the remote host.
+async fn rsync_available(remote: &Remote) -> bool {
+    if cfg!(not(unix)) {
+        return false;
+    }
+    let local = Command::new("rsync")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    let remote = match remote.exec("command -v rsync").await {
+        Ok(command) => command
+            .output()
+            .await
+            .is_ok_and(|output| output.status == Some(0)),
+        Err(_) => false,
+    };
+    local && remote
+}
+
+/// Sends `paths`, relative to both roots, with `rsync`, so that only the
+/// blocks that changed cross the network.
+///
+/// rsync's remote shell is this program, started with [`RSYNC_SHELL`]: it
+/// hands the command rsync wants run to a socket served here, which runs it
+/// over the session's connection and relays between the two.
+#[cfg(unix)]
+async fn rsync(
+    remote: &Remote,
+    direction: Direction,
+    local_root: &Path,
+    remote_root: &str,
+    paths: Vec<String>,
+) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let quote = |word: &str| shell_escape::unix::escape(word.into()).to_string();
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    // The socket runs commands over the session, so it lives in a directory
+    // only we can enter, made here so that nobody can have made it first.
+    let dir = std::env::temp_dir().join(format!("filessh-rsync-{}-{nanos:x}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .wrap_err("could not make a directory for rsync")?;
+    let uid = std::fs::metadata(&dir)?.uid();
+    let socket = dir.join("rsync.sock");
+    let listener = match tokio::net::UnixListener::bind(&socket) {
+        Ok(listener) => listener,
+        Err(err) => {
+            let _ = std::fs::remove_dir(&dir);
+            return Err(err).wrap_err("could not listen for rsync");
+        }
+    };
+    let session = remote.clone();
+    let server = tokio::spawn(async move {
+        let (connection, _) = listener.accept().await?;
+        if connection.peer_cred()?.uid() != uid {
+            bail!("the rsync socket was reached by another user");
+        }
+        let mut connection = tokio::io::BufReader::new(connection);
+        let mut command = String::new();
+        connection.read_line(&mut command).await?;
+        let command = command.trim_end();
+        if !is_rsync_server(command) {
+            bail!("refused to run {command:?} for rsync");
+        }
+        let mut channel = session.exec(command).await?.into_channel().into_stream();
+        tokio::io::copy_bidirectional(&mut connection, &mut channel).await?;
+        Ok::<_, color_eyre::Report>(())
+    });
+
+    let shell = format!(
+        "{} {RSYNC_SHELL} {}",
+        quote(&std::env::current_exe()?.display().to_string()),
+        quote(&socket.display().to_string())
+    );
+    // rsync only needs a host name to pass along; the socket decides where
+    // the command runs.
+    let remote = format!("filessh:{remote_root}");
+    let local = format!("{}/", local_root.display());
+    let (from, to) = match direction {
+        Direction::Upload => (local, remote),
+        Direction::Download => (remote, local),
+    };
+    let spawned = tokio::process::Command::new("rsync")
+        .args([
+            "--times",
+            "--perms",
+            "--protect-args",
+            "--from0",
+            "--files-from=-",
+        ])
+        .args(["-e", &shell, &from, &to])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err("could not run rsync");
+    let result = async {
+        let mut child = spawned?;
+        let mut stdin = child.stdin.take().expect("piped");
+        let input: Vec<u8> = paths
+            .iter()
+            .flat_map(|path| path.bytes().chain([0]))
+            .collect();
+        let feed = async move {
+            let written = stdin.write_all(&input).await;
+            drop(stdin);
+            written
+        };
+        let (written, output) = tokio::join!(feed, child.wait_with_output());
+        written?;
+        Ok::<_, color_eyre::Report>(output?)
+    }
+    .await;
+    // What went wrong on the remote side says more than rsync losing its
+    // connection over it.
+    let served = if server.is_finished() {
+        server.await?
+    } else {
+        server.abort();
+        Ok(())
+    };
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_dir(&dir);
+    let output = result?;
+    served?;
+    if !output.status.success() {
+        bail!(
+            "rsync failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Whether `command` is rsync's server side, as rsync asks its remote shell
+/// to run it. With `--protect-args` the paths go over the connection, so the
+/// line holds only options and placeholders, never anything for a shell.
+#[cfg(unix)]
+fn is_rsync_server(command: &str) -> bool {
+    let mut words = command.split(' ');
+    words.next() == Some("rsync")
+        && words.next() == Some("--server")
+        && words.all(|word| {
+            !word.is_empty()
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.,=+".contains(c))
+        })
+}
+
+#[cfg(not(unix))]
+async fn rsync(_: &Remote, _: Direction, _: &Path, _: &str, _: Vec<String>) -> Result<()> {
+    bail!("rsync is only run on unix")
+}
+
+/// Runs as `rsync`'s remote shell, given the socket [`rsync`] listens on and
+/// what rsync passes an `ssh`: the host, then the command. The command goes
+/// to the socket as a line, as `ssh` would join it, and the socket is then
+/// relayed to and from rsync.
+#[cfg(unix)]
+pub(crate) fn rsync_shell(args: &[String]) -> Result<()> {
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+
+    let [socket, _host, command @ ..] = args else {
+        bail!("{RSYNC_SHELL} needs a socket, a host and a command");
+    };
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n", command.join(" ")).as_bytes())?;
+    let mut input = stream.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin().lock(), &mut input);
+        let _ = input.shutdown(Shutdown::Write);
+    });
+    std::io::copy(&mut stream, &mut std::io::stdout().lock())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(entries: &[(&str, Option<(u64, u32)>)]) -> Tree {
+        entries
+            .iter()
+            .map(|(path, file)| {
+                let node = match file {
+                    Some((size, mtime)) => Node {
+                        is_dir: false,
+                        is_link: false,
+                        size: *size,
+                        mtime: Some(*mtime),
+                    },
+                    None => Node {
+                        is_dir: true,
+                        is_link: false,
+                        size: 0,
+                        mtime: None,
+                    },
+                };
+                (path.to_string(), node)
+            })
+            .collect()
+    }
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| {
+                let line = change.to_string();
+                line.split(" (").next().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plans_adds_updates_and_deletes() {
+        let source = tree(&[
+            ("docs", None),
+            ("docs/guide.md", Some((10, 100))),
+            ("index.html", Some((20, 200))),
+            ("same.txt", Some((5, 50))),
+            ("was_dir", Some((1, 1))),
+        ]);
+        let dest = tree(&[
+            ("index.html", Some((20, 100))),
+            ("same.txt", Some((5, 50))),
+            ("old", None),
+            ("old/a", Some((1, 1))),
+            ("old/b", None),
+            ("stale.txt", Some((1, 1))),
+            ("was_dir", None),
+            ("was_dir/x", Some((1, 1))),
+        ]);
+
+        let keep = compare(&source, &dest, false, quick_check);
+        assert_eq!(
+            summary(&keep),
+            [
+                "- was_dir/",
+                "+ docs/",
+                "+ docs/guide.md",
+                "~ index.html",
+                "+ was_dir"
+            ]
+        );
+
+        let delete = compare(&source, &dest, true, quick_check);
+        assert_eq!(
+            summary(&delete),
+            [
+                "- old/",
+                "- stale.txt",
+                "- was_dir/",
+                "+ docs/",
+                "+ docs/guide.md",
+                "~ index.html",
+                "+ was_dir"
+            ]
+        );
+
+        let plan = Plan {
+            changes: delete,
+            ..Default::default()
+        };
+        assert!(
+            plan.describe()
+                .ends_with("3 to add, 1 to update, 3 to delete, 31.0 B to send")
+        );
+    }
+
+    #[test]
+    fn replaces_links_in_the_destination() {
+        let source = tree(&[("config", Some((10, 100))), ("data", None)]);
+        let mut dest = tree(&[("config", Some((10, 100))), ("data", Some((0, 1)))]);
+        for node in dest.values_mut() {
+            node.is_link = true;
+        }
+
+        // Writing through either link would change what it points at.
+        assert_eq!(
+            summary(&compare(&source, &dest, false, quick_check)),
+            ["- config", "- data", "+ config", "+ data/"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_runs_the_rsync_server() {
+        assert!(is_rsync_server(
+            "rsync --server -tpe.LsfxCIvu --from0 --files-from=- . ."
+        ));
+        assert!(!is_rsync_server("rsync --server . ; rm -rf ~"));
+        assert!(!is_rsync_server("rsync --server  ."));
+        assert!(!is_rsync_server("sh -c true"));
+        assert!(!is_rsync_server("rsync --daemon"));
+    }
+}
@@ -0,0 +1,284 @@
+//! A trash on the remote host, laid out as the freedesktop.org Trash
+//! specification describes for a home trash: what is deleted is moved into
+//! `files/`, and a `.trashinfo` file of the same name in `info/` records
+//! where it came from and when. Desktop file managers logged in on the host
+//! see the same trash.
+//!
+//! Only the home trash is used, at `~/.local/share/Trash`, as the remote
+//! `$XDG_DATA_HOME` is not known over SFTP. Entries are moved there with a
+//! rename, so one on another filesystem than the home directory cannot be
+//! trashed and is left where it is.
+use chrono::Local;
+use color_eyre::eyre::{Result, bail};
+use russh_sftp::client::SftpSession;
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use super::{Summary, SymlinkPolicy, split_parent};
+
+/// The `[trash]` section of the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct TrashSettings {
+    /// Whether deleting moves entries to the trash, rather than removing
+    /// them for good.
+    #[serde(default)]
+    pub(crate) default: bool,
+}
+
+/// An entry in the trash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Trashed {
+    /// Its name in `files/`, which its info file shares.
+    pub(crate) name: String,
+    /// The absolute path it was deleted from.
+    pub(crate) original: String,
+    /// When it was deleted, as the info file has it: local time on the
+    /// deleting machine, `2026-10-18T09:41:00`.
+    pub(crate) deleted: String,
+    pub(crate) is_dir: bool,
+    pub(crate) size: u64,
+}
+
+/// The home trash of the remote user.
+pub(crate) struct Trash {
+    home: String,
+}
+
+impl Trash {
+    /// Finds the trash of the user the SFTP session is logged in as. It is
+    /// only created once something is put in it.
+    pub(crate) async fn home(sftp: &SftpSession) -> Result<Self> {
+        let home = sftp.canonicalize(".").await?;
+        Ok(Self { home })
+    }
+
+    pub(crate) fn root(&self) -> String {
+        format!("{}/.local/share/Trash", self.home.trim_end_matches('/'))
+    }
+
+    fn files(&self, name: &str) -> String {
+        format!("{}/files/{name}", self.root())
+    }
+
+    fn info(&self, name: &str) -> String {
+        format!("{}/info/{name}.trashinfo", self.root())
+    }
+
+    /// Moves each of `paths` into the trash.
+    pub(crate) async fn put(&self, sftp: &SftpSession, paths: &[String]) -> Result<Summary> {
+        for dir in [".local", ".local/share", ".local/share/Trash"] {
+            create_dir(sftp, &format!("{}/{dir}", self.home.trim_end_matches('/'))).await?;
+        }
+        for dir in ["files", "info"] {
+            create_dir(sftp, &format!("{}/{dir}", self.root())).await?;
+        }
+        let mut summary = Summary::default();
+        for path in paths {
+            match self.put_one(sftp, path).await {
+                Ok(name) => {
+                    info!(path, name, "Moved to the trash");
+                    summary.done += 1;
+                }
+                Err(error) => summary.skip(path.as_str(), error),
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Claims a name by creating its info file, which fails if the file is
+    /// there already, then moves the entry in under that name.
+    async fn put_one(&self, sftp: &SftpSession, path: &str) -> Result<String> {
+        // Only the parent is resolved: a link is trashed as itself, and has to
+        // be restored to where the link was, not to where it pointed.
+        let (parent, base) = split_parent(path);
+        let parent = sftp.canonicalize(parent).await?;
+        let original = format!("{}/{base}", parent.trim_end_matches('/'));
+        let base = base.to_string();
+        let info = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(&original),
+            Local::now().format("%Y-%m-%dT%H:%M:%S")
+        );
+        let mut counter = 1;
+        let name = loop {
+            let name = match counter {
+                1 => base.clone(),
+                _ => format!("{base}.{counter}"),
+            };
+            let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+            match sftp.open_with_flags(self.info(&name), flags).await {
+                Ok(mut file) => {
+                    file.write_all(info.as_bytes()).await?;
+                    file.shutdown().await?;
+                    break name;
+                }
+                Err(SftpError::Status(status))
+                    if status.status_code == StatusCode::Failure && counter < 1000 =>
+                {
+                    counter += 1;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        };
+        if let Err(error) = sftp.rename(path, self.files(&name)).await {
+            let _ = sftp.remove_file(self.info(&name)).await;
+            bail!("could not move it to the trash, which may be on another filesystem: {error}");
+        }
+        Ok(name)
+    }
+
+    /// What is in the trash, most recently deleted first. Info files whose
+    /// entry is gone, and entries without one, are left out.
+    pub(crate) async fn list(&self, sftp: &SftpSession) -> Result<Vec<Trashed>> {
+        let entries = match sftp.read_dir(format!("{}/info", self.root())).await {
+            Ok(entries) => entries,
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+                return Ok(Vec::new());
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let mut trashed = Vec::new();
+        for entry in entries {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.strip_suffix(".trashinfo") else {
+                continue;
+            };
+            let Ok(text) = sftp.read(format!("{}/info/{file_name}", self.root())).await else {
+                continue;
+            };
+            let Some((original, deleted)) = parse_info(&String::from_utf8_lossy(&text)) else {
+                continue;
+            };
+            let Ok(attributes) = sftp.symlink_metadata(self.files(name)).await else {
+                continue;
+            };
+            trashed.push(Trashed {
+                name: name.to_string(),
+                original,
+                deleted,
+                is_dir: attributes.is_dir(),
+                size: attributes.size.unwrap_or_default(),
+            });
+        }
+        trashed.sort_by(|a, b| b.deleted.cmp(&a.deleted));
+        Ok(trashed)
+    }
+
+    /// Moves `entry` back where it came from, unless something has taken
+    /// its place since.
+    pub(crate) async fn restore(&self, sftp: &SftpSession, entry: &Trashed) -> Result<()> {
+        if sftp.symlink_metadata(entry.original.as_str()).await.is_ok() {
+            bail!(
+                "{} exists again; move it away to restore this",
+                entry.original
+            );
+        }
+        sftp.rename(self.files(&entry.name), entry.original.as_str())
+            .await?;
+        sftp.remove_file(self.info(&entry.name)).await?;
+        info!(entry.original, "Restored from the trash");
+        Ok(())
+    }
+
+    /// Deletes `entry` for good.
+    pub(crate) async fn purge(&self, sftp: &SftpSession, entry: &Trashed) -> Result<Summary> {
+        let path = self.files(&entry.name);
+        let mut summary = Summary::default();
+        if entry.is_dir {
+            summary = super::remove_dir_recursive(sftp, &path, SymlinkPolicy::Preserve).await?;
+        } else {
+            sftp.remove_file(path.as_str()).await?;
+            summary.done += 1;
+        }
+        if summary.skipped.is_empty() {
+            sftp.remove_file(self.info(&entry.name)).await?;
+        }
+        Ok(summary)
+    }
+}
+
+/// Creates the directory `path` unless it exists, private to its owner as
+/// the specification asks for the trash.
+async fn create_dir(sftp: &SftpSession, path: &str) -> Result<()> {
+    if sftp.metadata(path).await.is_ok() {
+        return Ok(());
+    }
+    sftp.create_dir(path).await?;
+    let mut attributes = FileAttributes::empty();
+    attributes.permissions = Some(0o700);
+    sftp.set_metadata(path, attributes).await?;
+    Ok(())
+}
+
+/// Reads the `Path` and `DeletionDate` keys of an info file.
+fn parse_info(text: &str) -> Option<(String, String)> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next()? != "[Trash Info]" {
+        return None;
+    }
+    let (mut path, mut deleted) = (None, String::new());
+    for line in lines {
+        match line.split_once('=') {
+            Some(("Path", value)) => path = decode_path(value),
+            Some(("DeletionDate", value)) => deleted = value.to_string(),
+            _ => {}
+        }
+    }
+    Some((path?, deleted))
+}
+
+/// Escapes a path as the specification asks, like a URL path: bytes other
+/// than unreserved characters and `/` become `%XX`.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn decode_path(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let [first, tail @ ..] = rest {
+        if *first == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(*first);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_files_round_trip_escaped_paths() {
+        let path = "/srv/my files/100%/é.txt";
+        let encoded = encode_path(path);
+        assert_eq!(encoded, "/srv/my%20files/100%25/%C3%A9.txt");
+        assert_eq!(decode_path(&encoded).as_deref(), Some(path));
+        assert_eq!(decode_path("/a%2"), None);
+
+        let info = format!("[Trash Info]\nPath={encoded}\nDeletionDate=2026-10-18T09:41:00\n");
+        assert_eq!(
+            parse_info(&info),
+            Some((path.to_string(), "2026-10-18T09:41:00".to_string()))
+        );
+        assert_eq!(parse_info("Path=/a\n"), None);
+        assert_eq!(parse_info("[Trash Info]\nDeletionDate=x\n"), None);
+    }
+}
@@ -0,0 +1,294 @@
+//! The permission editor, drawn over the file table.
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+
+use crate::transfer::chmod::{ModeSpec, Target, parse_octal};
+
+/// The special bits, in the order of the grid's last row.
+const SPECIAL: [(u32, &str); 3] = [(0o4000, "setuid"), (0o2000, "setgid"), (0o1000, "sticky")];
+const ROWS: [&str; 4] = ["user", "group", "other", "special"];
+
+pub struct ChmodDialog {
+    pub(crate) targets: Vec<Target>,
+    /// The mode for files, or for everything when not recursive.
+    pub(crate) files: ModeSpec,
+    /// The mode for directories, when recursive.
+    pub(crate) dirs: ModeSpec,
+    pub(crate) recursive: bool,
+    /// Whether the cursor is in the directory grid.
+    in_dirs: bool,
+    row: usize,
+    column: usize,
+    /// Octal digits typed so far, applied to the grid under the cursor once
+    /// there are three or four of them.
+    typed: String,
+}
+
+impl ChmodDialog {
+    /// Starts from the mode of the first target. A directory's execute
+    /// bits become `X` for files, so that a recursive change keeps scripts
+    /// runnable without making every file executable.
+    pub fn new(targets: Vec<Target>) -> Self {
+        let (mode, is_dir) = targets.first().map_or((0o644, false), |target| {
+            (target.mode.unwrap_or(0o644) & 0o7777, target.is_dir)
+        });
+        let (files, dirs) = if is_dir {
+            let files = ModeSpec {
+                bits: mode & !0o111,
+                keep_x: mode & 0o111,
+            };
+            (files, ModeSpec::new(mode))
+        } else {
+            // Directories need execute wherever they can be read.
+            (
+                ModeSpec::new(mode),
+                ModeSpec::new(mode | (mode & 0o444) >> 2),
+            )
+        };
+        Self {
+            targets,
+            files,
+            dirs,
+            recursive: false,
+            in_dirs: false,
+            row: 0,
+            column: 0,
+            typed: String::new(),
+        }
+    }
+
+    pub fn can_recurse(&self) -> bool {
+        self.targets.iter().any(|target| target.is_dir)
+    }
+
+    fn grid(&mut self) -> &mut ModeSpec {
+        if self.in_dirs {
+            &mut self.dirs
+        } else {
+            &mut self.files
+        }
+    }
+
+    pub fn move_cursor(&mut self, rows: isize, columns: isize) {
+        self.row = self.row.saturating_add_signed(rows).min(ROWS.len() - 1);
+        self.column = self.column.saturating_add_signed(columns).min(2);
+        self.typed.clear();
+    }
+
+    /// Moves between the file and directory grids, when both are shown.
+    pub fn switch_grid(&mut self) {
+        self.in_dirs = self.recursive && !self.in_dirs;
+        self.typed.clear();
+    }
+
+    pub fn toggle_recursive(&mut self) {
+        self.recursive = self.can_recurse() && !self.recursive;
+        self.in_dirs &= self.recursive;
+    }
+
+    /// Flips the bit under the cursor. An execute bit for files goes round
+    /// off, on, and `X`.
+    pub fn toggle(&mut self) {
+        let (row, column, in_dirs) = (self.row, self.column, self.in_dirs);
+        let bit = bit(row, column);
+        let spec = self.grid();
+        if row < 3 && column == 2 && !in_dirs {
+            if spec.bits & bit != 0 {
+                spec.bits &= !bit;
+                spec.keep_x |= bit;
+            } else if spec.keep_x & bit != 0 {
+                spec.keep_x &= !bit;
+            } else {
+                spec.bits |= bit;
+            }
+        } else {
+            spec.bits ^= bit;
+        }
+        self.typed.clear();
+    }
+
+    /// Adds an octal digit, and sets the grid from the digits once they make
+    /// a whole mode. Until then the grid keeps the mode it had: `6` on its
+    /// own is not `0006`.
+    pub fn type_digit(&mut self, digit: char) {
+        if self.typed.len() == 4 {
+            self.typed.clear();
+        }
+        self.typed.push(digit);
+        self.apply_typed();
+    }
+
+    pub fn backspace(&mut self) {
+        self.typed.pop();
+        self.apply_typed();
+    }
+
+    fn apply_typed(&mut self) {
+        if self.typed.len() >= 3
+            && let Ok(mode) = parse_octal(&self.typed)
+        {
+            *self.grid() = ModeSpec::new(mode);
+        }
+    }
+
+    /// The dialog's text, with the cell under the cursor highlighted.
+    pub fn lines(&self) -> Vec<Line<'static>> {
+        let cursor = Style::default().add_modifier(Modifier::REVERSED);
+        let cell = |spec: &ModeSpec, row: usize, column: usize, in_dirs: bool| {
+            let bit = bit(row, column);
+            let mark = if spec.bits & bit != 0 {
+                if row == 3 {
+                    ['s', 's', 't'][column]
+                } else {
+                    ['r', 'w', 'x'][column]
+                }
+            } else if spec.keep_x & bit != 0 {
+                'X'
+            } else {
+                '-'
+            };
+            let style = if self.row == row && self.column == column && self.in_dirs == in_dirs {
+                cursor
+            } else if mark == '-' {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default().fg(Color::Green)
+            };
+            Span::styled(format!("[{mark}]"), style)
+        };
+        let mut grids = vec![(&self.files, false)];
+        if self.recursive {
+            grids.push((&self.dirs, true));
+        }
+
+        let mut header = vec![Span::raw(format!("{:<9}", ""))];
+        for (_, in_dirs) in &grids {
+            let title = match (self.recursive, in_dirs) {
+                (false, _) => "Mode",
+                (true, false) => "Files",
+                (true, true) => "Directories",
+            };
+            header.push(Span::raw(format!("{title:<14}")));
+        }
+        let mut lines = vec![Line::from(header)];
+        for (row, name) in ROWS.iter().enumerate() {
+            let mut spans = vec![Span::raw(format!("{name:<9}"))];
+            for (spec, in_dirs) in &grids {
+                for column in 0..3 {
+                    spans.push(cell(spec, row, column, *in_dirs));
+                }
+                spans.push(Span::raw(" ".repeat(5)));
+            }
+            lines.push(Line::from(spans));
+        }
+        let special = SPECIAL.map(|(_, name)| name).join(", ");
+        lines.push(Line::from(format!("{:<9}{special}", "")).style(Color::DarkGray));
+        lines.push(Line::raw(""));
+
+        let mut octal = format!("Octal    {}", describe(&self.files));
+        if self.recursive {
+            octal.push_str(&format!(" / {}", describe(&self.dirs)));
+        }
+        if !self.typed.is_empty() {
+            octal.push_str(&format!("   typed {}", self.typed));
+        }
+        lines.push(Line::raw(octal));
+        if self.can_recurse() {
+            let state = if self.recursive { "on" } else { "off" };
+            lines.push(Line::raw(format!("Recurse  {state}")));
+        }
+        lines.push(Line::raw(""));
+        lines.push(
+            Line::raw("Space toggle  0-7 octal  r recurse  Tab grid  Enter apply  Esc cancel")
+                .style(Color::DarkGray),
+        );
+        lines
+    }
+}
+
+/// The bit a grid cell stands for.
+fn bit(row: usize, column: usize) -> u32 {
+    match row {
+        0..3 => 0o400 >> (row * 3 + column),
+        _ => SPECIAL[column].0,
+    }
+}
+
+/// A mode in octal, with `X` bits shown after a `+`.
+fn describe(spec: &ModeSpec) -> String {
+    if spec.keep_x == 0 {
+        format!("{:04o}", spec.bits)
+    } else {
+        format!("{:04o}+X{:03o}", spec.bits, spec.keep_x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialog(mode: u32, is_dir: bool) -> ChmodDialog {
+        ChmodDialog::new(vec![Target {
+            path: "/srv/a".to_string(),
+            mode: Some(mode),
+            is_dir,
+        }])
+    }
+
+    #[test]
+    fn typed_modes_apply_once_whole() {
+        let mut dialog = dialog(0o644, false);
+        dialog.type_digit('7');
+        dialog.type_digit('5');
+        assert_eq!(dialog.files.bits, 0o644);
+        dialog.type_digit('5');
+        assert_eq!(dialog.files.bits, 0o755);
+        dialog.type_digit('1');
+        assert_eq!(dialog.files.bits, 0o7551);
+
+        // Going back below three digits keeps the last whole mode.
+        dialog.backspace();
+        assert_eq!(dialog.files.bits, 0o755);
+        dialog.backspace();
+        assert_eq!(dialog.files.bits, 0o755);
+    }
+
+    #[test]
+    fn a_directory_starts_files_without_execute() {
+        let dir = dialog(0o755, true);
+        assert_eq!(describe(&dir.files), "0644+X111");
+        assert_eq!(describe(&dir.dirs), "0755");
+
+        // Going the other way, directories get execute where they are read.
+        let file = dialog(0o640, false);
+        assert_eq!(describe(&file.dirs), "0750");
+    }
+
+    #[test]
+    fn file_execute_goes_round_off_on_and_x() {
+        let mut dialog = dialog(0o644, false);
+        dialog.move_cursor(0, 2);
+        dialog.toggle();
+        assert_eq!(describe(&dialog.files), "0744");
+        dialog.toggle();
+        assert_eq!(describe(&dialog.files), "0644+X100");
+        dialog.toggle();
+        assert_eq!(describe(&dialog.files), "0644");
+    }
+
+    #[test]
+    fn only_directories_can_recurse() {
+        let mut file = dialog(0o644, false);
+        file.toggle_recursive();
+        assert!(!file.recursive);
+
+        let mut dir = dialog(0o755, true);
+        dir.toggle_recursive();
+        dir.switch_grid();
+        dir.type_digit('7');
+        dir.type_digit('0');
+        dir.type_digit('0');
+        assert_eq!(dir.dirs.bits, 0o700);
+        assert_eq!(describe(&dir.files), "0644+X111");
+    }
+}
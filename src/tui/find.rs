@@ -0,0 +1,103 @@
+//! The results of a find, shown in place of the file table as they arrive.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rat_ftable::selection::RowSelection;
+use rat_ftable::{TableData, TableState};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::text::Span;
+use ratatui::widgets::Widget;
+
+use crate::files::{FileEntry, format_timestamp, human_readable_size};
+
+pub struct FindView {
+    /// The directory searched below.
+    pub(crate) root: String,
+    pub(crate) query: String,
+    /// What was found so far, named by full path.
+    pub(crate) results: Vec<FileEntry>,
+    pub table_state: TableState<RowSelection>,
+    /// Set to stop the search, which is under way while this is `Some`.
+    pub(crate) cancel: Option<Arc<AtomicBool>>,
+    /// Tells this search's results from those of one started before it.
+    pub(crate) id: usize,
+    /// What went wrong along the way, once the search is over.
+    pub(crate) report: Option<String>,
+}
+
+impl FindView {
+    pub(crate) fn new(root: String, query: String, id: usize, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            root,
+            query,
+            results: Vec::new(),
+            table_state: TableState::default(),
+            cancel: Some(cancel),
+            id,
+            report: None,
+        }
+    }
+
+    pub(crate) fn add(&mut self, found: Vec<FileEntry>) {
+        self.results.extend(found);
+        if self.table_state.selected().is_none() && !self.results.is_empty() {
+            self.table_state.select(Some(0));
+        }
+    }
+
+    /// Stops the search, if it is still going. Returns whether it was.
+    pub(crate) fn stop(&mut self) -> bool {
+        match self.cancel.take() {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn selected(&self) -> Option<&FileEntry> {
+        self.results.get(self.table_state.selected()?)
+    }
+}
+
+impl Drop for FindView {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+pub struct FindSlice<'a>(pub &'a [FileEntry], pub &'a str);
+
+impl<'a> TableData<'a> for FindSlice<'a> {
+    fn rows(&self) -> usize {
+        self.0.len()
+    }
+
+    fn render_cell(
+        &self,
+        ctx: &rat_ftable::TableContext,
+        column: usize,
+        row: usize,
+        area: Rect,
+        buf: &mut Buffer,
+    ) {
+        let entry = &self.0[row];
+        let span = match column {
+            0 => {
+                let marker = if ctx.selected_row { "> " } else { "  " };
+                let path = entry.name.strip_prefix(self.1).unwrap_or(&entry.name);
+                let path = path.trim_start_matches('/');
+                let slash = if entry.is_dir() { "/" } else { "" };
+                Span::from(format!("{marker}{path}{slash}"))
+            }
+            1 if !entry.is_dir() => Span::from(human_readable_size(
+                entry.attributes.size.unwrap_or_default(),
+            )),
+            2 => Span::from(format_timestamp(entry.attributes.mtime).unwrap_or_default()),
+            _ => return,
+        };
+        span.render(area, buf);
+    }
+}
@@ -0,0 +1,182 @@
+//! The transfer history view, shown in place of the file table.
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, bail};
+use rat_ftable::selection::RowSelection;
+use rat_ftable::{TableData, TableState};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use ratatui::text::Span;
+use ratatui::widgets::Widget;
+
+use super::{AppEvent, Config};
+use crate::files::{JoinablePaths, human_readable_size};
+use crate::transfer::filter::PathFilter;
+use crate::transfer::history::{self, Kind, Outcome, Record, split_remote};
+
+pub struct HistoryView {
+    pub(crate) records: Vec<Record>,
+    pub table_state: TableState<RowSelection>,
+}
+
+impl HistoryView {
+    pub fn load() -> Result<Self> {
+        let records = history::load()?;
+        let mut table_state = TableState::default();
+        if !records.is_empty() {
+            table_state.select(Some(0));
+        }
+        Ok(Self {
+            records,
+            table_state,
+        })
+    }
+
+    pub(crate) fn selected(&self) -> Option<&Record> {
+        self.records.get(self.table_state.selected()?)
+    }
+}
+
+/// The event that runs `record` again, the same way round.
+pub(crate) fn rerun(record: &Record, cfg: &Config) -> Result<AppEvent> {
+    same_host(record, cfg)?;
+    let event = match record.kind {
+        Kind::Download => download(&record.source, &record.destination, record.is_dir, cfg)?,
+        Kind::Archive => {
+            // A finished archive is recorded by the file it was saved as.
+            let local = Path::new(&record.destination);
+            let local = if local.is_file() {
+                local.parent().unwrap_or(local)
+            } else {
+                local
+            };
+            AppEvent::DownloadArchive(record.archive(), local.to_path_buf(), None)
+        }
+        Kind::Upload => AppEvent::Upload(
+            PathBuf::from(&record.source),
+            record.destination.clone(),
+            None,
+        ),
+    };
+    Ok(event)
+}
+
+/// The event that sends what `record` moved back where it came from.
+pub(crate) fn reverse(record: &Record, cfg: &Config) -> Result<AppEvent> {
+    same_host(record, cfg)?;
+    if record.outcome == Outcome::Failed {
+        bail!("This transfer failed, there is nothing to send back");
+    }
+    let event = match record.kind {
+        Kind::Download => AppEvent::Upload(
+            PathBuf::from(&record.destination),
+            record.source.clone(),
+            None,
+        ),
+        Kind::Archive => {
+            let local = Path::new(&record.destination);
+            let Some(name) = local.file_name().filter(|_| local.is_file()) else {
+                bail!("The archive was not saved, there is nothing to send back");
+            };
+            AppEvent::Upload(
+                local.to_path_buf(),
+                record.archive().parent.join(&name.to_string_lossy()),
+                None,
+            )
+        }
+        Kind::Upload => download(&record.destination, &record.source, record.is_dir, cfg)?,
+    };
+    Ok(event)
+}
+
+fn download(remote: &str, local: &str, is_dir: bool, cfg: &Config) -> Result<AppEvent> {
+    let local = PathBuf::from(local);
+    if is_dir {
+        let download = &cfg.settings.download;
+        let filter = PathFilter::new(&download.include, &download.exclude)?;
+        return Ok(AppEvent::DownloadFolder(
+            remote.to_string(),
+            local,
+            filter,
+            None,
+        ));
+    }
+    let (_, name) = split_remote(remote);
+    Ok(AppEvent::DownloadFile(
+        remote.to_string(),
+        local,
+        Some(name),
+        None,
+    ))
+}
+
+fn same_host(record: &Record, cfg: &Config) -> Result<()> {
+    if record.host != cfg.cli.host {
+        bail!(
+            "This transfer was with {}, not {}; connect there to repeat it",
+            record.host,
+            cfg.cli.host
+        );
+    }
+    Ok(())
+}
+
+pub struct HistorySlice<'a>(pub &'a [Record]);
+
+impl<'a> TableData<'a> for HistorySlice<'a> {
+    fn rows(&self) -> usize {
+        self.0.len()
+    }
+
+    fn render_cell(
+        &self,
+        ctx: &rat_ftable::TableContext,
+        column: usize,
+        row: usize,
+        area: Rect,
+        buf: &mut Buffer,
+    ) {
+        let record = &self.0[row];
+        let span = match column {
+            0 => Span::from(
+                chrono::DateTime::from_timestamp(record.started as i64, 0)
+                    .map(|started| {
+                        started
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default(),
+            ),
+            1 => Span::from(match record.kind {
+                Kind::Download => "↓ get",
+                Kind::Archive => "↓ tar",
+                Kind::Upload => "↑ put",
+            }),
+            2 => {
+                let marker = if ctx.selected_row { "> " } else { "  " };
+                let source = match record.names.as_slice() {
+                    [] => record.source.clone(),
+                    names => record.source.join(&format!("{{{}}}", names.join(","))),
+                };
+                Span::from(format!("{marker}{source} → {}", record.destination))
+            }
+            3 => Span::from(human_readable_size(record.bytes)),
+            4 => Span::from(format!("{:.1}s", record.duration_ms as f64 / 1000.0)),
+            5 => match record.outcome {
+                Outcome::Done => Span::styled("done", Color::Green),
+                Outcome::Partial => Span::styled(
+                    format!("partial: {}", record.error.as_deref().unwrap_or_default()),
+                    Color::Yellow,
+                ),
+                Outcome::Failed => Span::styled(
+                    format!("failed: {}", record.error.as_deref().unwrap_or_default()),
+                    Color::Red,
+                ),
+            },
+            _ => return,
+        };
+        span.render(area, buf);
+    }
+}
@@ -3,13 +3,41 @@ use crate::files::FileEntry;
 use crate::files::JoinablePaths;
 use crate::files::MetadataSlice;
 use crate::files::ProgressDataSlice;
+use crate::files::{format_timestamp, human_readable_size};
 use crate::par_dir_traversal::WalkParallel;
 use crate::par_dir_traversal::WalkState;
 use crate::patched_line_gauge::LineGauge;
+use crate::sort::{SortKey, SortOrder};
 use crate::ssh::Session;
+use crate::transfer;
+use crate::transfer::archive::{Archive, OnProgress};
+use crate::transfer::chmod::{self, Target};
+use crate::transfer::conflict::{
+    Ask, ConflictChoice, ConflictPolicy, ConflictPrompt, ConflictResolver, Stat,
+};
+use crate::transfer::copy::{self, Copier};
+use crate::transfer::delete::{self, Preview};
+use crate::transfer::filter::PathFilter;
+use crate::transfer::find::{self, Query};
+use crate::transfer::history::{Kind as TransferKind, Transfer};
+use crate::transfer::journal::{self, Change, Journal, Kind as CreatedKind, Record};
+use crate::transfer::merge;
+use crate::transfer::open;
+use crate::transfer::owners::{self, Owners};
+use crate::transfer::prompt::{CopyPrompt, LinkPrompt, OwnerPrompt, SyncPrompt, TransferPrompt};
+use crate::transfer::ratelimit::{Direction as TransferDirection, Limit};
+use crate::transfer::rename::{self, Plan as RenamePlan};
+use crate::transfer::save::{self, Content, Edit, EditConflict};
+use crate::transfer::sync::{self, SyncOptions};
+use crate::transfer::trash::Trash;
+use crate::transfer::{Summary, SymlinkPolicy, TransferSettings};
 
 use super::AppEvent;
 use super::Global;
+use super::chmod::ChmodDialog;
+use super::find::{FindSlice, FindView};
+use super::history::{self, HistorySlice, HistoryView};
+use super::trash::{TrashSlice, TrashView};
 
 use color_eyre::Report as Error;
 use color_eyre::eyre;
@@ -67,14 +95,15 @@ use ratatui::widgets::StatefulWidget;
 use ratatui::widgets::Widget;
 use ratatui::widgets::block;
 use russh_sftp::client::SftpSession;
-use russh_sftp::protocol::FileType;
+use russh_sftp::protocol::OpenFlags;
 use std::borrow::Cow;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::f64;
 use std::io::stdout;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
@@ -88,6 +117,7 @@ use tracing::warn;
 use throbber_widgets_tui::ThrobberState;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
+use tokio::task::AbortHandle;
 
 use tracing::debug;
 use tracing::{error, info};
@@ -95,6 +125,11 @@ use tui_logger::TuiLoggerLevelOutput;
 use tui_logger::TuiLoggerWidget;
 use tui_logger::TuiWidgetState;
 
+/// How many results of a find are shown at once, at most, and how long the
+/// first of them waits for the rest.
+const FIND_BATCH: usize = 256;
+const FIND_BATCH_WAIT: Duration = Duration::from_millis(200);
+
 const CHARSET: symbols::line::Set = symbols::line::Set {
     top_left: "#",
     top_right: "#",
@@ -126,25 +161,76 @@ pub struct MainUI {
     pub download_progress: f64,
     pub next_five_files: VecDeque<FileEntry>,
     pub throbber_cancel: Option<Cancel>,
+    /// What the progress pane counts: downloaded or deleted entries.
+    pub progress_label: &'static str,
+    /// Set to stop the deletion under way.
+    pub delete_cancel: Option<Arc<AtomicBool>>,
     pub effects: EffectManager<()>,
     pub elapsed: Instant,
     pub details_para_state: ParagraphState,
     pub detail_window_mode: DetailWindowMode,
     pub current_file_content: Option<String>,
+    /// The file shown in the content pane.
+    pub content_path: Option<String>,
+    /// An edit waiting on what to do about changes made on the server.
+    pub edit_conflict: Option<EditConflict>,
     pub in_multi_key_combo_new: bool,
     pub in_editor: bool,
     pub hidden_files: bool,
+    /// How the file list is sorted.
+    pub sort: SortOrder,
+    pub pending_conflicts: VecDeque<ConflictPrompt>,
+    /// Past transfers, shown in place of the file table while open.
+    pub history: Option<HistoryView>,
+    /// The trash browser, shown in place of the file table while open.
+    pub trash: Option<TrashView>,
+    /// The results of a find, shown in place of the file table while open.
+    pub find: Option<FindView>,
+    /// How many finds were started, to tell their results apart.
+    pub finds: usize,
+    /// The permission editor, drawn over the file table while open.
+    pub chmod: Option<ChmodDialog>,
+    /// The remote host's user and group names.
+    pub owners: Owners,
+    /// A bulk rename waiting to be confirmed, drawn over the file table.
+    pub rename: Option<RenamePlan>,
+    /// What this session changed, for undoing.
+    pub journal: Journal,
+    /// The tasks watching opened files for saves, by cached copy.
+    pub watchers: HashMap<PathBuf, AbortHandle>,
+    /// Saved copies of opened files, each with the remote path it came from,
+    /// waiting to be offered for upload.
+    pub pending_uploads: VecDeque<(PathBuf, String)>,
+    /// Names of the marked entries in the current directory.
+    pub marked: HashSet<String>,
+    /// The rest of a batch of transfers, each started once the one before it
+    /// finishes.
+    pub queued: VecDeque<AppEvent>,
+    /// The entry to select once the next listing arrives.
+    pub select_after: Option<String>,
 }
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum InputMode {
     #[default]
     Filter,
     DownloadPath,
+    ArchivePath,
+    UploadPath,
+    SyncPath,
     ConfirmDelete,
+    ConfirmTrash,
+    ConfirmRename,
     MoveEntry,
-    _CopyEntry,
+    CopyEntry,
+    ChangeOwner,
     CreateNewFile,
     CreateNewFolder,
+    CreateSymlink,
+    ResolveConflict,
+    UploadBack,
+    ResolveEdit,
+    SelectGlob,
+    Find,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -187,14 +273,33 @@ impl MainUI {
             downloaded_files: 0,
             next_five_files: VecDeque::new(),
             throbber_cancel: None,
+            progress_label: "Downloaded",
+            delete_cancel: None,
             effects,
             elapsed: Instant::now(),
             details_para_state: ParagraphState::default(),
             detail_window_mode: DetailWindowMode::default(),
             current_file_content: None,
+            content_path: None,
+            edit_conflict: None,
             in_multi_key_combo_new: false,
             in_editor: false,
             hidden_files: false,
+            sort: SortOrder::default(),
+            pending_conflicts: VecDeque::new(),
+            history: None,
+            trash: None,
+            find: None,
+            finds: 0,
+            chmod: None,
+            owners: Owners::default(),
+            rename: None,
+            journal: Journal::default(),
+            watchers: HashMap::new(),
+            pending_uploads: VecDeque::new(),
+            marked: HashSet::new(),
+            queued: VecDeque::new(),
+            select_after: None,
         }
     }
 
@@ -218,6 +323,72 @@ impl MainUI {
             Cow::Borrowed(entries.as_slice())
         }
     }
+
+    /// The marked entries, shown or not, or the one under the cursor when
+    /// none are marked. A filter typed after marking hides entries from view,
+    /// but does not unmark them.
+    pub fn targets(&self) -> Vec<FileEntry> {
+        if self.marked.is_empty() {
+            let selected = self.table_state.selected();
+            selected
+                .and_then(|idx| self.get_file_entries().get(idx).cloned())
+                .into_iter()
+                .collect()
+        } else {
+            self.current_file_entries
+                .iter()
+                .filter(|entry| self.marked.contains(entry.name()))
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// The count and size of the marked entries, for the status line.
+    fn marked_status(&self) -> Control<AppEvent> {
+        let message = if self.marked.is_empty() {
+            String::new()
+        } else {
+            describe_entries(&self.targets())
+        };
+        Control::Event(AppEvent::Status(0, message))
+    }
+}
+
+/// How many `entries` there are and how big, such as `3 selected (1
+/// directory), 12.0 KB`. Directories count towards the number only, as their
+/// size is not known without walking them.
+fn describe_entries(entries: &[FileEntry]) -> String {
+    let dirs = entries.iter().filter(|entry| entry.is_dir()).count();
+    let size: u64 = entries
+        .iter()
+        .filter(|entry| !entry.is_dir())
+        .filter_map(|entry| entry.attributes.size)
+        .sum();
+    let dirs = match dirs {
+        0 => String::new(),
+        1 => " (1 directory)".to_string(),
+        dirs => format!(" ({dirs} directories)"),
+    };
+    format!(
+        "{} selected{dirs}, {}",
+        entries.len(),
+        human_readable_size(size)
+    )
+}
+
+/// What a prompt acts on: the marked entries, summarised, or the entry under
+/// the cursor.
+fn prompt_subject(state: &MainUI) -> String {
+    if state.marked.is_empty() {
+        let current_item = state.table_state.selected_checked().unwrap_or_default();
+        state
+            .get_file_entries()
+            .get(current_item)
+            .map(|file| format!("[{}/{}]", state.current_path, file.name()))
+            .unwrap_or_default()
+    } else {
+        format!("[{}]", describe_entries(&state.targets()))
+    }
 }
 
 pub fn render(
@@ -327,8 +498,8 @@ pub fn render(
         };
         let throbber = Throbber::default()
             .label(format!(
-                "Downloaded {:.0}/{}  ",
-                state.downloaded_files, state.total_files_to_download
+                "{} {:.0}/{}  ",
+                state.progress_label, state.downloaded_files, state.total_files_to_download
             ))
             .throbber_set(throbber_widgets_tui::ASCII);
 
@@ -370,7 +541,12 @@ pub fn render(
             keybind("Tab", "Focus  "),
             keybind("h/j/k/l", "Navigate Table  "),
             keybind("d", "Download  "),
+            keybind("z", "Archive  "),
+            keybind("u", "Upload  "),
+            keybind("s", "Sync  "),
             keybind("f", "Filter  "),
+            keybind("F", "Find  "),
+            keybind("N/S/M/E/P", "Sort  "),
         ]
         .iter()
         .flatten()
@@ -378,9 +554,19 @@ pub fn render(
         .collect::<Vec<_>>();
         let hints_2 = [
             keybind("Enter", "View Content  "),
+            keybind("Space", "Mark  "),
             keybind("x", "Delete  "),
+            keybind("T", "Trash  "),
             keybind("m", "Move  "),
-            keybind("Enter e", "Edit  "),
+            keybind("r", "Rename All  "),
+            keybind("p", "Permissions  "),
+            keybind("O", "Owner  "),
+            keybind("c", "Copy  "),
+            keybind("o", "Open  "),
+            keybind("t", "History  "),
+            keybind("L", "Link Target  "),
+            keybind("e", "Edit  "),
+            keybind("C-z/C-y", "Undo/Redo  "),
         ]
         .iter()
         .flatten()
@@ -454,31 +640,63 @@ pub fn render(
     }
     let input_block_title = match state.input_mode {
         InputMode::Filter => "[3] Filter".to_string(),
-        InputMode::DownloadPath => {
-            let current_item = state.table_state.selected_checked().unwrap_or_default();
-            let file = state.get_file_entries()[current_item].clone();
-
-            format!(
-                "[3] Download [{}/{}] to Path",
-                state.current_path,
-                file.name()
-            )
-        }
-        InputMode::ConfirmDelete => {
-            let current_item = state.table_state.selected_checked().unwrap_or_default();
-            let file = state.get_file_entries()[current_item].clone();
-
-            format!("[3] rm -rf [{}/{}]", state.current_path, file.name())
-        }
-        InputMode::MoveEntry => {
-            let current_item = state.table_state.selected_checked().unwrap_or_default();
-            let file = state.get_file_entries()[current_item].clone();
-
-            format!("[3] mv [{}/{}] to Path", state.current_path, file.name())
+        InputMode::DownloadPath => format!(
+            "[3] Download {} to Path [-x EXCLUDE] [-i INCLUDE] [-l RATE]",
+            prompt_subject(state)
+        ),
+        InputMode::ArchivePath => format!(
+            "[3] Download {} as an archive into Path [-l RATE]",
+            prompt_subject(state)
+        ),
+        InputMode::UploadPath => format!(
+            "[3] Upload local path into [{}/] [-l RATE]",
+            state.current_path
+        ),
+        InputMode::SyncPath => format!(
+            "[3] Sync [{}/] with local Path [--pull] [--delete] [-n] [-c] [--delta] [-x EXCLUDE] [-i INCLUDE]",
+            state.current_path
+        ),
+        InputMode::ConfirmDelete => format!("[3] rm -rf {}", prompt_subject(state)),
+        InputMode::ConfirmTrash => format!("[3] trash {}", prompt_subject(state)),
+        InputMode::ConfirmRename => format!("[3] mv in [{}/], as edited", state.current_path),
+        InputMode::MoveEntry if !state.marked.is_empty() => {
+            format!("[3] mv {} into Path", prompt_subject(state))
         }
+        InputMode::MoveEntry => format!("[3] mv {} to Path", prompt_subject(state)),
+        InputMode::CopyEntry => format!(
+            "[3] cp {} to Path [-r], or nothing for a numbered copy",
+            prompt_subject(state)
+        ),
+        InputMode::ChangeOwner => format!(
+            "[3] chown {} to User[:Group] or :Group [-R]",
+            prompt_subject(state)
+        ),
+        InputMode::SelectGlob => format!("[3] Select in [{}/] by Pattern", state.current_path),
+        InputMode::Find => format!(
+            "[3] Find below [{}/]: Name or /Regex/ [type:f|d|l] [size:+10M] [mtime:-7d]",
+            state.current_path
+        ),
         InputMode::CreateNewFile => format!("[3] touch [{}/] Name", state.current_path),
         InputMode::CreateNewFolder => format!("[3] mkdir [{}/] Name", state.current_path),
-        _ => String::new(),
+        InputMode::CreateSymlink => format!("[3] ln -s Target [{}/]Name", state.current_path),
+        InputMode::ResolveConflict => match state.pending_conflicts.front() {
+            Some(prompt) => format!(
+                "[3] [{}] exists: [o]verwrite [s]kip [k]eep both if [n]ewer if [d]ifferent, Shift for all",
+                prompt.conflict.target
+            ),
+            None => String::new(),
+        },
+        InputMode::ResolveEdit => match &state.edit_conflict {
+            Some(conflict) => format!(
+                "[3] [{}] changed on the server: [o]verwrite [a]bort [m]erge [d]iff",
+                conflict.edit.path
+            ),
+            None => String::new(),
+        },
+        InputMode::UploadBack => match state.pending_uploads.front() {
+            Some((_, remote)) => format!("[3] [{remote}] was saved locally: upload it back [y/n]?"),
+            None => String::new(),
+        },
     };
     let input = TextInput::new().style(ctx.theme.container_base()).block(
             Block::bordered()
@@ -491,7 +709,7 @@ pub fn render(
     input.render(left_bottom, buf, &mut state.input_state);
 
     let files = state.get_file_entries().into_owned();
-    let data = FileDataSlice(&files.clone());
+    let data = FileDataSlice(&files, &state.marked);
 
     let table_style = ctx.theme.table_style();
     let table = Table::<RowSelection>::default()
@@ -507,19 +725,35 @@ pub fn render(
             Constraint::Length(12),
             Constraint::Length(40),
             Constraint::Length(15),
+            Constraint::Length(16),
             Constraint::Length(30),
         ])
         .column_spacing(1)
         .header(rat_ftable::textdata::Row::new([
-            Cell::from("Permissions"),
-            Cell::from(ROUNDED_TOP_LEFT.to_string() + &HORIZONTAL.repeat(3) + "Path"),
-            Cell::from("Size"),
-            Cell::from("Modified At"),
+            Cell::from(state.sort.mark(0, "Permissions")),
+            Cell::from(
+                ROUNDED_TOP_LEFT.to_string() + &HORIZONTAL.repeat(3) + &state.sort.mark(1, "Path"),
+            ),
+            Cell::from(state.sort.mark(2, "Size")),
+            Cell::from("Owner"),
+            Cell::from(state.sort.mark(4, "Modified At")),
         ]))
         .vscroll(Scroll::new())
         .flex(Flex::Start)
         .styles(table_style);
-    table.render(left_top, buf, &mut state.table_state);
+    match (&mut state.history, &mut state.trash, &mut state.find) {
+        (Some(view), _, _) => render_history(left_top, buf, view, ctx),
+        (None, Some(view), _) => render_trash(left_top, buf, view, ctx),
+        (None, None, Some(view)) => render_find(left_top, buf, view, ctx),
+        (None, None, None) => table.render(left_top, buf, &mut state.table_state),
+    }
+    if let Some(dialog) = &state.chmod {
+        let subject = prompt_subject(state);
+        render_chmod(left_top, buf, dialog, &subject, ctx);
+    }
+    if let Some(plan) = &state.rename {
+        render_rename(left_top, buf, plan, ctx);
+    }
 
     Ok(())
 }
@@ -532,6 +766,9 @@ pub fn init(
 ) -> Result<(), Error> {
     let path = state.current_path.clone();
     let cli = ctx.cfg.cli.clone();
+    let host = ctx.cfg.settings.host(&cli.host);
+    state.sort = host.sort.unwrap_or_default();
+    let sftp_server = host.sftp_server;
     let _ = ctx.spawn_async_ext(|chan| async move {
         info!("connecting to {}:{}", cli.host, cli.port);
         let mut ssh = Session::connect(
@@ -542,16 +779,26 @@ pub fn init(
         )
         .await?;
         info!("Connected");
+        if let Some(user) = &cli.sudo {
+            ssh.elevate(user, sftp_server.as_deref()).await?;
+        }
 
         let sftp = ssh.sftp().await?;
+        if ssh.uses_scp() {
+            let message = "No SFTP on this host, using scp".to_string();
+            chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                .await?;
+        }
         let files = sftp.read_dir(path.clone()).await?;
-        let files = files.into_iter().map(FileEntry::from).collect::<Vec<_>>();
+        let mut files = files.into_iter().map(FileEntry::from).collect::<Vec<_>>();
+        FileEntry::resolve_links(&sftp, &path, &mut files).await;
         let full_path = sftp.canonicalize(path).await?;
         chan.send(Ok(Control::Event(AppEvent::UpdateCurrentPath(full_path))))
             .await?;
         chan.send(Ok(Control::Event(AppEvent::UpdateFiles(files))))
             .await?;
-        Ok(Control::Event(AppEvent::AsyncTick(300)))
+        let owners = Owners::load(&sftp, &ssh.remote()).await;
+        Ok(Control::Event(AppEvent::Owners(owners)))
     });
 
     ctx.focus().first();
@@ -565,6 +812,204 @@ pub fn event(
 ) -> Result<Control<AppEvent>, Error> {
     let r = match event {
         AppEvent::Event(event) => {
+            // The permission editor takes every key while it is open, but quitting.
+            if let Some(dialog) = &mut state.chmod
+                && !matches!(event, ct_event!(key press CONTROL-'q'))
+            {
+                let outcome = match event {
+                    ct_event!(key press 'h') | ct_event!(keycode press Left) => {
+                        dialog.move_cursor(0, -1);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'l') | ct_event!(keycode press Right) => {
+                        dialog.move_cursor(0, 1);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'k') | ct_event!(keycode press Up) => {
+                        dialog.move_cursor(-1, 0);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'j') | ct_event!(keycode press Down) => {
+                        dialog.move_cursor(1, 0);
+                        Control::Changed
+                    }
+                    ct_event!(key press ' ') => {
+                        dialog.toggle();
+                        Control::Changed
+                    }
+                    ct_event!(key press 'r') => {
+                        dialog.toggle_recursive();
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Tab) => {
+                        dialog.switch_grid();
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Backspace) => {
+                        dialog.backspace();
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Esc) => {
+                        state.chmod = None;
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Enter) => {
+                        let dirs = dialog.recursive.then_some(dialog.dirs);
+                        let apply = AppEvent::Chmod(dialog.targets.clone(), dialog.files, dirs);
+                        state.chmod = None;
+                        state.marked.clear();
+                        Control::Event(apply)
+                    }
+                    crossterm::event::Event::Key(key)
+                        if key.kind == KeyEventKind::Press
+                            && matches!(key.code, crossterm::event::KeyCode::Char('0'..='7')) =>
+                    {
+                        if let crossterm::event::KeyCode::Char(digit) = key.code {
+                            dialog.type_digit(digit);
+                        }
+                        Control::Changed
+                    }
+                    _ => Control::Unchanged,
+                };
+                return Ok(outcome);
+            }
+            // So does the history.
+            if let Some(view) = &mut state.history
+                && !matches!(event, ct_event!(key press CONTROL-'q'))
+            {
+                let record = view.selected().cloned();
+                let outcome = match event {
+                    ct_event!(key press 'j') | ct_event!(keycode press Down) => {
+                        view.table_state.move_down(1);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'k') | ct_event!(keycode press Up) => {
+                        view.table_state.move_up(1);
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Esc)
+                    | ct_event!(key press 't')
+                    | ct_event!(key press 'q') => {
+                        state.history = None;
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Enter)
+                    | ct_event!(key press 'r')
+                    | ct_event!(key press 'b') => {
+                        let Some(record) = record else {
+                            return Ok(Control::Continue);
+                        };
+                        let again = if matches!(event, ct_event!(key press 'b')) {
+                            history::reverse(&record, &ctx.cfg)
+                        } else {
+                            history::rerun(&record, &ctx.cfg)
+                        };
+                        match again {
+                            Ok(again) => {
+                                state.history = None;
+                                Control::Event(again)
+                            }
+                            Err(err) => Control::Event(AppEvent::Message(err.to_string())),
+                        }
+                    }
+                    ct_event!(key press 'o') => {
+                        let Some(record) = record else {
+                            return Ok(Control::Continue);
+                        };
+                        match transfer::history::reveal(record.local_path()) {
+                            Ok(()) => Control::Unchanged,
+                            Err(err) => Control::Event(AppEvent::Message(format!("{err:#}"))),
+                        }
+                    }
+                    _ => rowselection::handle_events(&mut view.table_state, true, event).into(),
+                };
+                return Ok(outcome);
+            }
+            // And the trash browser.
+            if let Some(view) = &mut state.trash
+                && !matches!(event, ct_event!(key press CONTROL-'q'))
+            {
+                let entry = view.selected().cloned();
+                let confirm_purge = std::mem::take(&mut view.confirm_purge);
+                let outcome = match event {
+                    ct_event!(key press 'j') | ct_event!(keycode press Down) => {
+                        view.table_state.move_down(1);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'k') | ct_event!(keycode press Up) => {
+                        view.table_state.move_up(1);
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Esc)
+                    | ct_event!(key press SHIFT-'T')
+                    | ct_event!(key press 'q') => {
+                        state.trash = None;
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Enter) | ct_event!(key press 'r') => match entry {
+                        Some(entry) => Control::Event(AppEvent::RestoreTrashed(entry)),
+                        None => Control::Continue,
+                    },
+                    ct_event!(key press 'x') => match entry {
+                        Some(entry) => {
+                            view.confirm_purge = true;
+                            let question =
+                                format!("Delete {} for good? Press y to confirm", entry.original);
+                            Control::Event(AppEvent::Status(0, question))
+                        }
+                        None => Control::Continue,
+                    },
+                    ct_event!(key press 'y') if confirm_purge => match entry {
+                        Some(entry) => Control::Event(AppEvent::PurgeTrashed(entry)),
+                        None => Control::Continue,
+                    },
+                    _ => rowselection::handle_events(&mut view.table_state, true, event).into(),
+                };
+                return Ok(outcome);
+            }
+            // And the results of a find.
+            if let Some(view) = &mut state.find
+                && !matches!(event, ct_event!(key press CONTROL-'q'))
+            {
+                let outcome = match event {
+                    ct_event!(key press 'j') | ct_event!(keycode press Down) => {
+                        view.table_state.move_down(1);
+                        Control::Changed
+                    }
+                    ct_event!(key press 'k') | ct_event!(keycode press Up) => {
+                        view.table_state.move_up(1);
+                        Control::Changed
+                    }
+                    // Esc stops a search under way, and closes one that is over.
+                    ct_event!(keycode press Esc) if view.stop() => Control::Changed,
+                    ct_event!(keycode press Esc) | ct_event!(key press 'q') => {
+                        state.find = None;
+                        Control::Changed
+                    }
+                    ct_event!(keycode press Enter) | ct_event!(key press 'l') => {
+                        let Some(found) = view.selected() else {
+                            return Ok(Control::Continue);
+                        };
+                        let Some((parent, name)) = found.name.rsplit_once('/') else {
+                            return Ok(Control::Continue);
+                        };
+                        let parent = if parent.is_empty() {
+                            "/".to_string()
+                        } else {
+                            parent.to_string()
+                        };
+                        state.select_after = Some(name.to_string());
+                        state.find = None;
+                        state.detail_window_mode = DetailWindowMode::Details;
+                        state.current_path = parent.clone();
+                        state.filtered_file_entries.clear();
+                        state.marked.clear();
+                        Control::Event(AppEvent::ChangeDir(parent))
+                    }
+                    _ => rowselection::handle_events(&mut view.table_state, true, event).into(),
+                };
+                return Ok(outcome);
+            }
             if let Some(t) = ctx.focus().focused()
                 && t != state.input_state.focus
             {
@@ -601,6 +1046,12 @@ pub fn event(
                         ctx.focus().focus(&state.input_state);
                         Control::Changed
                     }
+                    ct_event!(key press 'l') => {
+                        state.input_mode = InputMode::CreateSymlink;
+                        state.input_state.clear();
+                        ctx.focus().focus(&state.input_state);
+                        Control::Changed
+                    }
                     _ => {
                         warn!("Not a valid key combination");
                         Control::Continue
@@ -623,6 +1074,18 @@ pub fn event(
                     // on a delete confirmation, focusing the input and typing a
                     // `y` for any reason carried out the delete, unprompted.
                     state.input_mode = InputMode::default();
+                    // Dropping the prompts cancels the operations waiting on them.
+                    state.pending_conflicts.clear();
+                    state.pending_uploads.clear();
+                    state.rename = None;
+                    // Unlike aborting, which says where the edit is kept,
+                    // Esc lets it go.
+                    if let Some(conflict) = state.edit_conflict.take() {
+                        conflict.edit.discard();
+                    }
+                    if let Some(cancel) = state.delete_cancel.take() {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
                     Control::Changed
                 }
                 _ => Control::Continue,
@@ -648,6 +1111,24 @@ pub fn event(
             } else {
                 Control::Continue
             });
+            // A click on a column header sorts by it, or the other way round.
+            if let ct_event!(mouse down Left for column, row) = event
+                && state.history.is_none()
+                && state.trash.is_none()
+                && state.find.is_none()
+                && state
+                    .table_state
+                    .header_area
+                    .contains((*column, *row).into())
+                && let Some(index) = state
+                    .table_state
+                    .column_areas
+                    .iter()
+                    .position(|area| (area.left()..area.right()).contains(column))
+                && let Some(key) = SortKey::for_column(index)
+            {
+                return Ok(resort(state, ctx, state.sort.toggle(key)));
+            }
             try_flow!(state.details_para_state.handle(event, Regular));
 
             try_flow!(match_focus!(
@@ -679,9 +1160,64 @@ pub fn event(
                             state.table_state.move_up(1);
                             Control::Changed
                         }
-                        ct_event!(key press 'x') => {
-                            state.input_mode = InputMode::ConfirmDelete;
-                            state.input_state.set_value("Delete file [Y/n]?".to_string());
+                        ct_event!(key press 'x') | ct_event!(key press SHIFT-'X') => {
+                            // Shift does whichever of trashing and deleting is not the default.
+                            let trash = ctx.cfg.settings.trash.default != matches!(event, ct_event!(key press SHIFT-'X'));
+                            let (verb, end) = if trash { ("Move", " to the trash") } else { ("Delete", "") };
+                            let question = move |size: &str| format!("{verb} {size}{end} [Y/n]?");
+                            let targets = state.targets();
+                            // Directories are counted in the background, and the
+                            // question filled in once they are.
+                            let question = if targets.iter().any(FileEntry::is_dir) {
+                                let names = targets.iter().map(|target| target.name().clone()).collect::<Vec<_>>();
+                                let counting = format!("{} entries, counting…", targets.len());
+                                let sftp = Arc::clone(&state.sftp);
+                                let dir = state.current_path.clone();
+                                let symlinks = ctx.cfg.settings.transfer.symlinks;
+                                ctx.spawn_async_ext(move |_| async move {
+                                    let (entries, _) = delete::plan(&sftp, &dir, &targets, symlinks).await;
+                                    let preview = Preview::of(&entries).to_string();
+                                    Ok(Control::Event(AppEvent::DeletePreview(names, question(&preview))))
+                                });
+                                question(&counting)
+                            } else {
+                                question(&Preview::of(&targets).to_string())
+                            };
+                            state.input_mode = if trash { InputMode::ConfirmTrash } else { InputMode::ConfirmDelete };
+                            state.input_state.set_value(question);
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
+                        ct_event!(key press ' ') => {
+                            if let Some(file) = state.table_state.selected().and_then(|idx| state.get_file_entries().get(idx).cloned())
+                                && !state.marked.remove(file.name())
+                            {
+                                state.marked.insert(file.name().clone());
+                            }
+                            state.table_state.move_down(1);
+                            state.marked_status()
+                        }
+                        ct_event!(key press 'a') => {
+                            let names = state.get_file_entries().iter().map(|entry| entry.name().clone()).collect::<Vec<_>>();
+                            if names.iter().all(|name| state.marked.contains(name)) {
+                                state.marked.clear();
+                            } else {
+                                state.marked.extend(names);
+                            }
+                            state.marked_status()
+                        }
+                        ct_event!(key press 'i') => {
+                            let names = state.get_file_entries().iter().map(|entry| entry.name().clone()).collect::<Vec<_>>();
+                            for name in names {
+                                if !state.marked.remove(&name) {
+                                    state.marked.insert(name);
+                                }
+                            }
+                            state.marked_status()
+                        }
+                        ct_event!(key press 'g') => {
+                            state.input_mode = InputMode::SelectGlob;
+                            state.input_state.clear();
                             ctx.focus().focus(&state.input_state);
                             Control::Changed
                         }
@@ -689,14 +1225,146 @@ pub fn event(
                             state.in_multi_key_combo_new = true;
                             Control::Changed
                         }
+                        ct_event!(key press 'r') => {
+                            // A name with a line break in it cannot be edited as a line.
+                            let names = match state.marked.is_empty() {
+                                true => state.get_file_entries().iter().map(|entry| entry.name().clone()).collect::<Vec<_>>(),
+                                false => state.targets().iter().map(|entry| entry.name().clone()).collect(),
+                            };
+                            let names = names.into_iter().filter(|name| !name.contains('\n')).collect::<Vec<_>>();
+                            if names.is_empty() {
+                                Control::Continue
+                            } else {
+                                Control::Event(AppEvent::EditNames(names))
+                            }
+                        }
                         ct_event!(key press 'm') => {
                             state.input_mode = InputMode::MoveEntry;
                             ctx.focus().focus(&state.input_state);
                             Control::Changed
                         }
+                        ct_event!(key press 'c') => {
+                            state.input_mode = InputMode::CopyEntry;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
+                        ct_event!(key press SHIFT-'O') => {
+                            state.input_mode = InputMode::ChangeOwner;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
+                        ct_event!(key press SHIFT-'L') => {
+                            let Some(file) = state.table_state.selected().and_then(|selected| state.get_file_entries().get(selected).cloned()) else {
+                                return Ok(Control::Continue);
+                            };
+                            let Some(link) = &file.link else {
+                                return Ok(Control::Event(AppEvent::Status(0, format!("{} is not a link", file.name()))));
+                            };
+                            let target = state.current_path.clone().join(link.path.trim_end_matches('/'));
+                            let Some((parent, name)) = target.rsplit_once('/') else {
+                                return Ok(Control::Continue);
+                            };
+                            let parent = if parent.is_empty() { "/".to_string() } else { parent.to_string() };
+                            state.select_after = Some(name.to_string());
+                            state.filtered_file_entries.clear();
+                            state.marked.clear();
+                            let sftp = Arc::clone(&state.sftp);
+                            ctx.spawn_async_ext(|chan| async move {
+                                let parent = sftp.canonicalize(parent).await?;
+                                chan.send(Ok(Control::Event(AppEvent::UpdateCurrentPath(parent.clone())))).await?;
+                                Ok(Control::Event(AppEvent::ChangeDir(parent)))
+                            });
+                            Control::Changed
+                        }
+                        ct_event!(key press SHIFT-'T') => {
+                            let sftp = Arc::clone(&state.sftp);
+                            ctx.spawn_async_ext(|_| async move {
+                                let trashed = Trash::home(&sftp).await?.list(&sftp).await?;
+                                Ok(Control::Event(AppEvent::ShowTrash(trashed)))
+                            });
+                            Control::Continue
+                        }
+                        ct_event!(key press 'p') => {
+                            // `setstat` on a link changes what it points to,
+                            // so links are left out.
+                            let (links, files): (Vec<_>, Vec<_>) = state.targets().into_iter().partition(|file| file.is_symlink());
+                            let targets = files
+                                .iter()
+                                .map(|file| Target {
+                                    path: state.current_path.clone().join(file.name()),
+                                    mode: file.attributes.permissions,
+                                    is_dir: file.is_dir(),
+                                })
+                                .collect::<Vec<_>>();
+                            if targets.is_empty() {
+                                if links.is_empty() {
+                                    Control::Continue
+                                } else {
+                                    Control::Event(AppEvent::Message("Links have no mode of their own; change the entry they point to".to_string()))
+                                }
+                            } else {
+                                state.chmod = Some(ChmodDialog::new(targets));
+                                if links.is_empty() {
+                                    Control::Changed
+                                } else {
+                                    Control::Event(AppEvent::Status(0, format!("Leaving out {} symbolic links", links.len())))
+                                }
+                            }
+                        }
+                        ct_event!(key press 't') => match HistoryView::load() {
+                            Ok(view) => {
+                                state.history = Some(view);
+                                Control::Changed
+                            }
+                            Err(err) => Control::Event(AppEvent::Message(format!("Could not read the transfer history: {err}"))),
+                        },
+                        ct_event!(key press 'o') => {
+                            let Some(file) = state.table_state.selected().and_then(|idx| state.get_file_entries().get(idx).cloned()) else {
+                                return Ok(Control::Continue);
+                            };
+                            if file.is_dir() {
+                                Control::Event(AppEvent::Message("Only files can be opened".to_string()))
+                            } else {
+                                Control::Event(AppEvent::OpenEntry(state.current_path.clone().join(file.name())))
+                            }
+                        }
+                        ct_event!(key press SHIFT-'N') => resort(state, ctx, state.sort.toggle(SortKey::Name)),
+                        ct_event!(key press SHIFT-'S') => resort(state, ctx, state.sort.toggle(SortKey::Size)),
+                        ct_event!(key press SHIFT-'M') => resort(state, ctx, state.sort.toggle(SortKey::Modified)),
+                        ct_event!(key press SHIFT-'E') => resort(state, ctx, state.sort.toggle(SortKey::Extension)),
+                        ct_event!(key press SHIFT-'P') => resort(state, ctx, state.sort.toggle(SortKey::Permissions)),
+                        ct_event!(key press SHIFT-'D') => {
+                            let order = SortOrder { dirs_first: !state.sort.dirs_first, ..state.sort };
+                            resort(state, ctx, order)
+                        }
                         ct_event!(key press 'e') => {
-                            Control::Event(AppEvent::SpawnExternalEditor(state.current_path.clone().join(state.get_file_entries()[state.table_state.selected_checked().unwrap_or_default()].name())))
+                            let Some(file) = state.table_state.selected().and_then(|idx| state.get_file_entries().get(idx).cloned()) else {
+                                return Ok(Control::Continue);
+                            };
+                            if file.is_dir() {
+                                Control::Event(AppEvent::Message("Only files can be edited".to_string()))
+                            } else {
+                                Control::Event(AppEvent::SpawnExternalEditor(state.current_path.clone().join(file.name())))
+                            }
+                        }
+                        ct_event!(key press CONTROL-'z') => {
+                            if let Some(last) = state.journal.last()
+                                && let Some(why) = last.why_irreversible()
+                            {
+                                let message = format!("{} cannot be undone: {why}", last.label);
+                                return Ok(Control::Event(AppEvent::Status(0, message)));
+                            }
+                            match state.journal.undo() {
+                                Some(record) => Control::Event(AppEvent::Revert(record, true)),
+                                None => Control::Event(AppEvent::Status(0, "Nothing to undo".to_string())),
+                            }
                         }
+                        ct_event!(key press CONTROL-'y') => match state.journal.redo() {
+                            Some(record) => Control::Event(AppEvent::Revert(record, false)),
+                            None => Control::Event(AppEvent::Status(0, "Nothing to redo".to_string())),
+                        },
                         ct_event!(key press CONTROL-'o' )  => {
                             return Ok(Control::Event(AppEvent::SpawnSSHCommand));
 
@@ -705,12 +1373,10 @@ pub fn event(
                         if let Some(row_idx) = state.table_state.selected() && let Some(row) = state.get_file_entries().get(row_idx) && row.is_file() {
 
                                 let sftp = Arc::clone(&state.sftp);
-                                let current_path = state.current_path.clone();
-                                let row = row.clone();
-                                ctx.spawn_async_ext(async move |_| {
-                                    let mut file = sftp
-                                        .open(current_path.clone().join(row.name()))
-                                    .await?;
+                                let path = state.current_path.clone().join(row.name());
+                                state.content_path = Some(path.clone());
+                                ctx.spawn_async(async move {
+                                    let mut file = sftp.open(path.as_str()).await?;
                                     let mut buf = Vec::new();
                                     file.read_to_end(&mut buf).await?;
                                     let content = String::from_utf8(buf).ok();
@@ -731,6 +1397,7 @@ pub fn event(
                                 let parent = parent.display();
                                 state.current_path = parent.to_string();
                                 state.filtered_file_entries.clear();
+                                state.marked.clear();
                                 Control::Event(AppEvent::ChangeDir(parent.to_string()))
                             } else {
                                 Control::Continue
@@ -746,10 +1413,11 @@ pub fn event(
                                 let Some(file) = files.get(selected) else {
                                     return Ok(Control::Continue);
                                 };
-                                if file.is_dir() {
+                                if file.leads_to_dir() {
                                     let path = path.join(file.name());
                                     state.current_path = path.display().to_string();
                                     state.filtered_file_entries.clear();
+                                    state.marked.clear();
                                     return Ok(Control::Event(AppEvent::ChangeDir(path.display().to_string())));
                                 }
                             }
@@ -762,29 +1430,57 @@ pub fn event(
 
                             Control::Changed
                         }
+                        ct_event!(key press 'z') => {
+                            state.input_mode = InputMode::ArchivePath;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
+                        ct_event!(key press 'u') => {
+                            state.input_mode = InputMode::UploadPath;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
+                        ct_event!(key press 's') => {
+                            state.input_mode = InputMode::SyncPath;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
                         ct_event!(key press 'f') => {
                             state.input_mode = InputMode::Filter;
                             ctx.focus().focus(&state.input_state);
                             Control::Changed
                         }
+                        ct_event!(key press SHIFT-'F') => {
+                            state.input_mode = InputMode::Find;
+                            state.input_state.clear();
+                            ctx.focus().focus(&state.input_state);
+                            Control::Changed
+                        }
                         _ => Control::Continue
                     }
                 },
                 state.input_state => {
                     match state.input_mode {
-                        InputMode::ConfirmDelete => {
+                        InputMode::ConfirmDelete | InputMode::ConfirmTrash => {
                         try_flow!(
                             match event {
                                 ct_event!(key press 'y') => {
-                                    if let Some(idx) = state.table_state.clone().selected() && let Some(file) = state.get_file_entries().get(idx){
-                                        let file = file.clone();
+                                    let files = state.targets();
+                                    if files.is_empty() {
+                                        Control::Continue
+                                    } else {
+                                        state.marked.clear();
                                         state.input_state.clear();
                                         ctx.focus().first();
-                                        Control::Event(AppEvent::DeleteEntry(file.clone()))
-                                    } else {
-                                        Control::Continue
+                                        if state.input_mode == InputMode::ConfirmTrash {
+                                            Control::Event(AppEvent::TrashEntries(files))
+                                        } else {
+                                            Control::Event(AppEvent::DeleteEntries(files))
+                                        }
                                     }
-
                                 }
                                 ct_event!(key press 'n') => {
                                     state.input_state.clear();
@@ -797,34 +1493,299 @@ pub fn event(
                             }
                         )
                         }
+                        InputMode::ConfirmRename => {
+                            match event {
+                                ct_event!(key press 'y') => {
+                                    let Some(plan) = state.rename.take() else {
+                                        return Ok(Control::Continue);
+                                    };
+                                    state.marked.clear();
+                                    state.input_state.clear();
+                                    state.input_mode = InputMode::default();
+                                    ctx.focus().first();
+                                    return Ok(Control::Event(AppEvent::BulkRename(plan)));
+                                }
+                                ct_event!(key press 'n') => {
+                                    state.rename = None;
+                                    state.input_state.clear();
+                                    state.input_mode = InputMode::default();
+                                    ctx.focus().focus(&state.table_state);
+                                    return Ok(Control::Changed);
+                                }
+                                _ => {}
+                            }
+                        }
                         InputMode::DownloadPath => {
                         match event {
                             ct_event!(keycode press Enter) => {
-                                let path:String = state.input_state.value();
-                                let path = PathBuf::from(path);
+                                let prompt = TransferPrompt::parse(&state.input_state.value::<String>())?;
+                                let path = PathBuf::from(shellexpand::tilde(&prompt.path).as_ref());
                                 std::fs::create_dir_all(path.clone())?;
                                 let path = path.canonicalize()?;
-                                let selected = state.table_state.selected();
-                                if let Some(selected) = selected {
-                                        let files = state.get_file_entries();
-                                    let Some(file) = files.get(selected) else {
-                                        return Ok(Control::Continue);
-                                    };
-                                    let path = path.join(file.name());
-                                    let name = state.current_path.clone().join(file.name());
-                                    if file.is_dir() {
-                                        return Ok(Control::Event(AppEvent::DownloadFolder(name, path)));
-                                    }
-                                    return Ok(Control::Event(AppEvent::DownloadFile(name, path, Some(file.name().clone()))));
+                                let files = state.targets();
+                                let prompt = match files.as_slice() {
+                                    [file] if !file.is_dir() => prompt.without_patterns()?,
+                                    _ => prompt,
+                                };
+                                // Patterns from the prompt add to the configured ones.
+                                let download = &ctx.cfg.settings.download;
+                                let include = [download.include.clone(), prompt.include].concat();
+                                let exclude = [download.exclude.clone(), prompt.exclude].concat();
+                                let filter = PathFilter::new(&include, &exclude)?;
+                                // A batch goes one entry at a time, so that
+                                // directories do not fight over the progress pane.
+                                let mut downloads = files
+                                    .iter()
+                                    .map(|file| {
+                                        let path = path.join(file.name());
+                                        let name = state.current_path.clone().join(file.name());
+                                        if file.is_dir() {
+                                            AppEvent::DownloadFolder(name, path, filter.clone(), prompt.limit)
+                                        } else {
+                                            AppEvent::DownloadFile(name, path, Some(file.name().clone()), prompt.limit)
+                                        }
+                                    })
+                                    .collect::<VecDeque<_>>();
+                                if let Some(first) = downloads.pop_front() {
+                                    state.queued.extend(downloads);
+                                    state.marked.clear();
+                                    return Ok(Control::Event(first));
                                 }
                             }
                             _ => {}
                         }
                         }
+                        InputMode::Find => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let text = state.input_state.value::<String>().trim().to_string();
+                                let query = match Query::parse(&text) {
+                                    Ok(query) => Arc::new(query),
+                                    Err(err) => return Ok(Control::Event(AppEvent::Message(format!("{err:#}")))),
+                                };
+                                state.finds += 1;
+                                let id = state.finds;
+                                let cancel = Arc::new(AtomicBool::new(false));
+                                let root = state.current_path.clone();
+                                state.find = Some(FindView::new(root.clone(), text, id, Arc::clone(&cancel)));
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                ctx.focus().focus(&state.table_state);
+                                let sftp = Arc::clone(&state.sftp);
+                                ctx.spawn_async_ext(move |chan| async move {
+                                    let (found, mut results) = mpsc::unbounded_channel();
+                                    let walk = tokio::spawn(async move { find::find(sftp, &root, query, cancel, found).await });
+                                    // Results go to the view in batches, so that a
+                                    // busy tree does not redraw it for every entry.
+                                    let (mut batch, mut count, mut sent) = (Vec::new(), 0, Instant::now());
+                                    loop {
+                                        match tokio::time::timeout(FIND_BATCH_WAIT, results.recv()).await {
+                                            Ok(Some(entry)) => batch.push(entry),
+                                            Ok(None) => break,
+                                            Err(_) => {}
+                                        }
+                                        if !batch.is_empty() && (batch.len() >= FIND_BATCH || sent.elapsed() >= FIND_BATCH_WAIT) {
+                                            count += batch.len();
+                                            chan.send(Ok(Control::Event(AppEvent::Found(id, std::mem::take(&mut batch).into())))).await?;
+                                            sent = Instant::now();
+                                        }
+                                    }
+                                    count += batch.len();
+                                    if !batch.is_empty() {
+                                        chan.send(Ok(Control::Event(AppEvent::Found(id, batch.into())))).await?;
+                                    }
+                                    let mut summary = walk.await?;
+                                    summary.done = count;
+                                    Ok(Control::Event(AppEvent::FindDone(id, summary.report("Find"))))
+                                });
+                                return Ok(Control::Changed);
+                            }
+                        }
+                        InputMode::SelectGlob => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let pattern = state.input_state.value::<String>();
+                                let pattern = glob::Pattern::new(pattern.trim())
+                                    .map_err(|err| eyre::eyre!("invalid pattern: {err}"))?;
+                                let matching = state
+                                    .get_file_entries()
+                                    .iter()
+                                    .filter(|entry| pattern.matches(entry.name()))
+                                    .map(|entry| entry.name().clone())
+                                    .collect::<Vec<_>>();
+                                state.marked.extend(matching);
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(state.marked_status());
+                            }
+                        }
+                        InputMode::ResolveEdit => {
+                            if let crossterm::event::Event::Key(key) = event
+                                && key.kind == KeyEventKind::Press
+                                && let crossterm::event::KeyCode::Char(c) = key.code
+                                && let Some(conflict) = state.edit_conflict.clone()
+                            {
+                                return Ok(match c {
+                                    'o' => {
+                                        end_edit_conflict(state, ctx);
+                                        Control::Event(AppEvent::SaveEdit(conflict.rebased()))
+                                    }
+                                    'a' => {
+                                        end_edit_conflict(state, ctx);
+                                        let kept = if conflict.merged { "the merge" } else { "your version" };
+                                        let message = format!("{} was not saved; {kept} is kept at {}", conflict.edit.path, conflict.edit.local.display());
+                                        Control::Event(AppEvent::Status(0, message))
+                                    }
+                                    'm' => {
+                                        // Once merged, merging again goes back to the merge as left.
+                                        if !conflict.merged {
+                                            let (Some(base), Some(mine), Some(theirs)) = (&conflict.edit.base, conflict.edit.mine()?, &conflict.theirs) else {
+                                                return Ok(Control::Event(AppEvent::Status(0, too_large_to_compare(&conflict))));
+                                            };
+                                            std::fs::write(&conflict.edit.local, merge::merge(base, &mine, theirs)?)?;
+                                        }
+                                        file_in_editor(state, ctx, &conflict.edit.local)?;
+                                        let merged = std::fs::read(&conflict.edit.local)?;
+                                        if merged.split(|&byte| byte == b'\n').any(|line| line.starts_with(b"<<<<<<< ") || line.starts_with(b">>>>>>> ")) {
+                                            state.edit_conflict = Some(EditConflict { merged: true, ..conflict });
+                                            let message = "The merge still has conflict markers: merge again, or overwrite to save it as it is".to_string();
+                                            return Ok(Control::Event(AppEvent::Status(0, message)));
+                                        }
+                                        end_edit_conflict(state, ctx);
+                                        Control::Event(AppEvent::SaveEdit(conflict.rebased()))
+                                    }
+                                    'd' => {
+                                        let (Some(mine), Some(theirs)) = (conflict.edit.mine()?, &conflict.theirs) else {
+                                            return Ok(Control::Event(AppEvent::Status(0, too_large_to_compare(&conflict))));
+                                        };
+                                        let mut path = conflict.edit.local.clone().into_os_string();
+                                        path.push(".diff");
+                                        std::fs::write(&path, merge::diff(&mine, theirs)?)?;
+                                        let shown = file_in_editor(state, ctx, path.as_ref());
+                                        let _ = std::fs::remove_file(&path);
+                                        shown?;
+                                        Control::Changed
+                                    }
+                                    _ => Control::Unchanged,
+                                });
+                            }
+                        }
+                        InputMode::UploadBack => {
+                            if let crossterm::event::Event::Key(key) = event
+                                && key.kind == KeyEventKind::Press
+                                && let crossterm::event::KeyCode::Char(c) = key.code
+                            {
+                                let upload = match c.to_ascii_lowercase() {
+                                    'y' => true,
+                                    'n' => false,
+                                    _ => return Ok(Control::Unchanged),
+                                };
+                                let answered = state.pending_uploads.pop_front();
+                                show_next_upload(state, ctx);
+                                return Ok(match answered {
+                                    Some((local, remote)) if upload => Control::Event(AppEvent::UploadBack(local, remote)),
+                                    _ => Control::Changed,
+                                });
+                            }
+                        }
+                        InputMode::ResolveConflict => {
+                            if let crossterm::event::Event::Key(key) = event
+                                && key.kind == KeyEventKind::Press
+                                && let crossterm::event::KeyCode::Char(c) = key.code
+                            {
+                                let policy = match c.to_ascii_lowercase() {
+                                    'o' => ConflictPolicy::Overwrite,
+                                    's' => ConflictPolicy::Skip,
+                                    'k' => ConflictPolicy::KeepBoth,
+                                    'n' => ConflictPolicy::OverwriteIfNewer,
+                                    'd' => ConflictPolicy::OverwriteIfSizeDiffers,
+                                    // Keep stray keys out of the summary.
+                                    _ => return Ok(Control::Unchanged),
+                                };
+                                if let Some(prompt) = state.pending_conflicts.pop_front() {
+                                    let _ = prompt.reply.try_send(ConflictChoice {
+                                        policy,
+                                        apply_to_all: c.is_ascii_uppercase(),
+                                    });
+                                }
+                                show_next_conflict(state, ctx);
+                                return Ok(Control::Changed);
+                            }
+                        }
+                        InputMode::ArchivePath => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = TransferPrompt::parse(&state.input_state.value::<String>())?.without_patterns()?;
+                                let local = PathBuf::from(shellexpand::tilde(&prompt.path).as_ref());
+                                std::fs::create_dir_all(&local)?;
+                                let local = local.canonicalize()?;
+                                let names = state.targets().iter().map(|file| file.name().clone()).collect::<Vec<_>>();
+                                if names.is_empty() {
+                                    return Ok(Control::Continue);
+                                }
+                                let archive = Archive {
+                                    parent: state.current_path.clone(),
+                                    names,
+                                };
+                                state.marked.clear();
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(Control::Event(AppEvent::DownloadArchive(archive, local, prompt.limit)));
+                            }
+                        }
+                        InputMode::UploadPath => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = TransferPrompt::parse(&state.input_state.value::<String>())?.without_patterns()?;
+                                let local = PathBuf::from(shellexpand::tilde(&prompt.path).as_ref()).canonicalize()?;
+                                let Some(name) = local.file_name() else {
+                                    return Ok(Control::Continue);
+                                };
+                                let remote = state.current_path.clone().join(&name.to_string_lossy());
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(Control::Event(AppEvent::Upload(local, remote, prompt.limit)));
+                            }
+                        }
+                        InputMode::SyncPath => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = SyncPrompt::parse(&state.input_state.value::<String>())?;
+                                let local = PathBuf::from(shellexpand::tilde(&prompt.path).as_ref());
+                                let download = &ctx.cfg.settings.download;
+                                let include = [download.include.clone(), prompt.include].concat();
+                                let exclude = [download.exclude.clone(), prompt.exclude].concat();
+                                let options = SyncOptions {
+                                    direction: if prompt.pull { TransferDirection::Download } else { TransferDirection::Upload },
+                                    delete: prompt.delete,
+                                    checksum: prompt.checksum,
+                                    filter: PathFilter::new(&include, &exclude)?,
+                                    delta: prompt.delta,
+                                };
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(Control::Event(AppEvent::Sync(local, state.current_path.clone(), options, prompt.dry_run)));
+                            }
+                        }
                         InputMode::MoveEntry => {
                             match event {
+                                ct_event!(keycode press Enter) if !state.marked.is_empty() => {
+                                    // Marked entries all go into the directory given.
+                                    let dir: String = state.current_path.clone().join(&state.input_state.value::<String>());
+                                    for file in state.targets() {
+                                        let old_path = state.current_path.clone().join(file.name());
+                                        ctx.queue_event(AppEvent::MoveEntry(old_path, dir.join(file.name())));
+                                    }
+                                    state.marked.clear();
+                                    ctx.focus().first();
+                                    state.input_state.clear();
+                                    state.input_mode = InputMode::default();
+                                    return Ok(Control::Changed);
+                                }
                                 ct_event!(keycode press Enter) => {
-                                    let old_path = state.current_path.clone().join(state.get_file_entries()[state.table_state.selected_checked().unwrap_or_default()].name());
+                                    let Some(file) = state.targets().pop() else {
+                                        return Ok(Control::Continue);
+                                    };
+                                    let old_path = state.current_path.clone().join(file.name());
                                     let new_path:String = state.current_path.clone().join(&state.input_state.value::<String>());
                                     ctx.focus().first();
                                     state.input_state.clear();
@@ -835,6 +1796,42 @@ pub fn event(
                                 _ => {}
                             }
                         }
+                        InputMode::CopyEntry => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = CopyPrompt::parse(&state.input_state.value::<String>())?;
+                                let dest = state.current_path.clone().join(&prompt.path.unwrap_or_default());
+                                let sources = state
+                                    .targets()
+                                    .iter()
+                                    .map(|file| state.current_path.clone().join(file.name()))
+                                    .collect::<Vec<_>>();
+                                if sources.is_empty() {
+                                    return Ok(Control::Continue);
+                                }
+                                state.marked.clear();
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(Control::Event(AppEvent::CopyEntries(sources, dest, prompt.recursive)));
+                            }
+                        }
+                        InputMode::ChangeOwner => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = OwnerPrompt::parse(&state.input_state.value::<String>())?;
+                                let uid = prompt.user.map(|user| state.owners.uid(&user)).transpose()?;
+                                let gid = prompt.group.map(|group| state.owners.gid(&group)).transpose()?;
+                                let paths = state
+                                    .targets()
+                                    .iter()
+                                    .map(|file| state.current_path.clone().join(file.name()))
+                                    .collect::<Vec<_>>();
+                                state.marked.clear();
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                                return Ok(Control::Event(AppEvent::Chown(paths, uid, gid, prompt.recursive)));
+                            }
+                        }
                         InputMode::CreateNewFile => {
                             match event {
                                 ct_event!(keycode press Enter) => {
@@ -847,11 +1844,19 @@ pub fn event(
                                         let sftp = session.sftp().await?;
                                         let path = sftp.canonicalize(path.clone()).await?;
                                         info!(path, "Creating file");
-                                        let mut file = sftp.create(path.clone()).await?;
-                                        file.write_all(b"").await?;
+                                        // Never over a file already there, which undoing
+                                        // the create would then remove.
+                                        if sftp.symlink_metadata(path.as_str()).await.is_ok() {
+                                            return Err(eyre::eyre!("{path} already exists"));
+                                        }
+                                        let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+                                        let mut file = sftp.open_with_flags(path.clone(), flags).await?;
                                         file.flush().await?;
                                         file.sync_all().await?;
                                         info!(path, "Created file");
+                                        let created = Change::Create { path: path.clone(), kind: CreatedKind::File };
+                                        let record = Record::new(format!("Create {path}"), vec![created]);
+                                        chan.send(Ok(Control::Event(AppEvent::Journal(record)))).await?;
                                         chan.send(Ok(Control::Event(AppEvent::ChangeDir(curr_dir)))).await?;
                                         Ok(Control::Event(AppEvent::AsyncTick(300)))
                                     });
@@ -861,6 +1866,26 @@ pub fn event(
                                 _ => {}
                             }
                         }
+                        InputMode::CreateSymlink => {
+                            if let ct_event!(keycode press Enter) = event {
+                                let prompt = LinkPrompt::parse(&state.input_state.value::<String>())?;
+                                let curr_dir = state.current_path.clone();
+                                let link = curr_dir.join(&prompt.name);
+                                let sftp = Arc::clone(&state.sftp);
+                                ctx.spawn_async_ext(|chan| async move {
+                                    info!(link, target = prompt.target, "Creating symlink");
+                                    transfer::create_remote_symlink(&sftp, &link, &prompt.target).await?;
+                                    let created = Change::Create { path: link.clone(), kind: CreatedKind::Symlink(prompt.target) };
+                                    let record = Record::new(format!("Link {link}"), vec![created]);
+                                    chan.send(Ok(Control::Event(AppEvent::Journal(record)))).await?;
+                                    chan.send(Ok(Control::Event(AppEvent::ChangeDir(curr_dir)))).await?;
+                                    Ok(Control::Event(AppEvent::AsyncTick(300)))
+                                });
+                                ctx.focus().first();
+                                state.input_state.clear();
+                                state.input_mode = InputMode::default();
+                            }
+                        }
                         InputMode::CreateNewFolder => {
                             match event {
                                 ct_event!(keycode press Enter) => {
@@ -873,6 +1898,9 @@ pub fn event(
                                         let sftp = session.sftp().await?;
                                         let path = sftp.canonicalize(path.clone()).await?;
                                         sftp.create_dir(path.clone()).await?;
+                                        let created = Change::Create { path: path.clone(), kind: CreatedKind::Dir };
+                                        let record = Record::new(format!("Create {path}/"), vec![created]);
+                                        chan.send(Ok(Control::Event(AppEvent::Journal(record)))).await?;
                                         chan.send(Ok(Control::Event(AppEvent::ChangeDir(curr_dir)))).await?;
                                         Ok(Control::Event(AppEvent::AsyncTick(300)))
                                     });
@@ -916,38 +1944,223 @@ pub fn event(
             state.throbber.calc_next();
             Control::Changed
         }
-        AppEvent::SpawnExternalEditor(_) => {
-            if let Some(template) = state.current_file_content.clone() {
-                state.in_editor = true;
-                stdout().execute(crossterm::terminal::LeaveAlternateScreen)?;
-                disable_raw_mode()?;
-                let edited = edit::edit(template)?;
-                let session = Arc::clone(&state.session);
-                let current_path = state.current_path.clone();
-                let current_file = state.get_file_entries()
-                    [state.table_state.selected_checked().unwrap_or_default()]
-                .clone();
-
-                let path = current_path.join(current_file.name());
-                ctx.spawn_async_ext(async move |chan| {
-                    chan.send(Ok(Control::Event(AppEvent::UpdateContent(Some(
-                        edited.clone(),
-                    )))))
-                    .await?;
+        AppEvent::SpawnExternalEditor(path) => {
+            let sftp = Arc::clone(&state.sftp);
+            let path = path.clone();
+            ctx.spawn_async_ext(async move |chan| {
+                chan.send(Ok(Control::Event(AppEvent::Status(
+                    0,
+                    format!("Downloading {path} to edit it"),
+                ))))
+                .await?;
+                let edit = Edit::download(&sftp, &path).await?;
+                Ok(Control::Event(AppEvent::EditDownloaded(edit)))
+            });
+            Control::Changed
+        }
+        AppEvent::EditDownloaded(edit) => {
+            if let Err(error) = file_in_editor(state, ctx, &edit.local) {
+                edit.discard();
+                return Err(error);
+            }
+            if edit.read_as.matches(&edit.local)? {
+                edit.discard();
+                let message = format!("{} is unchanged, and was not saved", edit.path);
+                return Ok(Control::Event(AppEvent::Status(0, message)));
+            }
+            Control::Event(AppEvent::SaveEdit(edit.clone()))
+        }
+        AppEvent::SaveEdit(edit) => {
+            let session = Arc::clone(&state.session);
+            let edit = edit.clone();
+            let backup = ctx.cfg.settings.editor.backup;
+            ctx.spawn_async_ext(async move |chan| {
+                let (sftp, raw) = {
                     let mut session = session.lock().await;
-                    let sftp = session.sftp().await?;
-                    let mut f = sftp.create(path.clone()).await?;
-                    f.write_all(edited.as_bytes()).await?;
+                    (
+                        session.sftp().await?,
+                        session.raw_sftp(save::POSIX_RENAME).await?,
+                    )
+                };
+                let path = edit.path.clone();
+                if let Some((now, theirs)) = edit.read_as.changed(&sftp, &path).await? {
                     sftp.close().await?;
-                    Ok(Control::Event(AppEvent::AsyncTick(300)))
-                });
-                stdout().execute(EnterAlternateScreen)?;
-                enable_raw_mode()?;
-                ctx.terminal().borrow_mut().clear()?;
-                state.in_editor = false;
+                    let conflict = EditConflict {
+                        edit,
+                        theirs,
+                        now,
+                        merged: false,
+                    };
+                    return Ok(Control::Event(AppEvent::EditConflict(conflict)));
+                }
+                let saved = save::save(
+                    &sftp,
+                    raw.as_ref(),
+                    &path,
+                    Content::File(&edit.local),
+                    backup,
+                )
+                .await?;
+                sftp.close().await?;
+                let mine = edit.mine()?;
+                edit.discard();
+                let label = format!("Edit {path}");
+                let record = match (mine.clone(), edit.base) {
+                    (Some(content), Some(old)) => Record::new(
+                        label,
+                        vec![Change::Write {
+                            path: path.clone(),
+                            content,
+                            old,
+                        }],
+                    ),
+                    _ => Record::irreversible(label, "the file was too large to keep what it held"),
+                };
+                chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                    .await?;
+                let content = mine.and_then(|mine| String::from_utf8(mine).ok());
+                chan.send(Ok(Control::Event(AppEvent::EditSaved(
+                    path.clone(),
+                    content,
+                ))))
+                .await?;
+                let mut message = format!("Saved {path}");
+                if let Some(backup) = saved.backup {
+                    message.push_str(&format!(", keeping the old version as {backup}"));
+                }
+                if saved.in_place {
+                    message.push_str(", in place, as its owner could not be kept otherwise");
+                }
+                Ok(Control::Event(AppEvent::Status(0, message)))
+            });
+            Control::Changed
+        }
+        AppEvent::EditSaved(path, content) => {
+            if state.content_path.as_ref() == Some(path)
+                && state.detail_window_mode == DetailWindowMode::Content
+            {
+                state.current_file_content = content.clone();
             }
             Control::Changed
         }
+        AppEvent::EditConflict(conflict) => {
+            let when = format_timestamp(conflict.now.mtime())
+                .unwrap_or_else(|| "an unknown time".to_string());
+            info!(
+                path = conflict.edit.path,
+                when, "Changed on the server while being edited"
+            );
+            state.edit_conflict = Some(conflict.clone());
+            state.input_mode = InputMode::ResolveEdit;
+            state.input_state.clear();
+            ctx.focus().focus(&state.input_state);
+            let message = format!(
+                "{} was changed on the server at {when}, after it was read",
+                conflict.edit.path
+            );
+            Control::Event(AppEvent::Status(0, message))
+        }
+        AppEvent::EditNames(names) => {
+            let edited = in_editor(state, ctx, names.join("\n") + "\n")?;
+            let existing = state
+                .current_file_entries
+                .iter()
+                .map(|entry| entry.name().clone())
+                .collect::<HashSet<_>>();
+            let plan = match RenamePlan::new(names, &edited, &existing) {
+                Ok(plan) => plan,
+                Err(err) => {
+                    return Ok(Control::Event(AppEvent::Message(format!(
+                        "Nothing was renamed: {err}"
+                    ))));
+                }
+            };
+            if plan.renames.is_empty() {
+                let mut summary = Summary::default();
+                for (name, reason) in plan.collisions {
+                    summary.skip(name, reason);
+                }
+                return Ok(Control::Event(match summary.report("Rename") {
+                    Some(report) => AppEvent::Message(report),
+                    None => AppEvent::Status(0, "Nothing to rename".to_string()),
+                }));
+            }
+            let count = plan.renames.len();
+            state.rename = Some(plan);
+            state.input_mode = InputMode::ConfirmRename;
+            state
+                .input_state
+                .set_value(format!("Rename {count} entries as listed [Y/n]?"));
+            ctx.focus().focus(&state.input_state);
+            Control::Changed
+        }
+        AppEvent::BulkRename(plan) => {
+            let sftp = Arc::clone(&state.sftp);
+            let plan = plan.clone();
+            let dir = state.current_path.clone();
+            ctx.spawn_async_ext(|chan| async move {
+                let (mut summary, changes) = rename::apply(&sftp, &dir, &plan.steps).await;
+                for (name, reason) in plan.collisions {
+                    summary.skip(dir.clone().join(&name), reason);
+                }
+                let message = format!("Renamed {} entries", summary.done);
+                if !changes.is_empty() {
+                    let record =
+                        Record::new(format!("Rename {} entries in {dir}", summary.done), changes);
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                }
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Rename") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(dir)))
+            });
+            Control::Changed
+        }
+        AppEvent::Journal(record) => {
+            state.journal.record(record.clone());
+            Control::Continue
+        }
+        AppEvent::Revert(record, undo) => {
+            let sftp = Arc::clone(&state.sftp);
+            let (record, undo) = (record.clone(), *undo);
+            let current_path = state.current_path.clone();
+            ctx.spawn_async_ext(move |chan| async move {
+                let (summary, done) = if undo {
+                    (record.undo(&sftp).await, "Undid")
+                } else {
+                    (record.redo(&sftp).await, "Redid")
+                };
+                let message = format!("{done}: {}", record.label);
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                // Something that went only partly back cannot be trusted to
+                // go back again, so it leaves the journal.
+                match summary.report(if undo { "Undo" } else { "Redo" }) {
+                    Some(report) => {
+                        chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                            .await?
+                    }
+                    None => {
+                        chan.send(Ok(Control::Event(AppEvent::Reverted(record, undo))))
+                            .await?
+                    }
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Changed
+        }
+        AppEvent::Reverted(record, undo) => {
+            if *undo {
+                state.journal.undone(record.clone());
+            } else {
+                state.journal.redone(record.clone());
+            }
+            Control::Continue
+        }
         AppEvent::SpawnSSHCommand => {
             let path = PathBuf::from(state.current_path.clone());
             let cli = ctx.cfg.cli.clone();
@@ -967,8 +2180,14 @@ pub fn event(
             state.in_editor = false;
             Control::Changed
         }
-        AppEvent::DownloadStart => {
+        AppEvent::DownloadStart | AppEvent::DeleteStart => {
             state.is_downloading = true;
+            if matches!(event, AppEvent::DeleteStart) {
+                state.progress_label = "Deleted";
+                state.next_five_files.clear();
+            } else {
+                state.progress_label = "Downloaded";
+            }
             let cancel = ctx.spawn_ext(|cancel, send| {
                 loop {
                     if cancel.is_canceled() {
@@ -983,12 +2202,20 @@ pub fn event(
             state.throbber_cancel = Some(cancel.0);
             Control::Changed
         }
+        AppEvent::ResolveConflict(prompt) => {
+            state.pending_conflicts.push_back(prompt.clone());
+            if state.pending_conflicts.len() == 1 {
+                show_next_conflict(state, ctx);
+            }
+            Control::Changed
+        }
         AppEvent::UpdateContent(content) => {
             state.current_file_content = content.clone();
             Control::Changed
         }
         AppEvent::DownloadEnd => {
             state.is_downloading = false;
+            state.delete_cancel = None;
             if let Some(cancel) = state.throbber_cancel.take() {
                 cancel.cancel();
             }
@@ -1011,46 +2238,408 @@ pub fn event(
             let oldpath = oldpath.clone();
             let newpath = newpath.clone();
             let current_path = state.current_path.clone();
-            ctx.spawn_async_ext(|_| async move {
-                let mut session = session.lock().await;
-                let sftp = session.sftp().await?;
-                let newpath = sftp.canonicalize(newpath.clone()).await.unwrap_or(newpath);
+            let policy = ctx.cfg.settings.transfer.conflict;
+            ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(policy, &chan);
+                let sftp = {
+                    let mut session = session.lock().await;
+                    session.sftp().await?
+                };
+                // Only the parent is resolved, so that a link at the
+                // target is met as itself rather than as what it points to.
+                let (parent, name) = transfer::split_parent(&newpath);
+                let newpath = match sftp.canonicalize(parent).await {
+                    Ok(parent) => parent.join(name),
+                    Err(_) => newpath.clone(),
+                };
                 info!(oldpath, newpath, "Moving");
-                sftp.rename(oldpath, newpath).await?;
+                if let Some(moved) =
+                    transfer::move_entry(&sftp, &oldpath, &newpath, &conflicts).await?
+                {
+                    let label = format!("Move {oldpath} to {moved}");
+                    let renamed = Change::Rename {
+                        from: oldpath,
+                        to: moved,
+                    };
+                    chan.send(Ok(Control::Event(AppEvent::Journal(Record::new(
+                        label,
+                        vec![renamed],
+                    )))))
+                    .await?;
+                }
                 Ok(Control::Event(AppEvent::ChangeDir(current_path)))
             });
             Control::Changed
         }
-        AppEvent::DownloadFile(name, path, filename) => {
-            state.throbber.calc_next();
-            info!(name, path = ?path.display(), filename = ?filename.clone(), "File Details");
+        AppEvent::CopyEntries(sources, dest, recursive) => {
             let session = Arc::clone(&state.session);
-            let path = path.clone();
-            let name = name.clone();
+            let (sources, dest, recursive) = (sources.clone(), dest.clone(), *recursive);
+            let current_path = state.current_path.clone();
+            let settings = ctx.cfg.settings.transfer;
+            ctx.spawn_async_ext(move |chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
+                // One copier for the batch, so its channels are opened once.
+                let (sftp, raw, remote) = {
+                    let mut session = session.lock().await;
+                    let sftp = session.sftp().await?;
+                    let raw = session.raw_sftp(copy::COPY_DATA).await?;
+                    (sftp, raw, session.remote())
+                };
+                let sftp = Arc::new(sftp);
+                let dest = sftp.canonicalize(dest.clone()).await.unwrap_or(dest);
+                let mut copier = Copier::new(sftp, raw, remote, settings);
+                // One failed copy does not stop the rest.
+                let mut summary = Summary::default();
+                for source in sources {
+                    info!(source, dest, recursive, "Copying");
+                    let message = format!("Copying {source}");
+                    chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                        .await?;
+                    match copier.copy(&source, &dest, recursive, &conflicts).await {
+                        Ok(Some((copied, copy))) => {
+                            let message = format!("Copied to {copied}");
+                            chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                                .await?;
+                            summary.done += copy.done;
+                            summary.skipped.extend(copy.skipped);
+                        }
+                        Ok(None) => {}
+                        Err(error) => summary.skip(source, error),
+                    }
+                }
+                if let Some(report) = summary.report("Copy") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Changed
+        }
+        AppEvent::OpenEntry(remote) => {
+            let session = Arc::clone(&state.session);
+            let remote = remote.clone();
+            let local = open::cache_path(&ctx.cfg.cli.host, &remote);
+            let handler = ctx.cfg.settings.open.handler_for(&local);
+            let mut settings = ctx.cfg.settings.transfer;
+            // The cache keeps one copy of each file, fetched afresh on every open.
+            settings.conflict = ConflictPolicy::Overwrite;
+            info!(remote, local = ?local.display(), handler, "Opening");
+            if let Some(watcher) = state.watchers.remove(&local) {
+                watcher.abort();
+            }
+            let watched = local.clone();
 
-            info!(name, path = ?path.display(), "File Details");
-            ctx.spawn_async_ext(|_| async move {
+            let (watcher, _) = ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
                 let sftp = {
                     let mut session = session.lock().await;
                     session.sftp().await?
                 };
-                let mut remote_file = sftp.open(name.clone()).await?;
-                let mut buf = Vec::new();
-                remote_file.read_to_end(&mut buf).await?;
-                info!(len = ?buf.len(), "Read file");
-                if let Some(parent) = path.parent() {
-                    tokio::fs::create_dir_all(parent).await?;
+                let limit = Limit::new(TransferDirection::Download, None);
+                transfer::download_file(&sftp, &remote, &local, settings, &limit, &conflicts)
+                    .await?;
+                open::keep_writable(&local).await?;
+                let mut handler_process = open::launch(&handler, &local)?;
+                let started = Instant::now();
+                let message = format!("Opened {remote} with {handler}");
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+
+                // A save is only offered once the file has held still for a
+                // tick, so that an application writing it in several steps
+                // is asked about once, with the finished file.
+                let mut offered = open::Stamp::of(&local).await;
+                let mut seen = offered;
+                let mut last_change = Instant::now();
+                // Once the handler is gone, so is the application that had
+                // the file open, unless it only passed the file on; then the
+                // watch lasts until the file goes quiet for a while.
+                let mut handler_done = false;
+                let mut closed = false;
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                        _ = handler_process.wait(), if !handler_done => {
+                            handler_done = true;
+                            closed = started.elapsed() >= open::HANDED_OFF;
+                        }
+                    }
+                    let now = open::Stamp::of(&local).await;
+                    if now.is_none() {
+                        return Ok(Control::Continue);
+                    }
+                    if now != seen {
+                        last_change = Instant::now();
+                    }
+                    // A file closed straight after a save is settled already.
+                    if now != offered && (now == seen || closed) {
+                        offered = now;
+                        let changed = AppEvent::CachedCopyChanged(local.clone(), remote.clone());
+                        chan.send(Ok(Control::Event(changed))).await?;
+                    }
+                    seen = now;
+                    if closed || (handler_done && last_change.elapsed() >= open::IDLE_WATCH) {
+                        info!(local = ?local.display(), "No longer watching");
+                        return Ok(Control::Continue);
+                    }
+                }
+            });
+            state.watchers.insert(watched, watcher);
+            Control::Continue
+        }
+        AppEvent::CachedCopyChanged(local, remote) => {
+            let saved = (local.clone(), remote.clone());
+            if !state.pending_uploads.contains(&saved) {
+                state.pending_uploads.push_back(saved);
+            }
+            if state.pending_uploads.len() == 1 && state.pending_conflicts.is_empty() {
+                show_next_upload(state, ctx);
+            }
+            Control::Changed
+        }
+        AppEvent::UploadBack(local, remote) => {
+            let session = Arc::clone(&state.session);
+            let (local, remote) = (local.clone(), remote.clone());
+            let current_path = state.current_path.clone();
+            let mut settings = ctx.cfg.settings.transfer;
+            // Asked for just now, so there is nothing left to ask.
+            settings.conflict = ConflictPolicy::Overwrite;
+            info!(local = ?local.display(), remote, "Uploading a saved copy back");
+            let mut record = Transfer::start(
+                TransferKind::Upload,
+                &ctx.cfg.cli.host,
+                local.display().to_string(),
+                remote.clone(),
+                false,
+            );
+
+            ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
+                let result = async {
+                    let sftp = {
+                        let mut session = session.lock().await;
+                        session.sftp().await?
+                    };
+                    // What is written over is kept, so that the upload can be undone.
+                    let old = match sftp.metadata(remote.as_str()).await {
+                        Ok(attributes)
+                            if attributes.size.unwrap_or_default() <= journal::MAX_KEPT_CONTENT =>
+                        {
+                            Some(sftp.read(remote.as_str()).await?)
+                        }
+                        _ => None,
+                    };
+                    let limit = Limit::new(TransferDirection::Upload, None);
+                    let written =
+                        transfer::upload_file(&sftp, &local, &remote, settings, &limit, &conflicts)
+                            .await?;
+                    let label = format!("Upload {} over {remote}", local.display());
+                    let record = match old {
+                        Some(old) if written.unwrap_or_default() <= journal::MAX_KEPT_CONTENT => {
+                            let content = tokio::fs::read(&local).await?;
+                            Record::new(
+                                label,
+                                vec![Change::Write {
+                                    path: remote.clone(),
+                                    content,
+                                    old,
+                                }],
+                            )
+                        }
+                        _ => Record::irreversible(
+                            label,
+                            "the content it replaced was too large to keep, or missing",
+                        ),
+                    };
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                    Ok(written)
+                }
+                .await;
+                if let Ok(written) = &result {
+                    record.finished(written.unwrap_or_default(), &Summary::default());
+                }
+                record.save(result.as_ref().err());
+                result?;
+                let message = format!("Uploaded {remote}");
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Continue
+        }
+        AppEvent::DownloadFile(name, path, filename, limit) => {
+            state.throbber.calc_next();
+            info!(name, path = ?path.display(), filename = ?filename.clone(), "File Details");
+            let session = Arc::clone(&state.session);
+            let path = path.clone();
+            let name = name.clone();
+            let settings = ctx.cfg.settings.transfer;
+            let limit = Limit::new(TransferDirection::Download, *limit);
+            let mut record = Transfer::start(
+                TransferKind::Download,
+                &ctx.cfg.cli.host,
+                name.clone(),
+                path.display().to_string(),
+                false,
+            );
+
+            ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
+                let result = async {
+                    let sftp = {
+                        let mut session = session.lock().await;
+                        session.sftp().await?
+                    };
+                    transfer::download_file(&sftp, &name, &path, settings, &limit, &conflicts).await
+                }
+                .await;
+                // Keeping an existing file counts as done, with nothing sent.
+                if let Ok(written) = &result {
+                    record.finished(written.unwrap_or_default(), &Summary::default());
                 }
-                info!(path = ?path.display(),"Writing file to" );
-                let mut file = tokio::fs::File::create(path).await?;
-                let _ = file.write(&buf).await?;
-                file.flush().await?;
-                file.sync_all().await?;
+                record.save(result.as_ref().err());
+                chan.send(Ok(Control::Event(AppEvent::RunQueued))).await?;
+                result?;
                 Ok(Control::Event(AppEvent::AsyncTick(300)))
             });
             Control::Continue
         }
-        AppEvent::DownloadFolder(file, path) => {
+        AppEvent::DownloadArchive(archive, local, limit) => {
+            ctx.queue_event(AppEvent::DownloadStart);
+            let session = Arc::clone(&state.session);
+            let archive = archive.clone();
+            let local = local.clone();
+            let settings = ctx.cfg.settings.transfer;
+            let limit = Limit::new(TransferDirection::Download, *limit);
+            info!(?archive, local = ?local.display(), "Downloading as an archive");
+            let mut record =
+                Transfer::archive(&ctx.cfg.cli.host, &archive, local.display().to_string());
+
+            ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
+                let status = chan.clone();
+                let progress: OnProgress = Arc::new(move |written| {
+                    let message = format!("Archived {}", human_readable_size(written));
+                    let _ = status.try_send(Ok(Control::Event(AppEvent::Status(0, message))));
+                });
+                let result = transfer::archive::download_archive(
+                    &session, &archive, &local, settings, limit, &conflicts, progress,
+                )
+                .await;
+                chan.send(Ok(Control::Event(AppEvent::DownloadEnd))).await?;
+                if let Ok(Some((path, summary))) = &result {
+                    let bytes = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+                    record.set_destination(path.display().to_string());
+                    record.finished(bytes, summary);
+                }
+                record.save(result.as_ref().err());
+                if let Some((path, summary)) = result? {
+                    let message = format!("Saved {}", path.display());
+                    chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                        .await?;
+                    if let Some(report) = summary.report("Archive") {
+                        chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                            .await?;
+                    }
+                }
+                Ok(Control::Event(AppEvent::AsyncTick(300)))
+            });
+            Control::Continue
+        }
+        AppEvent::Upload(local, remote, limit) => {
+            let session = Arc::clone(&state.session);
+            let local = local.clone();
+            let remote = remote.clone();
+            let current_path = state.current_path.clone();
+            let settings = ctx.cfg.settings.transfer;
+            let limit = Limit::new(TransferDirection::Upload, *limit);
+            info!(local = ?local.display(), remote, "Uploading");
+            let mut record = Transfer::start(
+                TransferKind::Upload,
+                &ctx.cfg.cli.host,
+                local.display().to_string(),
+                remote.clone(),
+                local.is_dir(),
+            );
+
+            ctx.spawn_async_ext(|chan| async move {
+                let conflicts = conflict_resolver(settings.conflict, &chan);
+                let result = async {
+                    let sftp = {
+                        let mut session = session.lock().await;
+                        session.sftp().await?
+                    };
+                    if tokio::fs::metadata(&local).await?.is_dir() {
+                        let summary = transfer::upload_dir(
+                            &sftp, &local, &remote, settings, &limit, &conflicts,
+                        )
+                        .await?;
+                        info!(files = summary.done, remote, "Uploaded directory");
+                        Ok(summary)
+                    } else {
+                        let written = transfer::upload_file(
+                            &sftp, &local, &remote, settings, &limit, &conflicts,
+                        )
+                        .await?;
+                        Ok(Summary {
+                            bytes: written.unwrap_or_default(),
+                            ..Summary::default()
+                        })
+                    }
+                }
+                .await;
+                if let Ok(summary) = &result {
+                    record.finished(summary.bytes, summary);
+                }
+                record.save(result.as_ref().err());
+                if let Some(report) = result?.report("Upload") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Continue
+        }
+        AppEvent::Sync(local, remote, options, dry_run) => {
+            let session = Arc::clone(&state.session);
+            let sftp = Arc::clone(&state.sftp);
+            let (local, remote, options, dry_run) =
+                (local.clone(), remote.clone(), options.clone(), *dry_run);
+            let settings = ctx.cfg.settings.transfer;
+            info!(local = ?local.display(), remote, ?options.direction, "Syncing");
+
+            ctx.spawn_async_ext(move |chan| async move {
+                let remote_shell = session.lock().await.remote();
+                let plan =
+                    sync::plan(Arc::clone(&sftp), &remote_shell, &local, &remote, &options).await?;
+                if dry_run {
+                    let mut message = plan.describe();
+                    if let Some(report) = plan.skipped.report("Listing") {
+                        message = format!("{message}\n\n{report}");
+                    }
+                    return Ok(Control::Event(AppEvent::Message(message)));
+                }
+                let status = chan.clone();
+                let progress = move |change: &sync::Change| {
+                    let message = format!("Syncing {change}");
+                    let _ = status.try_send(Ok(Control::Event(AppEvent::Status(0, message))));
+                };
+                let mut summary =
+                    sync::apply(&sftp, &remote_shell, &plan, &options, settings, progress).await?;
+                summary.skipped.extend(plan.skipped.skipped);
+                let message = format!("Synced {} changes", summary.done);
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Sync") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(remote)))
+            });
+            Control::Continue
+        }
+        AppEvent::DownloadFolder(file, path, filter, limit) => {
             ctx.queue_event(AppEvent::DownloadStart);
 
             info!("Downloading folder {}", file);
@@ -1062,25 +2651,47 @@ pub fn event(
             info!(path =?path.display(), dirname, "Path and dirname");
 
             let file = file.clone();
+            let filter = filter.clone();
+            let settings = ctx.cfg.settings.transfer;
+            // One bucket for the whole folder, however many files it holds.
+            let limit = Limit::new(TransferDirection::Download, *limit);
+            let max_depth = ctx.cfg.settings.download.max_depth;
+            let mut record = Transfer::start(
+                TransferKind::Download,
+                &ctx.cfg.cli.host,
+                file.clone(),
+                path.display().to_string(),
+                true,
+            );
             ctx.spawn_async_ext(|chan| async move {
+                let result: Result<Control<AppEvent>> = async {
                     let sftp = {
                         let mut session = session.lock().await;
                         session.sftp().await?
                     };
+                    let sftp = Arc::new(sftp);
+                    let root = file.clone();
                     let walker = WalkParallel {
-                        filter: Arc::new(|_| true),
+                        filter: Arc::new(move |entry: &FileEntry| {
+                            let relative = entry.name().strip_prefix(&root).unwrap_or(entry.name());
+                            filter.accepts(relative, entry.is_dir())
+                        }),
                         path: file.clone().into(),
-                        max_depth: Some(3),
+                        max_depth,
                         min_depth: None,
                         threads: 4,
-                        sftp: sftp.into(),
+                        sftp: Arc::clone(&sftp),
+                        follow_links: settings.symlinks == SymlinkPolicy::Follow,
                     };
                     let collected = Arc::new(Mutex::new(Vec::<FileEntry>::new()));
                     let collected_ref = Arc::clone(&collected);
+                    let summary = Arc::new(Mutex::new(Summary::default()));
+                    let summary_ref = Arc::clone(&summary);
                     walker
                         .run(|| {
                             // This closure is called once per worker thread.
                             let collected = collected_ref.clone();
+                            let summary = summary_ref.clone();
                             Box::new(move |entry_res: Result<FileEntry>| -> WalkState {
                                 match entry_res {
                                     Ok(entry) => {
@@ -1091,6 +2702,7 @@ pub fn event(
                                     }
                                     Err(err) => {
                                         error!("Error visiting entry: {:?}", err);
+                                        summary.lock().unwrap().skip("", err);
                                     }
                                 }
                                 WalkState::Continue
@@ -1101,33 +2713,81 @@ pub fn event(
                         let lock = collected.lock().unwrap();
                         lock.clone()
                     }; // lock dropped here
+                    let relative = |entry: &FileEntry| {
+                        entry.name().strip_prefix(&file).unwrap_or(entry.name()).trim_start_matches('/').to_string()
+                    };
+                    let mut summary = std::mem::take(&mut *summary.lock().unwrap());
+                    let (mut dirs, rest): (Vec<_>, Vec<_>) =
+                        collected_snapshot.into_iter().partition(|entry| entry.is_dir());
+                    let (links, rest): (Vec<_>, Vec<_>) = rest.into_iter().partition(|entry| entry.is_symlink());
+                    let mut files = Vec::new();
+                    for entry in rest {
+                        match transfer::special_kind(&entry.attributes) {
+                            Some(kind) => summary.skip(entry.name().clone(), kind),
+                            None => files.push(entry),
+                        }
+                    }
+
+                    // Create every directory up front, so that excluded files
+                    // do not leave their parents missing from the copy.
+                    for dir in &dirs {
+                        std::fs::create_dir_all(path.join(relative(dir)))?;
+                    }
+
+                    // Links are only left by now if they are to be kept as
+                    // links, or if following them led nowhere.
+                    for link in &links {
+                        let name = link.name().clone();
+                        match settings.symlinks {
+                            SymlinkPolicy::Preserve => {
+                                let created = async {
+                                    let target = sftp.read_link(name.as_str()).await?;
+                                    transfer::create_local_symlink(&path.join(relative(link)), &target)
+                                }
+                                .await;
+                                match created {
+                                    Ok(()) => summary.done += 1,
+                                    Err(err) => summary.skip(name, err),
+                                }
+                            }
+                            SymlinkPolicy::Follow => summary.skip(name, "dangling symbolic link"),
+                            SymlinkPolicy::Skip => summary.skip(name, "symbolic link"),
+                        }
+                    }
 
                     // 2️⃣ Process outside of the lock
-                    let tx = start_sftp_worker(session.clone());
-                    let total = collected_snapshot.len() as f64;
+                    let conflicts = Arc::new(conflict_resolver(settings.conflict, &chan));
+                    let tx = start_sftp_worker(session.clone(), settings, limit, conflicts);
+                    let total = files.len() as f64;
                     chan.send(Ok(Control::Event(AppEvent::SetTotalFilesToDownload(total as usize)))).await?;
                     let mut progress = 0.0;
-                    let mut windows = collected_snapshot.windows(5);
+                    let mut windows = files.windows(5);
                     let last_window = if let Some(window) = windows.clone().last() {
                         window.to_vec()
                     } else {
-                        collected_snapshot.to_vec()
+                        files.to_vec()
                     };
 
 
-                    for entry in collected_snapshot.clone() {
+                    for entry in files.clone() {
                         let file = file.clone();
-                        let filename = entry.name().strip_prefix(&file).unwrap_or(entry.name()).replacen("/", "", 1).to_string();
+                        let filename = relative(&entry);
                         let target_path = path.join(&filename);
                         info!(file, filename, target_path = ?target_path.display().to_string(), "Downloading");
 
                         let (reply_tx, reply_rx) = oneshot::channel();
                         tx.send(SftpCmd::ReadFile {
-                            remote_path: file.clone().join(&filename),
+                            remote_path: entry.name().clone(),
                             local_path: target_path,
                             reply: reply_tx,
                         })?;
-                        let _ = reply_rx.await?;
+                        match reply_rx.await? {
+                            Ok(()) => {
+                                summary.done += 1;
+                                summary.bytes += entry.attributes.size.unwrap_or_default();
+                            }
+                            Err(err) => summary.skip(entry.name().clone(), err),
+                        }
                         progress += 1.0;
                         if let Some(window) = windows.next() {
                             chan.send(Ok(Control::Event(AppEvent::UpdateNextFiveFiles(window.to_vec())))).await?;
@@ -1141,28 +2801,249 @@ pub fn event(
 
                         //sleep(Duration::from_millis(50)).await;
                     }
+
+                    // Deepest first, so that finishing a directory does not
+                    // bump the times just copied onto its parent.
+                    if settings.preserve {
+                        dirs.sort_by(|a, b| b.name().cmp(a.name()));
+                        for dir in &dirs {
+                            let local = path.join(relative(dir));
+                            if let Err(err) = transfer::apply_local_attributes(&local, &dir.attributes, settings) {
+                                warn!(local = ?local.display(), %err, "Could not preserve directory attributes");
+                            }
+                        }
+                    }
+                    record.finished(summary.bytes, &summary);
                     chan.send(Ok(Control::Event(AppEvent::DownloadEnd))).await?;
+                    if let Some(report) = summary.report("Download") {
+                        chan.send(Ok(Control::Event(AppEvent::Message(report)))).await?;
+                    }
                     Ok(Control::Event(AppEvent::AsyncTick(300)))
-                });
+                }
+                .await;
+                record.save(result.as_ref().err());
+                chan.send(Ok(Control::Event(AppEvent::RunQueued))).await?;
+                result
+            });
             Control::Continue
         }
-        AppEvent::DeleteEntry(file) => {
+        AppEvent::Chmod(targets, files, dirs) => {
             let session = Arc::clone(&state.session);
-            let file = file.clone();
-            let curr_path = state.current_path.clone();
-            let fname = curr_path.join(file.name());
-            ctx.spawn_async_ext(|chan| async move {
+            let (targets, files, dirs) = (targets.clone(), *files, *dirs);
+            let current_path = state.current_path.clone();
+            ctx.spawn_async_ext(move |chan| async move {
                 let mut session = session.lock().await;
                 let sftp = session.sftp().await?;
-                info!(fname, "Deleting");
-                match file.type_() {
-                    FileType::File => {
-                        sftp.remove_file(fname).await?;
+                let (summary, changes) = chmod::chmod(&sftp, &targets, files, dirs).await?;
+                let message = format!("Changed the mode of {} entries", summary.done);
+                let label = format!("Chmod {} entries", summary.done);
+                let record = match changes {
+                    Some(changes) if changes.is_empty() => None,
+                    Some(changes) => Some(Record::new(label, changes)),
+                    None => Some(Record::irreversible(
+                        label,
+                        "the server did not say what mode some entries had",
+                    )),
+                };
+                if let Some(record) = record {
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                }
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Chmod") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Changed
+        }
+        AppEvent::Chown(paths, uid, gid, recursive) => {
+            let session = Arc::clone(&state.session);
+            let (paths, uid, gid, recursive) = (paths.clone(), *uid, *gid, *recursive);
+            let current_path = state.current_path.clone();
+            ctx.spawn_async_ext(move |chan| async move {
+                let mut session = session.lock().await;
+                let sftp = session.sftp().await?;
+                let (summary, changes) = owners::chown(&sftp, &paths, uid, gid, recursive).await?;
+                let message = format!("Changed the owner of {} entries", summary.done);
+                if !changes.is_empty() {
+                    let record = Record::new(format!("Chown {} entries", changes.len()), changes);
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                }
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Chown") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Changed
+        }
+        AppEvent::Owners(owners) => {
+            state.owners = owners.clone();
+            for entry in state
+                .current_file_entries
+                .iter_mut()
+                .chain(state.filtered_file_entries.iter_mut())
+            {
+                state.owners.name(entry);
+            }
+            Control::Changed
+        }
+        AppEvent::TrashEntries(files) => {
+            let label = format!("Trashing {}", describe_entries(files));
+            let sftp = Arc::clone(&state.sftp);
+            let current_path = state.current_path.clone();
+            let paths = files
+                .iter()
+                .map(|file| current_path.clone().join(file.name()))
+                .collect::<Vec<_>>();
+            ctx.spawn_async_ext(|chan| async move {
+                let summary = Trash::home(&sftp).await?.put(&sftp, &paths).await?;
+                if summary.done > 0 {
+                    let record = Record::irreversible(
+                        label,
+                        "restore the entries from the trash browser, Shift+T",
+                    );
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                }
+                let message = format!("Moved {} entries to the trash", summary.done);
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Trash") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
+                }
+                Ok(Control::Event(AppEvent::ChangeDir(current_path)))
+            });
+            Control::Changed
+        }
+        AppEvent::Found(id, found) => match &mut state.find {
+            Some(view) if view.id == *id => {
+                let found = found.lock().map(|mut found| std::mem::take(&mut *found));
+                view.add(found.unwrap_or_default());
+                Control::Changed
+            }
+            _ => Control::Continue,
+        },
+        AppEvent::FindDone(id, report) => match &mut state.find {
+            Some(view) if view.id == *id => {
+                view.cancel = None;
+                view.report.clone_from(report);
+                match report {
+                    Some(report) => Control::Event(AppEvent::Message(report.clone())),
+                    None => Control::Changed,
+                }
+            }
+            _ => Control::Continue,
+        },
+        AppEvent::ShowTrash(trashed) => {
+            let selected = state
+                .trash
+                .as_ref()
+                .and_then(|view| view.table_state.selected());
+            let mut view = TrashView::new(trashed.clone());
+            if let Some(selected) = selected.filter(|_| !trashed.is_empty()) {
+                view.table_state
+                    .select(Some(selected.min(trashed.len() - 1)));
+            }
+            state.trash = Some(view);
+            Control::Changed
+        }
+        AppEvent::RestoreTrashed(entry) | AppEvent::PurgeTrashed(entry) => {
+            let sftp = Arc::clone(&state.sftp);
+            let entry = entry.clone();
+            let restore = matches!(event, AppEvent::RestoreTrashed(_));
+            let current_path = state.current_path.clone();
+            ctx.spawn_async_ext(move |chan| async move {
+                let trash = Trash::home(&sftp).await?;
+                let message = if restore {
+                    trash.restore(&sftp, &entry).await?;
+                    format!("Restored {}", entry.original)
+                } else {
+                    let summary = trash.purge(&sftp, &entry).await?;
+                    if summary.done > 0 {
+                        let record = Record::irreversible(
+                            format!("Deleting {} from the trash", entry.original),
+                            "deleted entries are gone for good",
+                        );
+                        chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                            .await?;
                     }
-                    FileType::Dir => {
-                        remove_dir_recursive(&sftp, &fname).await?;
+                    if let Some(report) = summary.report("Purge") {
+                        chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                            .await?;
                     }
-                    _ => {}
+                    format!("Deleted {} for good", entry.original)
+                };
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                chan.send(Ok(Control::Event(AppEvent::ChangeDir(current_path))))
+                    .await?;
+                let trashed = trash.list(&sftp).await?;
+                Ok(Control::Event(AppEvent::ShowTrash(trashed)))
+            });
+            Control::Changed
+        }
+        AppEvent::DeletePreview(names, question) => {
+            let targets = state.targets();
+            if matches!(
+                state.input_mode,
+                InputMode::ConfirmDelete | InputMode::ConfirmTrash
+            ) && targets.iter().map(FileEntry::name).eq(names.iter())
+            {
+                state.input_state.set_value(question.clone());
+            }
+            Control::Changed
+        }
+        AppEvent::DeleteEntries(files) => {
+            let label = format!("Deleting {}", describe_entries(files));
+            let sftp = Arc::clone(&state.sftp);
+            let files = files.clone();
+            let curr_path = state.current_path.clone();
+            let symlinks = ctx.cfg.settings.transfer.symlinks;
+            let cancel = Arc::new(AtomicBool::new(false));
+            state.delete_cancel = Some(Arc::clone(&cancel));
+            ctx.queue_event(AppEvent::DeleteStart);
+            ctx.spawn_async_ext(|chan| async move {
+                let (entries, mut summary) =
+                    delete::plan(&sftp, &curr_path, &files, symlinks).await;
+                info!(entries = entries.len(), "Deleting");
+                chan.send(Ok(Control::Event(AppEvent::SetTotalFilesToDownload(
+                    entries.len(),
+                ))))
+                .await?;
+                let total = entries.len() as f64;
+                // A link chosen for deletion, even to a directory, is
+                // removed as itself; the link policy only covers the ones
+                // found inside directories.
+                let deleted = delete::delete(&sftp, entries, &cancel, |removed| {
+                    let chan = chan.clone();
+                    async move {
+                        let progress = AppEvent::Gauge(removed as f64 / total);
+                        let _ = chan.send(Ok(Control::Event(progress))).await;
+                    }
+                })
+                .await;
+                summary.done += deleted.done;
+                summary.skipped.extend(deleted.skipped);
+                if summary.done > 0 {
+                    let record = Record::irreversible(label, "deleted entries are gone for good");
+                    chan.send(Ok(Control::Event(AppEvent::Journal(record))))
+                        .await?;
+                }
+                chan.send(Ok(Control::Event(AppEvent::DownloadEnd))).await?;
+                let message = format!("Deleted {} entries", summary.done);
+                chan.send(Ok(Control::Event(AppEvent::Status(0, message))))
+                    .await?;
+                if let Some(report) = summary.report("Delete") {
+                    chan.send(Ok(Control::Event(AppEvent::Message(report))))
+                        .await?;
                 }
                 chan.send(Ok(Control::Event(AppEvent::ChangeDir(curr_path.clone()))))
                     .await?;
@@ -1180,12 +3061,15 @@ pub fn event(
             };
             info!("changing dir to {}", path);
             let sftp = Arc::clone(&state.sftp);
-            state.input_state.clear();
-            state.input_mode = InputMode::default();
+            if state.pending_conflicts.is_empty() && state.pending_uploads.is_empty() {
+                state.input_state.clear();
+                state.input_mode = InputMode::default();
+            }
 
             ctx.spawn_async_ext(|chan| async move {
-                let files = sftp.read_dir(path).await?;
-                let files = files.into_iter().map(FileEntry::from).collect::<Vec<_>>();
+                let files = sftp.read_dir(path.clone()).await?;
+                let mut files = files.into_iter().map(FileEntry::from).collect::<Vec<_>>();
+                FileEntry::resolve_links(&sftp, &path, &mut files).await;
                 chan.send(Ok(Control::Event(AppEvent::UpdateFiles(files))))
                     .await?;
 
@@ -1197,13 +3081,37 @@ pub fn event(
             state.total_files_to_download = *total;
             Control::Changed
         }
+        AppEvent::RunQueued => match state.queued.pop_front() {
+            Some(next) => Control::Event(next),
+            None => Control::Continue,
+        },
         AppEvent::UpdateFiles(files) => {
             state.current_file_entries = files.to_vec();
-            state.input_state.clear();
-            state.input_mode = InputMode::default();
+            state.sort.sort(&mut state.current_file_entries);
+            for entry in &mut state.current_file_entries {
+                state.owners.name(entry);
+            }
+            if let Some(name) = state.select_after.take()
+                && let Some(index) = state
+                    .get_file_entries()
+                    .iter()
+                    .position(|entry| *entry.name() == name)
+            {
+                state.table_state.select(Some(index));
+            }
+            state
+                .marked
+                .retain(|name| files.iter().any(|entry| entry.name() == name));
+            if state.pending_conflicts.is_empty() && state.pending_uploads.is_empty() {
+                state.input_state.clear();
+                state.input_mode = InputMode::default();
+            }
             Control::Changed
         }
         AppEvent::UpdateCurrentPath(path) => {
+            if *path != state.current_path {
+                state.marked.clear();
+            }
             state.current_path = path.clone();
             Control::Continue
         }
@@ -1223,7 +3131,12 @@ enum SftpCmd {
     },
 }
 
-fn start_sftp_worker(session: Arc<AsyncMutex<Session>>) -> mpsc::UnboundedSender<SftpCmd> {
+fn start_sftp_worker(
+    session: Arc<AsyncMutex<Session>>,
+    settings: TransferSettings,
+    limit: Limit,
+    conflicts: Arc<ConflictResolver>,
+) -> mpsc::UnboundedSender<SftpCmd> {
     let (tx, mut rx) = mpsc::unbounded_channel();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
@@ -1240,20 +3153,15 @@ fn start_sftp_worker(session: Arc<AsyncMutex<Session>>) -> mpsc::UnboundedSender
                             info!("Opening remote file {:?}", remote_path);
                             let sftp = session.sftp().await?;
                             info!("Got SFTP channel");
-                            let mut remote_file = sftp.open(&remote_path).await?;
-                            info!("Opened remote file");
-                            let mut buf = Vec::new();
-                            let read = remote_file.read_to_end(&mut buf).await;
-
-                            if read.is_err() {
-                                error!("Read result: {:?}", read);
-                            } else {
-                                info!("Read result: {:?}", read);
-                            }
-                            let mut file = tokio::fs::File::create(local_path).await?;
-                            file.write_all(&buf).await?;
-                            file.flush().await?;
-                            file.sync_all().await?;
+                            transfer::download_file(
+                                &sftp,
+                                &remote_path,
+                                &local_path,
+                                settings,
+                                &limit,
+                                &conflicts,
+                            )
+                            .await?;
                             eyre::Ok(())
                         }
                         .await;
@@ -1268,6 +3176,298 @@ fn start_sftp_worker(session: Arc<AsyncMutex<Session>>) -> mpsc::UnboundedSender
     tx
 }
 
+/// Puts the oldest unanswered conflict in the input box, or gives the box
+/// back to filtering once none are left.
+fn show_next_conflict(state: &mut MainUI, ctx: &mut Global) {
+    let Some(prompt) = state.pending_conflicts.front() else {
+        show_next_upload(state, ctx);
+        return;
+    };
+    let describe = |stat: &Stat| {
+        format!(
+            "{}, modified {}",
+            human_readable_size(stat.size.unwrap_or_default()),
+            format_timestamp(stat.mtime).unwrap_or("N/A".to_string())
+        )
+    };
+    state.input_state.set_value(format!(
+        "new: {}; existing: {}",
+        describe(&prompt.conflict.source),
+        describe(&prompt.conflict.existing)
+    ));
+    state.input_mode = InputMode::ResolveConflict;
+    ctx.focus().focus(&state.input_state);
+}
+
+/// Suspends the interface to edit `text` in `$EDITOR`, and returns it as
+/// saved.
+fn in_editor(state: &mut MainUI, ctx: &mut Global, text: String) -> Result<String, Error> {
+    suspended(state, ctx, || edit::edit(text))
+}
+
+/// Suspends the interface to edit the local file at `path` in `$VISUAL` or
+/// `$EDITOR`.
+fn file_in_editor(state: &mut MainUI, ctx: &mut Global, path: &Path) -> Result<(), Error> {
+    suspended(state, ctx, || edit::edit_file(path))
+}
+
+/// Hands the terminal over to `run`, and takes it back once it returns.
+fn suspended<T>(
+    state: &mut MainUI,
+    ctx: &mut Global,
+    run: impl FnOnce() -> std::io::Result<T>,
+) -> Result<T, Error> {
+    state.in_editor = true;
+    stdout().execute(LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+    let result = run();
+    stdout().execute(EnterAlternateScreen)?;
+    enable_raw_mode()?;
+    ctx.terminal().borrow_mut().clear()?;
+    state.in_editor = false;
+    Ok(result?)
+}
+
+/// Why an edit conflict cannot be merged or compared here.
+fn too_large_to_compare(conflict: &EditConflict) -> String {
+    format!(
+        "{} is too large to merge or compare here: overwrite, or abort to keep your version",
+        conflict.edit.path
+    )
+}
+
+/// Sorts the list by `order`, keeping the cursor on the entry it was on, and
+/// keeps the order for the next time this host is browsed.
+fn resort(state: &mut MainUI, ctx: &mut Global, order: SortOrder) -> Control<AppEvent> {
+    let selected = state.table_state.selected().and_then(|index| {
+        state
+            .get_file_entries()
+            .get(index)
+            .map(|entry| entry.name.clone())
+    });
+    state.sort = order;
+    order.sort(&mut state.current_file_entries);
+    order.sort(&mut state.filtered_file_entries);
+    if let Some(name) = selected
+        && let Some(index) = state
+            .get_file_entries()
+            .iter()
+            .position(|entry| entry.name == name)
+    {
+        state.table_state.select(Some(index));
+    }
+    let host = ctx.cfg.cli.host.clone();
+    if let Err(error) = ctx.cfg.settings.save_sort(&host, order) {
+        warn!("Could not keep the sort order: {error}");
+        let message = format!("Sorted, but the order could not be kept for next time: {error}");
+        return Control::Event(AppEvent::Status(0, message));
+    }
+    Control::Changed
+}
+
+/// Puts the input box back to filtering once an edit conflict is settled.
+fn end_edit_conflict(state: &mut MainUI, ctx: &mut Global) {
+    state.edit_conflict = None;
+    state.input_state.clear();
+    state.input_mode = InputMode::default();
+    ctx.focus().focus(&state.table_state);
+}
+
+/// Asks about the next saved copy waiting to go back, or puts the input box
+/// back to filtering when none is left.
+fn show_next_upload(state: &mut MainUI, ctx: &mut Global) {
+    state.input_state.clear();
+    if state.pending_uploads.is_empty() {
+        state.input_mode = InputMode::default();
+        ctx.focus().focus(&state.table_state);
+    } else {
+        state.input_mode = InputMode::UploadBack;
+        ctx.focus().focus(&state.input_state);
+    }
+}
+
+/// A resolver for the conflicts of one operation, which prompts in the input
+/// box when the policy says to ask.
+fn conflict_resolver(
+    policy: ConflictPolicy,
+    chan: &mpsc::Sender<Result<Control<AppEvent>, Error>>,
+) -> ConflictResolver {
+    let chan = chan.clone();
+    let ask: Ask = Arc::new(move |conflict| {
+        let chan = chan.clone();
+        Box::pin(async move {
+            let (reply, mut answer) = mpsc::channel(1);
+            chan.send(Ok(Control::Event(AppEvent::ResolveConflict(
+                ConflictPrompt { conflict, reply },
+            ))))
+            .await?;
+            answer
+                .recv()
+                .await
+                .ok_or_else(|| eyre::eyre!("cancelled at the conflict prompt"))
+        })
+    });
+    ConflictResolver::new(policy, Some(ask))
+}
+
+fn render_chmod(
+    area: Rect,
+    buf: &mut Buffer,
+    dialog: &ChmodDialog,
+    subject: &str,
+    ctx: &mut Global,
+) {
+    render_popup(area, buf, dialog.lines(), format!("chmod {subject}"), ctx);
+}
+
+fn render_rename(area: Rect, buf: &mut Buffer, plan: &RenamePlan, ctx: &mut Global) {
+    let mut lines = plan
+        .renames
+        .iter()
+        .map(|(from, to)| Line::from(format!("{from} → {to}")))
+        .collect::<Vec<_>>();
+    lines.extend(plan.collisions.iter().map(|(from, reason)| {
+        Line::from(format!("{from}: left as it is, {reason}"))
+            .style(Style::default().fg(Color::Red))
+    }));
+    let title = format!("Rename {} entries", plan.renames.len());
+    render_popup(area, buf, lines, title, ctx);
+}
+
+/// Draws `lines` in a bordered box over the middle of `area`.
+fn render_popup(
+    area: Rect,
+    buf: &mut Buffer,
+    lines: Vec<Line<'static>>,
+    title: String,
+    ctx: &mut Global,
+) {
+    let popup = popup_area(area, &lines);
+    Clear.render(popup, buf);
+    Paragraph::new(lines)
+        .styles(ctx.theme.paragraph_style())
+        .block(
+            Block::bordered()
+                .border_type(BorderType::Rounded)
+                .border_style(ctx.theme.container_border().fg(Color::Yellow))
+                .title_top(title)
+                .padding(Padding::horizontal(1)),
+        )
+        .render(popup, buf, &mut ParagraphState::default());
+}
+
+/// A box centred in `area` that fits `lines` with a border and padding, or
+/// as much of them as `area` has room for.
+fn popup_area(area: Rect, lines: &[Line]) -> Rect {
+    let width = lines.iter().map(Line::width).max().unwrap_or_default() as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    Rect {
+        x: area.x + area.width.saturating_sub(width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width: width.min(area.width),
+        height: height.min(area.height),
+    }
+}
+
+/// A full-pane table in a rounded border, titled on top with the keys below.
+fn bordered_table<'a>(
+    title: impl Into<Line<'a>>,
+    hints: impl Into<Line<'a>>,
+    ctx: &Global,
+) -> Table<'a, RowSelection> {
+    Table::default()
+        .block(
+            Block::bordered()
+                .padding(Padding::horizontal(1))
+                .border_type(block::BorderType::Rounded)
+                .title_top(title)
+                .title_bottom(hints)
+                .border_style(ctx.theme.container_border()),
+        )
+        .column_spacing(1)
+        .vscroll(Scroll::new())
+        .flex(Flex::Start)
+        .styles(ctx.theme.table_style())
+}
+
+fn render_trash(area: Rect, buf: &mut Buffer, view: &mut TrashView, ctx: &mut Global) {
+    bordered_table(
+        "[1] Trash",
+        "Enter/r Restore  x Delete for good  Esc Close",
+        ctx,
+    )
+    .data(TrashSlice(&view.entries))
+    .widths([
+        Constraint::Length(19),
+        Constraint::Fill(1),
+        Constraint::Length(10),
+    ])
+    .header(rat_ftable::textdata::Row::new([
+        Cell::from("Deleted"),
+        Cell::from("Original path"),
+        Cell::from("Size"),
+    ]))
+    .render(area, buf, &mut view.table_state);
+}
+
+fn render_find(area: Rect, buf: &mut Buffer, view: &mut FindView, ctx: &mut Global) {
+    let state = match (&view.cancel, &view.report) {
+        (Some(_), _) => ", searching…",
+        (None, Some(_)) => ", some directories could not be read",
+        (None, None) => "",
+    };
+    let title = format!(
+        "[1] Find {} in {}/ ({} found{state})",
+        view.query,
+        view.root,
+        view.results.len()
+    );
+    let hints = if view.cancel.is_some() {
+        "Enter Go to  Esc Stop  q Close"
+    } else {
+        "Enter Go to  Esc Close"
+    };
+    bordered_table(title, hints, ctx)
+        .data(FindSlice(&view.results, &view.root))
+        .widths([
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(19),
+        ])
+        .header(rat_ftable::textdata::Row::new([
+            Cell::from("Path"),
+            Cell::from("Size"),
+            Cell::from("Modified At"),
+        ]))
+        .render(area, buf, &mut view.table_state);
+}
+
+fn render_history(area: Rect, buf: &mut Buffer, view: &mut HistoryView, ctx: &mut Global) {
+    bordered_table(
+        "[1] History",
+        "Enter/r Re-run  b Send back  o Reveal  Esc Close",
+        ctx,
+    )
+    .data(HistorySlice(&view.records))
+    .widths([
+        Constraint::Length(16),
+        Constraint::Length(5),
+        Constraint::Fill(1),
+        Constraint::Length(10),
+        Constraint::Length(7),
+        Constraint::Length(24),
+    ])
+    .header(rat_ftable::textdata::Row::new([
+        Cell::from("When"),
+        Cell::from("Kind"),
+        Cell::from("Transfer"),
+        Cell::from("Size"),
+        Cell::from("Took"),
+        Cell::from("Result"),
+    ]))
+    .render(area, buf, &mut view.table_state);
+}
+
 #[inline]
 fn keybind<'a>(key: &'a str, description: &str) -> Vec<Span<'a>> {
     vec![
@@ -1279,26 +3479,3 @@ fn keybind<'a>(key: &'a str, description: &str) -> Vec<Span<'a>> {
         ),
     ]
 }
-
-async fn remove_dir_recursive(sftp: &SftpSession, root: &str) -> Result<()> {
-    let mut stack = vec![root.to_string()];
-
-    while let Some(path) = stack.pop() {
-        let entries = sftp.read_dir(&path).await?;
-        for entry in entries {
-            let attrs = entry.metadata();
-            let name = entry.file_name();
-            let child_path = format!("{}/{}", path, name);
-            if attrs.is_dir() {
-                // Push directory for later deletion
-                stack.push(child_path.clone());
-            } else {
-                sftp.remove_file(&child_path).await?;
-            }
-        }
-        // Once children are deleted, remove dir
-        sftp.remove_dir(&path).await?;
-    }
-
-    Ok(())
-}
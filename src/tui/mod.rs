@@ -1,8 +1,19 @@
 use self::main_ui::MainUI;
 use crate::cli::ResolvedConnectArgs;
-use crate::config::Theme;
+use crate::config::{Settings, Theme};
 use crate::files::FileEntry;
 use crate::ssh::Session;
+use crate::transfer::archive::Archive;
+use crate::transfer::chmod::{ModeSpec, Target};
+use crate::transfer::conflict::ConflictPrompt;
+use crate::transfer::filter::PathFilter;
+use crate::transfer::journal::Record;
+use crate::transfer::owners::Owners;
+use crate::transfer::ratelimit::Rate;
+use crate::transfer::rename::Plan as RenamePlan;
+use crate::transfer::save::{Edit, EditConflict};
+use crate::transfer::sync::SyncOptions;
+use crate::transfer::trash::Trashed;
 use async_lock::Mutex;
 use color_eyre::Report as Error;
 use color_eyre::eyre::Result;
@@ -21,6 +32,7 @@ use ratatui::crossterm::cursor::Show;
 use ratatui::crossterm::terminal::{LeaveAlternateScreen, disable_raw_mode};
 use ratatui::crossterm::{self, ExecutableCommand};
 use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::Modifier;
 use ratatui::widgets::StatefulWidget;
 use russh_sftp::client::SftpSession;
 use std::io::stdout;
@@ -28,7 +40,11 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tracing::error;
+pub mod chmod;
+pub mod find;
+pub mod history;
 pub mod main_ui;
+pub mod trash;
 
 pub fn tui(
     current_path: String,
@@ -36,13 +52,13 @@ pub fn tui(
     rt: tokio::runtime::Runtime,
     sftp: Arc<SftpSession>,
     session: Arc<Mutex<Session>>,
-    theme: &Theme,
+    settings: Settings,
 ) -> Result<(), Error> {
-    let config = Config::new(cli);
-    let theme = match theme {
+    let theme = match settings.get_theme() {
         Theme::Custom(c) => c.clone().into(),
         Theme::Default(d) => create_theme(&d.to_string()).expect("theme"),
     };
+    let config = Config::new(cli, settings);
     let mut global = Global::new(config, theme);
     let mut state = Scenery::new(current_path, sftp, session);
 
@@ -96,11 +112,12 @@ impl Global {
 #[derive(Debug, Default)]
 pub struct Config {
     pub(crate) cli: ResolvedConnectArgs,
+    pub(crate) settings: Settings,
 }
 
 impl Config {
-    pub fn new(cli: ResolvedConnectArgs) -> Self {
-        Self { cli }
+    pub fn new(cli: ResolvedConnectArgs, settings: Settings) -> Self {
+        Self { cli, settings }
     }
 }
 
@@ -112,19 +129,75 @@ pub enum AppEvent {
     Event(crossterm::event::Event),
     ChangeDir(String),
     DownloadStart,
+    /// Show the progress pane for a deletion.
+    DeleteStart,
     DownloadEnd,
     UpdateCurrentPath(String),
     Throb,
     Gauge(f64),
     SetTotalFilesToDownload(usize),
     UpdateContent(Option<String>),
+    /// A file was downloaded for editing, to open in the editor.
+    EditDownloaded(Edit),
+    /// Save an edit, unless the file changed since it was read.
+    SaveEdit(Edit),
+    /// An edit that was not saved, as the file changed on the server.
+    EditConflict(EditConflict),
+    /// An edit was saved, with what the file holds now if it is short text.
+    EditSaved(String, Option<String>),
     UpdateFiles(Vec<FileEntry>),
     SpawnExternalEditor(String),
     SpawnSSHCommand,
-    DownloadFile(String, PathBuf, Option<String>),
-    DownloadFolder(String, PathBuf),
-    DeleteEntry(FileEntry),
+    DownloadFile(String, PathBuf, Option<String>, Option<Rate>),
+    DownloadFolder(String, PathBuf, PathFilter, Option<Rate>),
+    DownloadArchive(Archive, PathBuf, Option<Rate>),
+    Upload(PathBuf, String, Option<Rate>),
+    /// Sync a local directory with a remote one; `true` only shows the plan.
+    Sync(PathBuf, String, SyncOptions, bool),
+    DeleteEntries(Vec<FileEntry>),
+    /// The size of deleting the named entries, as the question to confirm it.
+    DeletePreview(Vec<String>, String),
+    /// Move entries into the remote trash.
+    TrashEntries(Vec<FileEntry>),
+    /// Show the trash browser with what is in the trash.
+    ShowTrash(Vec<Trashed>),
+    RestoreTrashed(Trashed),
+    PurgeTrashed(Trashed),
+    /// Entries found by the search with this id, since the last batch;
+    /// events come by reference, so the batch is taken out of the lock.
+    Found(usize, std::sync::Mutex<Vec<FileEntry>>),
+    /// The search with this id is over, with what went wrong along the way.
+    FindDone(usize, Option<String>),
+    /// Change the mode of entries, recursing with the directory mode if given.
+    Chmod(Vec<Target>, ModeSpec, Option<ModeSpec>),
+    /// Change the owner and group of remote paths, keeping whichever is
+    /// `None`, recursively if `true`.
+    Chown(Vec<String>, Option<u32>, Option<u32>, bool),
+    /// The remote host's user and group names, once read.
+    Owners(Owners),
     MoveEntry(String, String),
+    /// Open names of the current directory in the editor, to rename them.
+    EditNames(Vec<String>),
+    /// Carry out a confirmed bulk rename.
+    BulkRename(RenamePlan),
+    /// Note an operation that went through in the journal, for undoing.
+    Journal(Record),
+    /// Undo an operation taken off the journal if `true`, or redo it.
+    Revert(Record, bool),
+    /// An operation went back as asked, and returns to the journal.
+    Reverted(Record, bool),
+    /// Copy remote entries to a remote path, recursively if `true`.
+    CopyEntries(Vec<String>, String, bool),
+    /// Open a remote file in a local application, watching for saves.
+    OpenEntry(String),
+    /// The cached copy of an opened file was saved; holds the copy and the
+    /// remote path it came from.
+    CachedCopyChanged(PathBuf, String),
+    /// Upload a saved copy over the remote file it came from.
+    UploadBack(PathBuf, String),
+    /// Start the next transfer of a batch, if one is waiting.
+    RunQueued,
+    ResolveConflict(ConflictPrompt),
     Rendered,
     Message(String),
     Status(usize, String),
@@ -213,14 +286,31 @@ pub fn render(
     let len = remote_host_details.len();
     state.status.status(3, remote_host_details);
 
+    let mut widths = vec![
+        Constraint::Fill(1),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Length(len as u16),
+    ];
+    let mut styles = ctx.theme.statusline_style_ext();
+    // An elevated session can break what the login user could not, so it
+    // stands out for as long as it lasts.
+    if let Some(user) = &ctx.cfg.cli.sudo {
+        let elevated = format!("  SUDO {user}  ");
+        widths.push(Constraint::Length(elevated.len() as u16));
+        state.status.status(4, elevated);
+        let palette = ctx.theme.palette();
+        styles.styles.push(
+            palette
+                .normal_contrast(palette.white[0])
+                .bg(palette.red[2])
+                .add_modifier(Modifier::BOLD),
+        );
+    }
+
     StatusLine::new()
-        .layout([
-            Constraint::Fill(1),
-            Constraint::Length(8),
-            Constraint::Length(8),
-            Constraint::Length(len as u16),
-        ])
-        .styles_ext(ctx.theme.statusline_style_ext())
+        .layout(widths)
+        .styles_ext(styles)
         .render(layout[1], buf, &mut state.status);
 
     Ok(())
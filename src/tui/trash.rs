@@ -0,0 +1,66 @@
+//! The trash browser, shown in place of the file table.
+use rat_ftable::selection::RowSelection;
+use rat_ftable::{TableData, TableState};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::text::Span;
+use ratatui::widgets::Widget;
+
+use crate::files::human_readable_size;
+use crate::transfer::trash::Trashed;
+
+pub struct TrashView {
+    pub(crate) entries: Vec<Trashed>,
+    pub table_state: TableState<RowSelection>,
+    /// Set once purging the selected entry has been asked for, until it is
+    /// confirmed or anything else is pressed.
+    pub confirm_purge: bool,
+}
+
+impl TrashView {
+    pub(crate) fn new(entries: Vec<Trashed>) -> Self {
+        let mut table_state = TableState::default();
+        if !entries.is_empty() {
+            table_state.select(Some(0));
+        }
+        Self {
+            entries,
+            table_state,
+            confirm_purge: false,
+        }
+    }
+
+    pub(crate) fn selected(&self) -> Option<&Trashed> {
+        self.entries.get(self.table_state.selected()?)
+    }
+}
+
+pub struct TrashSlice<'a>(pub &'a [Trashed]);
+
+impl<'a> TableData<'a> for TrashSlice<'a> {
+    fn rows(&self) -> usize {
+        self.0.len()
+    }
+
+    fn render_cell(
+        &self,
+        ctx: &rat_ftable::TableContext,
+        column: usize,
+        row: usize,
+        area: Rect,
+        buf: &mut Buffer,
+    ) {
+        let entry = &self.0[row];
+        let span = match column {
+            0 => Span::from(entry.deleted.replacen('T', " ", 1)),
+            1 => {
+                let marker = if ctx.selected_row { "> " } else { "  " };
+                let slash = if entry.is_dir { "/" } else { "" };
+                Span::from(format!("{marker}{}{slash}", entry.original))
+            }
+            2 if !entry.is_dir => Span::from(human_readable_size(entry.size)),
+            _ => return,
+        };
+        span.render(area, buf);
+    }
+}